@@ -1,6 +1,6 @@
 use bitflags::bitflags;
 use libc::c_void;
-use std::mem;
+use std::{convert::TryFrom, mem};
 use vulkano::format::Format::{self, *};
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -113,15 +113,33 @@ pub enum GGPixelFormat {
 
 	PFMT_INVALID,
 }
-impl Into<Format> for GGPixelFormat {
-	fn into(self) -> Format {
-		match self {
-			Self::PFMT_RGBA8 => R8G8B8A8Unorm,
-			Self::PFMT_RGBA8_SRGB => R8G8B8A8Srgb,
-			Self::PFMT_RGBA32F => R32G32B32A32Sfloat,
-			Self::PFMT_RGBA16F => R16G16B16A16Sfloat,
-			_ => panic!("{:?} not supported", self),
-		}
+impl TryFrom<GGPixelFormat> for Format {
+	/// The format without a Vulkan equivalent.
+	type Error = GGPixelFormat;
+
+	fn try_from(format: GGPixelFormat) -> Result<Self, GGPixelFormat> {
+		use GGPixelFormat::*;
+		Ok(match format {
+			PFMT_RGBA8 => R8G8B8A8Unorm,
+			PFMT_RGBA8_SRGB => R8G8B8A8Srgb,
+			PFMT_RGBA32F => R32G32B32A32Sfloat,
+			PFMT_RGBA16F => R16G16B16A16Sfloat,
+			PFMT_BC1 => BC1_RGBAUnormBlock,
+			PFMT_BC1_SRGB => BC1_RGBASrgbBlock,
+			PFMT_BC2 => BC2UnormBlock,
+			PFMT_BC2_SRGB => BC2SrgbBlock,
+			PFMT_BC3 => BC3UnormBlock,
+			PFMT_BC3_SRGB => BC3SrgbBlock,
+			PFMT_BC4 => BC4UnormBlock,
+			PFMT_BC4_SIGNED => BC4SnormBlock,
+			PFMT_BC5 => BC5UnormBlock,
+			PFMT_BC5_SIGNED => BC5SnormBlock,
+			PFMT_BC6H => BC6HUfloatBlock,
+			PFMT_BC6H_SIGNED => BC6HSfloatBlock,
+			PFMT_BC7 => BC7UnormBlock,
+			PFMT_BC7_SRGB => BC7SrgbBlock,
+			_ => return Err(format),
+		})
 	}
 }
 
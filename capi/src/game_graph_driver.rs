@@ -1,8 +1,8 @@
 use crate::game_graph::*;
 use libc::c_void;
 use nice_engine::{
-	camera::Camera, mesh::Mesh, mesh_data::MeshData, mesh_group::MeshGroup, surface::Surface as NiceSurface,
-	texture::Texture,
+	camera::Camera, field_data::FieldData, mesh::Mesh, mesh_data::MeshData, mesh_group::MeshGroup,
+	surface::Surface as NiceSurface, texture::Texture,
 };
 #[cfg(unix)]
 use std::os::raw::c_ulong;
@@ -29,7 +29,7 @@ pub struct GGD_FontData {}
 
 #[allow(non_camel_case_types)]
 pub enum GGD_ImageData {
-	Uninitialized { usage: GGImageUsage, x: u32, y: u32, format: GGPixelFormat },
+	Uninitialized { usage: GGImageUsage, x: u32, y: u32, format: GGPixelFormat, cubemap: bool },
 	Initialized(Arc<dyn Texture + Send + Sync>),
 }
 impl GGD_ImageData {
@@ -45,7 +45,10 @@ impl GGD_ImageData {
 pub type GGD_MeshGroup = Arc<MeshGroup>;
 
 #[allow(non_camel_case_types)]
-pub type GGD_MeshData = Arc<MeshData>;
+pub enum GGD_MeshData {
+	Polygon(Arc<MeshData>),
+	Field(Arc<FieldData>),
+}
 
 #[allow(non_camel_case_types)]
 pub type GGD_MeshInstance = Mesh;
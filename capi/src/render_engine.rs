@@ -25,7 +25,7 @@ pub const RENDER_ENGINE: GGD_RenderEngine = GGD_RenderEngine {
 	Window_Draw,
 
 	MeshData_Alloc_Polygon,
-	MeshData_Alloc_Field: None,
+	MeshData_Alloc_Field: Some(MeshData_Alloc_Field),
 	MeshData_Free,
 
 	ImageData_Alloc,
@@ -5,15 +5,27 @@ use crate::{
 };
 use futures::task::SpawnExt;
 use half::f16;
-use log::trace;
+use log::{trace, warn};
 use nice_engine::{
 	resources::TextureResource,
 	texture::{ImmutableTexture, TargetTexture},
 	threads::{yield_once, FILE_THREAD},
 };
-use std::{ptr::null, slice, sync::Arc, time::Duration};
+use std::{
+	convert::TryFrom,
+	ffi::c_void,
+	mem,
+	ptr::{null, null_mut},
+	slice,
+	sync::Arc,
+	time::Duration,
+};
 use vulkano::{
-	format::Format::{self, *},
+	device::Queue,
+	format::{
+		AcceptsPixels,
+		Format::{self, *},
+	},
 	sync::{FlushError, GpuFuture},
 };
 
@@ -28,15 +40,54 @@ pub unsafe extern fn ImageData_Alloc(
 ) -> *mut GGD_ImageData {
 	trace!("ImageData_Alloc");
 
+	alloc(usage, x, y, format, false, pixelBuffer)
+}
+
+/// Like `ImageData_Alloc`, but the pixels are six square faces stacked vertically in the order +X, -X, +Y, -Y, +Z, -Z,
+/// so `y` must be `x * 6`. Compressed formats store all six faces of each mip level before the next level. Returns
+/// null for render targets, which can't be cubemaps.
+#[allow(non_snake_case)]
+#[no_mangle]
+pub unsafe extern fn NiceImageData_AllocCubemap(
+	usage: GGImageUsage,
+	x: u32,
+	y: u32,
+	format: GGPixelFormat,
+	pixelBuffer: *const GGD_BufferInfo,
+	_cacheBuffer: *mut GGD_BufferInfo,
+) -> *mut GGD_ImageData {
+	trace!("NiceImageData_AllocCubemap");
+
 	if usage.contains(GGImageUsage::IMG_USAGE_TARGET) {
-		let (tex, tex_future) =
-			TargetTexture::new::<Format>(ctx::get().queue().clone(), [x, y], format.into()).unwrap();
+		warn!("render targets can't be cubemaps");
+		return null_mut();
+	}
+	alloc(usage, x, y, format, true, pixelBuffer)
+}
+
+unsafe fn alloc(
+	usage: GGImageUsage,
+	x: u32,
+	y: u32,
+	format: GGPixelFormat,
+	cubemap: bool,
+	pixel_buffer: *const GGD_BufferInfo,
+) -> *mut GGD_ImageData {
+	if usage.contains(GGImageUsage::IMG_USAGE_TARGET) {
+		let format = match Format::try_from(format) {
+			Ok(format) => format,
+			Err(format) => {
+				warn!("{:?} images are not supported", format);
+				return null_mut();
+			},
+		};
+		let (tex, tex_future) = TargetTexture::new::<Format>(ctx::get().queue().clone(), [x, y], format).unwrap();
 		tex_future.then_signal_fence_and_flush().unwrap().wait(None).unwrap();
 		Box::into_raw(Box::new(GGD_ImageData::Initialized(Arc::new(tex))))
 	} else {
-		let ret = Box::into_raw(Box::new(GGD_ImageData::Uninitialized { usage, x, y, format }));
-		if pixelBuffer != null() {
-			ImageData_DrawPixelData(ret, pixelBuffer);
+		let ret = Box::into_raw(Box::new(GGD_ImageData::Uninitialized { usage, x, y, format, cubemap }));
+		if pixel_buffer != null() {
+			ImageData_DrawPixelData(ret, pixel_buffer);
 		}
 		ret
 	}
@@ -57,7 +108,7 @@ pub unsafe extern fn ImageData_DrawPixelData(this: *mut GGD_ImageData, buffer: *
 	let buffer = &*buffer;
 
 	match *this {
-		GGD_ImageData::Uninitialized { usage, x, y, format } => {
+		GGD_ImageData::Uninitialized { usage, x, y, format, cubemap } => {
 			if usage.contains(GGImageUsage::IMG_USAGE_TARGET) {
 				unimplemented!();
 			} else {
@@ -72,43 +123,17 @@ pub unsafe extern fn ImageData_DrawPixelData(this: *mut GGD_ImageData, buffer: *
 						}
 					}
 
-					let (tex, tex_future): (_, Box<dyn GpuFuture + Send>) = {
-						let pixels = (buffer.read)(buffer, 0, buffer.size);
-
-						let queue = ctx::get().queue().clone();
-						let dims = [x, y];
-						let len = x as usize * y as usize;
-
-						let format = format.into();
-
-						match format {
-							R8G8B8A8Unorm | R8G8B8A8Srgb => {
-								let buffer = slice::from_raw_parts(pixels as *const [u8; 4], len).iter().cloned();
-								let (tex, fut) = ImmutableTexture::from_iter_vk(queue, buffer, dims, format).unwrap();
-								(tex, Box::new(fut))
-							},
-							R32G32B32A32Sfloat => {
-								let buffer = slice::from_raw_parts(pixels as *const [f32; 4], len).iter().cloned();
-								let (tex, fut) = ImmutableTexture::from_iter_vk(queue, buffer, dims, format).unwrap();
-								(tex, Box::new(fut))
-							},
-							R16G16B16A16Sfloat => {
-								let buffer = slice::from_raw_parts(pixels as *const [f16; 4], len).iter().cloned();
-								let (tex, fut) = ImmutableTexture::from_iter_vk(queue, buffer, dims, format).unwrap();
-								(tex, Box::new(fut))
-							},
-							_ => panic!("{:?} not supported", format),
-						}
-					};
+					let pixels = (buffer.read)(buffer, 0, buffer.size);
+					if let Some((tex, tex_future)) = upload_pixels(pixels, buffer.size, [x, y], cubemap, format) {
+						let tex_future = tex_future.then_signal_fence_and_flush().unwrap();
 
-					let tex_future = tex_future.then_signal_fence_and_flush().unwrap();
+						while tex_future.wait(Some(Duration::new(0, 0))) == Err(FlushError::Timeout) {
+							yield_once().await;
+						}
 
-					while tex_future.wait(Some(Duration::new(0, 0))) == Err(FlushError::Timeout) {
-						yield_once().await;
+						res_clone.set_texture(Arc::new(tex));
 					}
 
-					res_clone.set_texture(Arc::new(tex));
-
 					if let Some(status) = buffer.status {
 						status(buffer, GGD_BUFFER_CLOSED as _);
 					}
@@ -123,6 +148,95 @@ pub unsafe extern fn ImageData_DrawPixelData(this: *mut GGD_ImageData, buffer: *
 	}
 }
 
+/// Logs a warning and returns `None` if the pixels can't be uploaded, leaving the image white.
+unsafe fn upload_pixels(
+	pixels: *const c_void,
+	size: u64,
+	dims: [u32; 2],
+	cubemap: bool,
+	format: GGPixelFormat,
+) -> Option<(ImmutableTexture, Box<dyn GpuFuture + Send>)> {
+	let format = match Format::try_from(format) {
+		Ok(format) => format,
+		Err(format) => {
+			warn!("{:?} images are not supported", format);
+			return None;
+		},
+	};
+	let [x, y] = dims;
+	if cubemap && y != x * 6 {
+		warn!("cubemap image is {}x{}, but its height must be 6 times its width", x, y);
+		return None;
+	}
+	let queue = ctx::get().queue().clone();
+
+	match format {
+		R8G8B8A8Unorm | R8G8B8A8Srgb => {
+			upload::<[u8; 4]>(queue, pixel_slice(pixels, size, dims)?, dims, cubemap, format)
+		},
+		R32G32B32A32Sfloat => upload::<[f32; 4]>(queue, pixel_slice(pixels, size, dims)?, dims, cubemap, format),
+		R16G16B16A16Sfloat => upload::<[f16; 4]>(queue, pixel_slice(pixels, size, dims)?, dims, cubemap, format),
+		BC1_RGBAUnormBlock | BC1_RGBASrgbBlock | BC2UnormBlock | BC2SrgbBlock | BC3UnormBlock | BC3SrgbBlock
+		| BC4UnormBlock | BC4SnormBlock | BC5UnormBlock | BC5SnormBlock | BC6HUfloatBlock | BC6HSfloatBlock
+		| BC7UnormBlock | BC7SrgbBlock => {
+			let block_size = match format {
+				BC1_RGBAUnormBlock | BC1_RGBASrgbBlock | BC4UnormBlock | BC4SnormBlock => 8,
+				_ => 16,
+			};
+			// the first level of every face, the rest of the mip chain is optional
+			let blocks = ((x as u64 + 3) / 4) * ((y as u64 + 3) / 4);
+			if size < blocks * block_size || size % block_size != 0 {
+				warn!("{} bytes is not a whole number of blocks covering a {}x{} {:?} image", size, x, y, format);
+				return None;
+			}
+
+			let buffer = slice::from_raw_parts(pixels as *const u8, size as usize);
+			let res = if cubemap {
+				ImmutableTexture::cubemap_from_compressed(queue, buffer, x, format)
+			} else {
+				ImmutableTexture::from_compressed(queue, buffer, dims, format)
+			};
+			res.map_err(|err| warn!("failed to create {:?} image: {}", format, err)).ok()
+		},
+		_ => {
+			warn!("{:?} images are not supported", format);
+			None
+		},
+	}
+}
+
+/// Returns `None` if `size` bytes don't hold `dims[0] * dims[1]` pixels.
+unsafe fn pixel_slice<'a, P>(pixels: *const c_void, size: u64, dims: [u32; 2]) -> Option<&'a [P]> {
+	let len = dims[0] as usize * dims[1] as usize;
+	let pixel_size = mem::size_of::<P>();
+	if (size as usize) < len * pixel_size {
+		warn!("{} bytes is not enough for a {}x{} image of {} byte pixels", size, dims[0], dims[1], pixel_size);
+		return None;
+	}
+	Some(slice::from_raw_parts(pixels as *const P, len))
+}
+
+fn upload<P>(
+	queue: Arc<Queue>,
+	pixels: &[P],
+	dims: [u32; 2],
+	cubemap: bool,
+	format: Format,
+) -> Option<(ImmutableTexture, Box<dyn GpuFuture + Send>)>
+where
+	P: Send + Sync + Clone + 'static,
+	Format: AcceptsPixels<P>,
+{
+	let pixels = pixels.iter().cloned();
+	let res: Result<(_, Box<dyn GpuFuture + Send>), _> = if cubemap {
+		ImmutableTexture::cubemap_from_iter_vk(queue, pixels, dims[0], format)
+			.map(|(tex, fut)| (tex, Box::new(fut) as _))
+	} else {
+		ImmutableTexture::from_iter_vk(queue, pixels, dims, format).map(|(tex, fut)| (tex, Box::new(fut) as _))
+	};
+	res.map_err(|err| warn!("failed to create {:?} image: {}", format, err)).ok()
+}
+
 // buffer can be null. x, y, and format are in/out params.
 #[allow(non_snake_case)]
 pub unsafe extern fn ImageData_ReadPixelData(_this: *mut GGD_ImageData, _buffer: *mut GGD_BufferInfo) {
@@ -1,14 +1,15 @@
 use crate::{
 	ctx,
-	game_graph::{GGIndexFormat::*, GGVertexFormat::*, *},
+	game_graph::{GGDistanceFormat::*, GGIndexFormat::*, GGVertexFormat::*, *},
 	game_graph_driver::*,
 };
-use log::trace;
+use log::{trace, warn};
 use nice_engine::{
+	field_data::FieldData,
 	mesh_data::{MeshData, Pntl_32F},
 	GpuFuture,
 };
-use std::{mem::size_of, slice, sync::Arc};
+use std::{mem::size_of, ptr::null_mut, slice, sync::Arc};
 use vulkano::{
 	buffer::{BufferAccess, BufferUsage, ImmutableBuffer},
 	pipeline::input_assembly::PrimitiveTopology,
@@ -73,7 +74,71 @@ pub unsafe extern fn MeshData_Alloc_Polygon(
 	};
 
 	vertices_future.join(indices_future).then_signal_fence_and_flush().unwrap().wait(None).unwrap();
-	Box::into_raw(Box::new(mesh_data))
+	Box::into_raw(Box::new(GGD_MeshData::Polygon(mesh_data)))
+}
+
+#[allow(non_snake_case)]
+pub unsafe extern fn MeshData_Alloc_Field(
+	fieldFormat: GGDistanceFormat,
+	x: u32,
+	y: u32,
+	z: u32,
+	fieldBuffer: *const GGD_BufferInfo,
+	_cacheBuffer: *mut GGD_BufferInfo,
+) -> *mut GGD_MeshData {
+	trace!("MeshData_Alloc_Field");
+
+	let fieldBuffer = &*fieldBuffer;
+	let ctx = ctx::get();
+	if x == 0 || y == 0 || z == 0 {
+		warn!("field dimensions {}x{}x{} are empty", x, y, z);
+		return null_mut();
+	}
+	let len = x as usize * y as usize * z as usize;
+
+	let elem_size = match fieldFormat {
+		DFMT_EXACT_DISTANCE_8 | DFMT_BOUND_DISTANCE_8 => size_of::<u8>(),
+		DFMT_EXACT_DISTANCE_32F | DFMT_BOUND_DISTANCE_32F => size_of::<f32>(),
+		DFMT_UNDEFINED => {
+			warn!("field format is undefined");
+			return null_mut();
+		},
+	};
+	if fieldBuffer.size != (len * elem_size) as u64 {
+		warn!("field buffer is {} bytes, but a {}x{}x{} field needs {}", fieldBuffer.size, x, y, z, len * elem_size);
+		return null_mut();
+	}
+
+	// bound distances may underestimate the true distance, which ray marching handles the same as exact distances
+	let result = match fieldFormat {
+		DFMT_EXACT_DISTANCE_8 | DFMT_BOUND_DISTANCE_8 => {
+			let data = (fieldBuffer.read)(fieldBuffer, 0, fieldBuffer.size) as *const u8;
+			let data = slice::from_raw_parts(data, len).iter().cloned();
+			FieldData::new_u8(ctx, data, [x, y, z])
+				.map(|(field_data, future)| (field_data, Box::new(future) as Box<dyn GpuFuture>))
+		},
+		DFMT_EXACT_DISTANCE_32F | DFMT_BOUND_DISTANCE_32F => {
+			let data = (fieldBuffer.read)(fieldBuffer, 0, fieldBuffer.size) as *const f32;
+			let data = slice::from_raw_parts(data, len).iter().cloned();
+			FieldData::new_f32(ctx, data, [x, y, z])
+				.map(|(field_data, future)| (field_data, Box::new(future) as Box<dyn GpuFuture>))
+		},
+		DFMT_UNDEFINED => unreachable!(),
+	};
+
+	if let Some(status) = fieldBuffer.status {
+		status(fieldBuffer, GGD_BufferStatus::GGD_BUFFER_CLOSED as _);
+	}
+
+	let (field_data, future) = match result {
+		Ok(result) => result,
+		Err(err) => {
+			warn!("failed to create a {}x{}x{} field: {}", x, y, z, err);
+			return null_mut();
+		},
+	};
+	future.then_signal_fence_and_flush().unwrap().wait(None).unwrap();
+	Box::into_raw(Box::new(GGD_MeshData::Field(field_data)))
 }
 
 #[allow(non_snake_case)]
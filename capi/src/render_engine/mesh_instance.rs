@@ -29,7 +29,17 @@ pub unsafe extern fn MeshInstance_SetMeshData(this: *mut GGD_MeshInstance, mesh:
 	let this = &mut *this;
 	let mesh = &mut *mesh;
 
-	this.inner().write().unwrap().set_mesh_data(Some(mesh.clone()));
+	let mut inner = this.inner().write().unwrap();
+	match mesh {
+		GGD_MeshData::Polygon(mesh_data) => {
+			inner.set_mesh_data(Some(mesh_data.clone()));
+			inner.set_field_data(None);
+		},
+		GGD_MeshData::Field(field_data) => {
+			inner.set_mesh_data(None);
+			inner.set_field_data(Some(field_data.clone()));
+		},
+	}
 }
 
 #[allow(non_snake_case)]
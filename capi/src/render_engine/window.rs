@@ -7,36 +7,82 @@ use crate::{
 	game_graph::GGPlatform,
 	game_graph_driver::{GGD_Camera, GGD_ImageData, GGD_Window, GGD_WindowInfo},
 };
-use log::trace;
-use nice_engine::surface::Surface;
-use std::ptr::null_mut;
+use log::{trace, warn};
+use nice_engine::surface::{PresentMode, Surface, SurfaceConfig};
+use std::{convert::TryFrom, ptr::null_mut};
+
+/// Extends the driver API with swapchain settings. `PresentMode` is 0 for automatic, or 1 + the `VkPresentModeKHR`
+/// value. `ImageCount` is 0 to use the minimum.
+#[allow(non_snake_case)]
+#[repr(C)]
+pub struct NiceSurfaceConfig {
+	pub PresentMode: u32,
+	pub VSync: i32,
+	pub ImageCount: u32,
+}
+impl TryFrom<&NiceSurfaceConfig> for SurfaceConfig {
+	/// The invalid `PresentMode`.
+	type Error = u32;
+
+	fn try_from(config: &NiceSurfaceConfig) -> Result<Self, u32> {
+		let present_mode = match config.PresentMode {
+			0 => None,
+			1 => Some(PresentMode::Immediate),
+			2 => Some(PresentMode::Mailbox),
+			3 => Some(PresentMode::Fifo),
+			4 => Some(PresentMode::Relaxed),
+			mode => return Err(mode),
+		};
+		let image_count = if config.ImageCount == 0 { None } else { Some(config.ImageCount) };
+		Ok(SurfaceConfig { present_mode, vsync: config.VSync != 0, image_count })
+	}
+}
 
 #[allow(non_snake_case)]
 pub unsafe extern fn Window_Alloc(info: *mut GGD_WindowInfo) -> *mut GGD_Window {
 	trace!("Window_Alloc");
 
+	alloc(info, SurfaceConfig::default())
+}
+
+/// Like `Window_Alloc`, but with swapchain settings. Returns null if `config` is invalid.
+#[allow(non_snake_case)]
+#[no_mangle]
+pub unsafe extern fn NiceWindow_Alloc(info: *mut GGD_WindowInfo, config: *const NiceSurfaceConfig) -> *mut GGD_Window {
+	trace!("NiceWindow_Alloc");
+
+	match SurfaceConfig::try_from(&*config) {
+		Ok(config) => alloc(info, config),
+		Err(mode) => {
+			warn!("invalid present mode {}", mode);
+			null_mut()
+		},
+	}
+}
+
+unsafe fn alloc(info: *mut GGD_WindowInfo, config: SurfaceConfig) -> *mut GGD_Window {
 	let info_ref = &*info;
 
 	let surface = match GGPlatform::from_u64_unchecked(info_ref.platform) {
 		#[cfg(windows)]
 		GGPlatform::WIN32 => {
 			let info_ref = &*(info as *mut GGD_WindowInfo_WIN32);
-			Surface::from_hwnd(ctx::get(), info_ref.hinstance, info_ref.hwnd)
+			Surface::from_hwnd(ctx::get(), info_ref.hinstance, info_ref.hwnd, config)
 		},
 		#[cfg(unix)]
 		GGPlatform::WAYLAND => {
 			let info_ref = &*(info as *mut GGD_WindowInfo_WAYLAND);
-			Surface::from_wayland(ctx::get(), info_ref.display, info_ref.surface)
+			Surface::from_wayland(ctx::get(), info_ref.display, info_ref.surface, config)
 		},
 		#[cfg(unix)]
 		GGPlatform::X11 => {
 			let info_ref = &*(info as *mut GGD_WindowInfo_X11);
-			Surface::from_xlib(ctx::get(), info_ref.display, info_ref.window)
+			Surface::from_xlib(ctx::get(), info_ref.display, info_ref.window, config)
 		},
 		#[cfg(unix)]
 		GGPlatform::OSX => {
 			let info_ref = &*(info as *mut GGD_WindowInfo_X11);
-			Surface::from_xlib(ctx::get(), info_ref.display, info_ref.window)
+			Surface::from_xlib(ctx::get(), info_ref.display, info_ref.window, config)
 		},
 		_ => panic!("invalid platform"),
 	};
@@ -90,3 +136,44 @@ pub unsafe extern fn Window_Draw(this: *mut GGD_Window) {
 
 	this.draw();
 }
+
+#[allow(non_snake_case)]
+#[no_mangle]
+pub unsafe extern fn NiceWindow_GetConfig(this: *mut GGD_Window, config: *mut NiceSurfaceConfig) {
+	trace!("NiceWindow_GetConfig");
+
+	let this = &mut *this;
+	let config = &mut *config;
+
+	let current = this.config();
+	config.PresentMode = match current.present_mode {
+		None => 0,
+		Some(PresentMode::Immediate) => 1,
+		Some(PresentMode::Mailbox) => 2,
+		Some(PresentMode::Fifo) => 3,
+		Some(PresentMode::Relaxed) => 4,
+	};
+	config.VSync = current.vsync as i32;
+	config.ImageCount = current.image_count.unwrap_or(0);
+}
+
+/// Returns 1 if the config was applied, or 0 if it's invalid.
+#[allow(non_snake_case)]
+#[no_mangle]
+pub unsafe extern fn NiceWindow_SetConfig(this: *mut GGD_Window, config: *const NiceSurfaceConfig) -> i32 {
+	trace!("NiceWindow_SetConfig");
+
+	let this = &mut *this;
+	let config = &*config;
+
+	match SurfaceConfig::try_from(config) {
+		Ok(config) => {
+			this.set_config(config);
+			true as i32
+		},
+		Err(mode) => {
+			warn!("invalid present mode {}", mode);
+			false as i32
+		},
+	}
+}
@@ -0,0 +1,296 @@
+use crate::{
+	pipelines::{DeferredPipelineDef, PipelineContext, PipelineDef},
+	resources::Resources,
+	Context,
+};
+use log::info;
+use maplit::{hashmap, hashset};
+use std::{
+	any::TypeId,
+	collections::HashSet,
+	error, fmt,
+	sync::{Arc, Mutex},
+};
+#[cfg(debug_assertions)]
+use vulkano::instance::debug::DebugCallback;
+use vulkano::{
+	device::{Device, DeviceCreationError, DeviceExtensions, Features},
+	instance::{
+		self, ApplicationInfo, Instance, InstanceCreationError, InstanceExtensions, LayersListError, PhysicalDevice,
+		PhysicalDeviceType, Version,
+	},
+	sync::GpuFuture,
+};
+
+/// Creates a `Context`, optionally choosing which physical device it uses.
+pub struct ContextBuilder {
+	instance: Arc<Instance>,
+	#[cfg(debug_assertions)]
+	debug_callback: DebugCallback,
+	headless: bool,
+	selection: DeviceSelection,
+	features: Features,
+}
+impl ContextBuilder {
+	pub fn new(name: Option<&str>, version: Option<Version>) -> Result<Self, ContextCreationError> {
+		Self::new_inner(name, version, false)
+	}
+
+	/// Like `new`, but without any surface or swapchain extensions, so it works without a display server. Surfaces and
+	/// windows can't be created with the resulting context, so nothing can be drawn, but resources can still be loaded.
+	pub fn headless(name: Option<&str>, version: Option<Version>) -> Result<Self, ContextCreationError> {
+		Self::new_inner(name, version, true)
+	}
+
+	fn new_inner(name: Option<&str>, version: Option<Version>, headless: bool) -> Result<Self, ContextCreationError> {
+		let app_info = ApplicationInfo {
+			application_name: name.map(|x| x.into()),
+			application_version: version,
+			engine_name: Some("nIce Game".into()),
+			engine_version: Some(Version {
+				major: env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap(),
+				minor: env!("CARGO_PKG_VERSION_MINOR").parse().unwrap(),
+				patch: env!("CARGO_PKG_VERSION_PATCH").parse().unwrap(),
+			}),
+		};
+
+		let exts = InstanceExtensions {
+			khr_surface: !headless,
+			khr_xlib_surface: !headless,
+			khr_xcb_surface: !headless,
+			khr_wayland_surface: !headless,
+			khr_android_surface: !headless,
+			khr_win32_surface: !headless,
+			mvk_ios_surface: !headless,
+			mvk_macos_surface: !headless,
+			#[cfg(debug_assertions)]
+			ext_debug_utils: true,
+			..InstanceExtensions::none()
+		};
+
+		let exts = match InstanceExtensions::supported_by_core() {
+			Ok(supported) => supported.intersection(&exts),
+			Err(_) => InstanceExtensions::none(),
+		};
+
+		#[cfg(debug_assertions)]
+		let layers = hashset! {
+			// "VK_LAYER_KHRONOS_validation".to_owned(),
+			"VK_LAYER_LUNARG_monitor".to_owned(),
+		};
+		#[cfg(not(debug_assertions))]
+		let layers = hashset! {
+			"VK_LAYER_LUNARG_monitor".to_owned(),
+		};
+
+		let instance_layers = instance::layers_list()?.map(|l| l.name().to_owned()).collect::<HashSet<String>>();
+		let layers = instance_layers.intersection(&layers).map(|s| s as &str);
+
+		let instance = Instance::new(Some(&app_info), &exts, layers)?;
+
+		#[cfg(debug_assertions)]
+		let debug_callback = DebugCallback::errors_and_warnings(&instance, |msg| {
+			if msg.severity.error {
+				log::error!("[{}]{}", msg.layer_prefix, msg.description);
+			} else {
+				log::warn!("[{}]{}", msg.layer_prefix, msg.description);
+			}
+		})
+		.unwrap();
+
+		Ok(Self {
+			instance,
+			#[cfg(debug_assertions)]
+			debug_callback,
+			headless,
+			selection: DeviceSelection::Auto,
+			features: default_features(Features::none()),
+		})
+	}
+
+	/// Lists every physical device, including ones that can't be used because they have no graphics queue.
+	pub fn devices(&self) -> Vec<DeviceInfo> {
+		PhysicalDevice::enumerate(&self.instance).map(DeviceInfo::new).collect()
+	}
+
+	pub fn device(mut self, selection: DeviceSelection) -> Self {
+		self.selection = selection;
+		self
+	}
+
+	/// Features to enable if the device supports them, in addition to `sampler_anisotropy`, `texture_compression_bc`,
+	/// `multi_draw_indirect` and `draw_indirect_first_instance`, which are always enabled when supported. In
+	/// `DeviceSelection::Auto`, devices that support all of them are preferred.
+	pub fn features(mut self, features: Features) -> Self {
+		self.features = default_features(features);
+		self
+	}
+
+	pub fn build(self) -> Result<(Arc<Context>, impl GpuFuture), ContextCreationError> {
+		self.build_with_pipeline::<DeferredPipelineDef>()
+	}
+
+	/// Like `build`, but surfaces created with this context will use the pipeline `P` by default.
+	pub fn build_with_pipeline<P: PipelineDef + 'static>(
+		self,
+	) -> Result<(Arc<Context>, impl GpuFuture), ContextCreationError> {
+		let pdevice = self.select_device()?;
+		info!("Using device: {} ({:?})", pdevice.name(), pdevice.ty());
+
+		let features = pdevice.supported_features().intersection(&self.features);
+		let qfam = pdevice.queue_families().find(|&q| q.supports_graphics()).unwrap();
+		let (device, mut queues) = Device::new(
+			pdevice,
+			&features,
+			&DeviceExtensions { khr_swapchain: !self.headless, ..DeviceExtensions::none() },
+			[(qfam, 1.0)].iter().cloned(),
+		)?;
+		let queue = queues.next().unwrap();
+
+		let (pipeline_ctx, pipeline_ctx_future) = P::make_context(&device, &queue);
+		let pipeline_ctx: Arc<dyn PipelineContext> = pipeline_ctx.into();
+		let pipeline_ctxs = Mutex::new(hashmap! { TypeId::of::<P>() => pipeline_ctx.clone() });
+
+		let (resources, resources_future) = Resources::new(queue.clone(), pipeline_ctx.layout_desc().clone());
+
+		Ok((
+			Arc::new(Context {
+				instance: self.instance,
+				#[cfg(debug_assertions)]
+				debug_callback: self.debug_callback,
+				headless: self.headless,
+				device,
+				queue,
+				pipeline_ctx,
+				pipeline_ctxs,
+				resources: Arc::new(resources),
+			}),
+			pipeline_ctx_future.join(resources_future),
+		))
+	}
+
+	fn select_device(&self) -> Result<PhysicalDevice, ContextCreationError> {
+		let mut pdevices = PhysicalDevice::enumerate(&self.instance)
+			.filter(|pd| pd.queue_families().any(|q| q.supports_graphics()))
+			.peekable();
+		if pdevices.peek().is_none() {
+			return Err(ContextCreationError::NoSuitableDevice);
+		}
+
+		let auto_key = |pd: &PhysicalDevice| (pd.supported_features().superset_of(&self.features), type_rank(pd.ty()));
+		let pdevice = match &self.selection {
+			DeviceSelection::Auto => pdevices.max_by_key(auto_key),
+			DeviceSelection::Index(index) => pdevices.find(|pd| pd.index() == *index),
+			DeviceSelection::Name(name) => pdevices.find(|pd| pd.name().contains(name.as_str())),
+			DeviceSelection::Type(ty) => pdevices.max_by_key(|pd| (pd.ty() == *ty, auto_key(pd))),
+		};
+		pdevice.ok_or_else(|| ContextCreationError::DeviceNotFound(self.selection.clone()))
+	}
+}
+
+/// Adds the features that are always enabled when supported to `features`.
+fn default_features(features: Features) -> Features {
+	Features {
+		sampler_anisotropy: true,
+		texture_compression_bc: true,
+		// indirect draws are grouped into multi-draws, and find their transforms with the first instance
+		multi_draw_indirect: true,
+		draw_indirect_first_instance: true,
+		..features
+	}
+}
+
+/// Higher is better.
+fn type_rank(ty: PhysicalDeviceType) -> u32 {
+	match ty {
+		PhysicalDeviceType::DiscreteGpu => 4,
+		PhysicalDeviceType::IntegratedGpu => 3,
+		PhysicalDeviceType::VirtualGpu => 2,
+		PhysicalDeviceType::Cpu => 1,
+		PhysicalDeviceType::Other => 0,
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DeviceSelection {
+	/// Prefers devices that support all requested features, then discrete, integrated, virtual and CPU devices, in that
+	/// order.
+	Auto,
+	/// An index into `ContextBuilder::devices`.
+	Index(usize),
+	/// The first device whose name contains this string.
+	Name(String),
+	/// Prefers devices of this type, but falls back to the same order as `Auto` if there are none.
+	Type(PhysicalDeviceType),
+}
+
+#[derive(Clone, Debug)]
+pub struct DeviceInfo {
+	pub index: usize,
+	pub name: String,
+	pub ty: PhysicalDeviceType,
+	/// The total size of all device-local memory heaps, in bytes.
+	pub memory: usize,
+	pub features: Features,
+	/// Devices without a graphics queue can't be selected.
+	pub graphics: bool,
+}
+impl DeviceInfo {
+	fn new(pd: PhysicalDevice) -> Self {
+		Self {
+			index: pd.index(),
+			name: pd.name().to_owned(),
+			ty: pd.ty(),
+			memory: pd.memory_heaps().filter(|h| h.is_device_local()).map(|h| h.size()).sum(),
+			features: *pd.supported_features(),
+			graphics: pd.queue_families().any(|q| q.supports_graphics()),
+		}
+	}
+}
+
+#[derive(Debug)]
+pub enum ContextCreationError {
+	LayersList(LayersListError),
+	InstanceCreation(InstanceCreationError),
+	DeviceCreation(DeviceCreationError),
+	/// There are no devices with a graphics queue.
+	NoSuitableDevice,
+	/// The device chosen by index or name doesn't exist or has no graphics queue.
+	DeviceNotFound(DeviceSelection),
+}
+impl fmt::Display for ContextCreationError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			ContextCreationError::LayersList(err) => write!(f, "failed to list instance layers: {}", err),
+			ContextCreationError::InstanceCreation(err) => write!(f, "failed to create instance: {}", err),
+			ContextCreationError::DeviceCreation(err) => write!(f, "failed to create device: {}", err),
+			ContextCreationError::NoSuitableDevice => write!(f, "no device supports graphics"),
+			ContextCreationError::DeviceNotFound(selection) => write!(f, "no usable device matches {:?}", selection),
+		}
+	}
+}
+impl error::Error for ContextCreationError {
+	fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+		match self {
+			ContextCreationError::LayersList(err) => Some(err),
+			ContextCreationError::InstanceCreation(err) => Some(err),
+			ContextCreationError::DeviceCreation(err) => Some(err),
+			ContextCreationError::NoSuitableDevice | ContextCreationError::DeviceNotFound(_) => None,
+		}
+	}
+}
+impl From<LayersListError> for ContextCreationError {
+	fn from(err: LayersListError) -> Self {
+		ContextCreationError::LayersList(err)
+	}
+}
+impl From<InstanceCreationError> for ContextCreationError {
+	fn from(err: InstanceCreationError) -> Self {
+		ContextCreationError::InstanceCreation(err)
+	}
+}
+impl From<DeviceCreationError> for ContextCreationError {
+	fn from(err: DeviceCreationError) -> Self {
+		ContextCreationError::DeviceCreation(err)
+	}
+}
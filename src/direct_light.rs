@@ -1,5 +1,10 @@
-use cgmath::{vec3, Vector3};
+use crate::transform::Transform;
+use cgmath::{prelude::*, vec2, vec3, Vector2, Vector3, Vector4};
 
+/// A point light that reaches exactly `radius`.
+///
+/// The deferred pipeline shades each pixel with at most 63 lights, the ones that were binned into its cluster first.
+/// Any further lights reaching the same cluster are skipped there, so dense groups of lights should be merged.
 pub struct DirectLight {
 	pub position: Vector3<f32>,
 	pub color: Vector3<f32>,
@@ -9,4 +14,121 @@ impl DirectLight {
 	pub fn new() -> Self {
 		DirectLight { position: vec3(0.0, 0.0, 0.0), color: vec3(0.0, 0.0, 0.0), radius: 0.0 }
 	}
+
+	/// Returns the `(position, color)` pair in the layout expected by `direct_light` in `lighting.glsl`.
+	pub(crate) fn packed(&self) -> ([f32; 4], [f32; 4]) {
+		let light_cutoff = 0.003035269835488375;
+		let radius_squared = self.radius * self.radius;
+		(
+			[self.position.x, self.position.y, self.position.z, 1.0 / radius_squared],
+			[self.color.x, self.color.y, self.color.z, light_cutoff * radius_squared],
+		)
+	}
+
+	/// Returns the part of the screen and the view distances this light can reach from a camera with the projection
+	/// `proj` and the transform `transform`, or `None` if it's entirely behind the camera or off screen.
+	pub(crate) fn bounds(&self, proj: Vector4<f32>, transform: &Transform) -> Option<LightBounds> {
+		let pos_cs = transform.rot.invert().rotate_vector(self.position - transform.pos.truncate());
+		let pos_es = vec3(pos_cs.x, -pos_cs.z, -pos_cs.y);
+		let distance = -pos_es.z;
+		let near_plane = proj.w / proj.z;
+		let (near, far) = (distance - self.radius, distance + self.radius);
+		if far <= near_plane {
+			return None;
+		}
+		if near <= near_plane {
+			// the camera is inside the volume, or the volume is cut by the near plane, so it can cover any pixel
+			return Some(LightBounds { min: vec2(-1.0, -1.0), max: vec2(1.0, 1.0), near: 0.0, far });
+		}
+
+		// bounds the sphere with a box, which is widest on screen at its near face and narrowest at its far face
+		let project = |side: f32, outward: bool, scale: f32| side / if outward { near } else { far } * scale;
+		let radius = vec2(self.radius, self.radius);
+		let (lo, hi) = (pos_es.truncate() - radius, pos_es.truncate() + radius);
+		let min = vec2(project(lo.x, lo.x < 0.0, proj.x).max(-1.0), project(lo.y, lo.y < 0.0, proj.y).max(-1.0));
+		let max = vec2(project(hi.x, hi.x > 0.0, proj.x).min(1.0), project(hi.y, hi.y > 0.0, proj.y).min(1.0));
+		if min.x >= max.x || min.y >= max.y {
+			return None;
+		}
+		Some(LightBounds { min, max, near, far })
+	}
+}
+
+/// A screen-space rectangle in normalized device coordinates, and a range of view distances.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct LightBounds {
+	pub(crate) min: Vector2<f32>,
+	pub(crate) max: Vector2<f32>,
+	pub(crate) near: f32,
+	pub(crate) far: f32,
+}
+impl LightBounds {
+	pub(crate) fn union(self, other: Self) -> Self {
+		Self {
+			min: vec2(self.min.x.min(other.min.x), self.min.y.min(other.min.y)),
+			max: vec2(self.max.x.max(other.max.x), self.max.y.max(other.max.y)),
+			near: self.near.min(other.near),
+			far: self.far.max(other.far),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::camera::projection;
+
+	fn light(x: f32, y: f32, z: f32, radius: f32) -> DirectLight {
+		DirectLight { position: vec3(x, y, z), color: vec3(1.0, 1.0, 1.0), radius }
+	}
+
+	// a square view with a 90 degree field of view, looking along +y from the origin
+	fn bounds(light: &DirectLight) -> Option<LightBounds> {
+		light.bounds(projection(1.0, 90.0, 0.1, 100.0), &Transform::default())
+	}
+
+	fn full_screen(bounds: &LightBounds) -> bool {
+		bounds.min == vec2(-1.0, -1.0) && bounds.max == vec2(1.0, 1.0) && bounds.near == 0.0
+	}
+
+	#[test]
+	fn in_front() {
+		let bounds = bounds(&light(0.0, 10.0, 0.0, 1.0)).unwrap();
+		assert!(bounds.min.x < 0.0 && bounds.min.y < 0.0 && bounds.max.x > 0.0 && bounds.max.y > 0.0);
+		assert!(bounds.min.x > -0.2 && bounds.min.y > -0.2 && bounds.max.x < 0.2 && bounds.max.y < 0.2);
+		assert_eq!((bounds.near, bounds.far), (9.0, 11.0));
+	}
+
+	#[test]
+	fn camera_inside() {
+		let bounds = bounds(&light(0.0, 0.5, 0.0, 1.0)).unwrap();
+		assert!(full_screen(&bounds));
+		assert_eq!(bounds.far, 1.5);
+
+		// mostly behind the camera, but it still surrounds it
+		let bounds = self::bounds(&light(0.0, -0.5, 0.0, 1.0)).unwrap();
+		assert!(full_screen(&bounds));
+		assert_eq!(bounds.far, 0.5);
+	}
+
+	#[test]
+	fn straddles_near_plane() {
+		// the camera is outside, but the near plane cuts the volume
+		let bounds = bounds(&light(0.0, 0.5, 0.0, 0.45)).unwrap();
+		assert!(full_screen(&bounds));
+		assert_eq!(bounds.far, 0.95);
+	}
+
+	#[test]
+	fn behind_camera() {
+		assert!(bounds(&light(0.0, -5.0, 0.0, 1.0)).is_none());
+		// touching the camera from behind, but not reaching the near plane
+		assert!(bounds(&light(0.0, -1.0, 0.0, 1.0)).is_none());
+	}
+
+	#[test]
+	fn off_screen() {
+		assert!(bounds(&light(100.0, 10.0, 0.0, 1.0)).is_none());
+		assert!(bounds(&light(0.0, 10.0, -100.0, 1.0)).is_none());
+	}
 }
@@ -0,0 +1,94 @@
+use crate::Context;
+use half::f16;
+use std::sync::{Arc, Mutex};
+use vulkano::{
+	descriptor::DescriptorSet,
+	format::Format,
+	image::{Dimensions, ImageCreationError, ImageViewAccess, ImmutableImage},
+	sync::GpuFuture,
+};
+
+/// A signed distance field sampled on a regular grid, drawn by ray marching instead of rasterizing triangles.
+///
+/// The field fills a box centered on the mesh's origin. Voxels are cubes, and the longest side of the box has a length
+/// of 1. Negative distances are inside the surface.
+pub struct FieldData {
+	image: Arc<dyn ImageViewAccess + Send + Sync>,
+	extent: [f32; 3],
+	distance_scale: f32,
+	distance_bias: f32,
+	/// The image never changes, so unlike a mesh's textures there's no version to check before reusing the set.
+	desc: Mutex<Option<Arc<dyn DescriptorSet + Send + Sync>>>,
+}
+impl FieldData {
+	/// Each byte maps linearly from 0..=255 to distances of -1..=1, measured in the same units as the box.
+	pub fn new_u8<I>(
+		ctx: &Context,
+		data: I,
+		dimensions: [u32; 3],
+	) -> Result<(Arc<Self>, impl GpuFuture), ImageCreationError>
+	where
+		I: ExactSizeIterator<Item = u8>,
+	{
+		let (image, future) =
+			ImmutableImage::from_iter(data, dims_3d(dimensions), Format::R8Unorm, ctx.queue().clone())?;
+		let field = Self {
+			image,
+			extent: extent(dimensions),
+			distance_scale: 2.0,
+			distance_bias: -1.0,
+			desc: Mutex::new(None),
+		};
+		Ok((Arc::new(field), future))
+	}
+
+	/// Distances are measured in voxels. They're stored as half floats, because many devices can't filter 32-bit
+	/// float images.
+	pub fn new_f32<I>(
+		ctx: &Context,
+		data: I,
+		dimensions: [u32; 3],
+	) -> Result<(Arc<Self>, impl GpuFuture), ImageCreationError>
+	where
+		I: ExactSizeIterator<Item = f32>,
+	{
+		let data = data.map(f16::from_f32);
+		let (image, future) =
+			ImmutableImage::from_iter(data, dims_3d(dimensions), Format::R16Sfloat, ctx.queue().clone())?;
+		let distance_scale = 1.0 / *dimensions.iter().max().unwrap() as f32;
+		let field =
+			Self { image, extent: extent(dimensions), distance_scale, distance_bias: 0.0, desc: Mutex::new(None) };
+		Ok((Arc::new(field), future))
+	}
+
+	pub(crate) fn image(&self) -> &Arc<dyn ImageViewAccess + Send + Sync> {
+		&self.image
+	}
+
+	/// The descriptor set that samples the image, made by `make` the first time it's needed.
+	pub(crate) fn desc(
+		&self,
+		make: impl FnOnce() -> Arc<dyn DescriptorSet + Send + Sync>,
+	) -> Arc<dyn DescriptorSet + Send + Sync> {
+		self.desc.lock().unwrap().get_or_insert_with(make).clone()
+	}
+
+	/// The size of the box in the mesh's local space.
+	pub(crate) fn extent(&self) -> [f32; 3] {
+		self.extent
+	}
+
+	/// Multiplying a sampled value by `x` and adding `y` gives a distance in the mesh's local space.
+	pub(crate) fn distance_transform(&self) -> [f32; 2] {
+		[self.distance_scale, self.distance_bias]
+	}
+}
+
+fn dims_3d(dimensions: [u32; 3]) -> Dimensions {
+	Dimensions::Dim3d { width: dimensions[0], height: dimensions[1], depth: dimensions[2] }
+}
+
+fn extent(dimensions: [u32; 3]) -> [f32; 3] {
+	let max = *dimensions.iter().max().unwrap() as f32;
+	[dimensions[0] as f32 / max, dimensions[1] as f32 / max, dimensions[2] as f32 / max]
+}
@@ -0,0 +1,124 @@
+use std::{
+	collections::VecDeque,
+	time::{Duration, Instant},
+};
+use vulkano::pipeline::input_assembly::PrimitiveTopology;
+
+const SAMPLES: usize = 60;
+
+/// Timings averaged over the last 60 frames, and counts from the most recent frame.
+///
+/// GPU times are zero if the device can't write timestamps on graphics queues.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FrameStats {
+	/// Time between the starts of consecutive calls to `Surface::draw`.
+	pub frame_time: Duration,
+	/// Time spent inside `Surface::draw`, including waiting for the previous frame to finish.
+	pub cpu_time: Duration,
+	/// GPU time spent drawing opaque geometry. The forward pipeline lights and draws transparent geometry as it goes,
+	/// so for it this includes everything `gpu_lighting_time` would.
+	pub gpu_geometry_time: Duration,
+	/// GPU time spent lighting the scene and drawing transparent geometry.
+	pub gpu_lighting_time: Duration,
+	/// GPU time spent writing the lit scene to the swapchain image, including post processing.
+	pub gpu_present_time: Duration,
+	pub draw_calls: u32,
+	pub triangles: u64,
+	pub lights: u32,
+}
+
+/// What a pipeline submitted while recording a frame.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct DrawCounts {
+	pub(crate) draw_calls: u32,
+	pub(crate) triangles: u64,
+	pub(crate) lights: u32,
+}
+impl DrawCounts {
+	pub(crate) fn add_draw(&mut self, triangles: u64) {
+		self.draw_calls += 1;
+		self.triangles += triangles;
+	}
+
+	pub(crate) fn add_indexed(&mut self, topology: PrimitiveTopology, indices: usize) {
+		self.add_instanced(topology, indices, 1);
+	}
+
+	pub(crate) fn add_instanced(&mut self, topology: PrimitiveTopology, indices: usize, instances: usize) {
+		self.add_draw(triangles(topology, indices) * instances as u64);
+	}
+
+	pub(crate) fn merge(&mut self, other: DrawCounts) {
+		self.draw_calls += other.draw_calls;
+		self.triangles += other.triangles;
+		self.lights += other.lights;
+	}
+}
+
+#[derive(Default)]
+pub(crate) struct FrameStatsRecorder {
+	frame_times: VecDeque<Duration>,
+	cpu_times: VecDeque<Duration>,
+	gpu_times: [VecDeque<Duration>; 3],
+	last_start: Option<Instant>,
+	counts: DrawCounts,
+}
+impl FrameStatsRecorder {
+	pub(crate) fn start_frame(&mut self) -> Instant {
+		let now = Instant::now();
+		if let Some(last_start) = self.last_start {
+			push_sample(&mut self.frame_times, now - last_start);
+		}
+		self.last_start = Some(now);
+		now
+	}
+
+	pub(crate) fn end_frame(&mut self, start: Instant, counts: DrawCounts) {
+		push_sample(&mut self.cpu_times, start.elapsed());
+		self.counts = counts;
+	}
+
+	/// Adds the geometry, lighting and present times of a frame, which may be older than the one just ended.
+	pub(crate) fn add_gpu_times(&mut self, times: [Duration; 3]) {
+		for (samples, &time) in self.gpu_times.iter_mut().zip(&times) {
+			push_sample(samples, time);
+		}
+	}
+
+	pub(crate) fn stats(&self) -> FrameStats {
+		FrameStats {
+			frame_time: average(&self.frame_times),
+			cpu_time: average(&self.cpu_times),
+			gpu_geometry_time: average(&self.gpu_times[0]),
+			gpu_lighting_time: average(&self.gpu_times[1]),
+			gpu_present_time: average(&self.gpu_times[2]),
+			draw_calls: self.counts.draw_calls,
+			triangles: self.counts.triangles,
+			lights: self.counts.lights,
+		}
+	}
+}
+
+/// The number of triangles drawn from `indices` indices.
+pub(crate) fn triangles(topology: PrimitiveTopology, indices: usize) -> u64 {
+	let triangles = match topology {
+		PrimitiveTopology::TriangleStrip => indices.saturating_sub(2),
+		_ => indices / 3,
+	};
+	triangles as u64
+}
+
+fn push_sample(samples: &mut VecDeque<Duration>, sample: Duration) {
+	if samples.len() == SAMPLES {
+		samples.pop_front();
+	}
+	samples.push_back(sample);
+}
+
+fn average(samples: &VecDeque<Duration>) -> Duration {
+	if samples.is_empty() {
+		Duration::default()
+	} else {
+		samples.iter().sum::<Duration>() / samples.len() as u32
+	}
+}
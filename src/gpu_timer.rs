@@ -0,0 +1,210 @@
+use std::{mem, sync::Arc, time::Duration};
+use vulkano::{
+	buffer::BufferAccess,
+	command_buffer::{
+		pool::standard::StandardCommandPoolAlloc,
+		sys::{
+			Flags, Kind, KindOcclusionQuery, KindSecondaryRenderPass, UnsafeCommandBuffer, UnsafeCommandBufferBuilder,
+		},
+		AutoCommandBufferBuilder, CommandBuffer, CommandBufferExecError,
+	},
+	device::{Device, DeviceOwned, Queue},
+	framebuffer::{FramebufferAbstract, RenderPassAbstract, Subpass},
+	image::{ImageAccess, ImageLayout},
+	query::{QueryPipelineStatisticFlags, QueryType, UnsafeQueryPool},
+	sync::{AccessCheckError, AccessFlagBits, GpuFuture, PipelineStages},
+	VulkanObject,
+};
+
+/// The timestamps written during a frame, in the order they're written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Stamp {
+	/// Written by `Surface` before the pipeline's commands.
+	Start,
+	/// Written by the pipeline once opaque geometry has been drawn.
+	Geometry,
+	/// Written by the pipeline once the scene has been lit, before it's tonemapped into the swapchain image.
+	Lighting,
+	/// Written by `Surface` after the pipeline's commands.
+	End,
+}
+
+const STAMPS: u32 = 4;
+// a frame is only read after the next one has been submitted, so two frames use their queries at once
+const FRAMES: u32 = 2;
+
+/// Measures how long the GPU spends on each part of a frame with timestamp queries.
+///
+/// vulkano's `AutoCommandBufferBuilder` can't write timestamps, so each one is recorded into a tiny secondary command
+/// buffer with the unsafe builder, which is then executed wherever the timestamp belongs.
+pub(crate) struct GpuTimer {
+	pool: Arc<UnsafeQueryPool>,
+	qfam: u32,
+	/// Nanoseconds per tick.
+	period: f64,
+	next: u32,
+	latest: Option<u32>,
+	/// The frame submitted before `latest`, which is read once it has finished.
+	previous: Option<u32>,
+}
+impl GpuTimer {
+	/// Returns `None` if the device can't write timestamps on graphics queues.
+	pub(crate) fn new(queue: &Arc<Queue>) -> Option<Self> {
+		let device = queue.device();
+		let limits = device.physical_device().limits();
+		if limits.timestamp_compute_and_graphics() == 0 {
+			return None;
+		}
+
+		let pool = UnsafeQueryPool::new(device.clone(), QueryType::Timestamp, STAMPS * FRAMES).unwrap();
+		Some(Self {
+			pool: Arc::new(pool),
+			qfam: queue.family().id(),
+			period: limits.timestamp_period() as f64,
+			next: 0,
+			latest: None,
+			previous: None,
+		})
+	}
+
+	/// Hands out the queries for a new frame. They were last used two frames ago, which must have been read or
+	/// abandoned by now.
+	pub(crate) fn start_frame(&mut self) -> FrameQueries {
+		let frame = self.next;
+		self.next = (frame + 1) % FRAMES;
+		self.previous = self.latest.replace(frame);
+		FrameQueries { pool: self.pool.clone(), qfam: self.qfam, first: frame * STAMPS }
+	}
+
+	/// Forgets the latest frame because it was never submitted, so the next `read_previous` skips it instead of
+	/// reading queries that were never written.
+	pub(crate) fn abandon_latest(&mut self) {
+		self.latest = None;
+	}
+
+	/// How long the geometry, lighting and present parts of the frame before the latest one took. Must only be called
+	/// once that frame has finished. Returns `None` if it was already read, or if it never ran.
+	pub(crate) fn read_previous(&mut self) -> Option<[Duration; 3]> {
+		let frame = self.previous.take()?;
+		let device = self.pool.device();
+
+		let mut stamps = [0u64; STAMPS as usize];
+		let result = unsafe {
+			device.pointers().GetQueryPoolResults(
+				device.internal_object(),
+				self.pool.internal_object(),
+				frame * STAMPS,
+				STAMPS,
+				mem::size_of_val(&stamps),
+				stamps.as_mut_ptr() as *mut _,
+				mem::size_of::<u64>() as u64,
+				vk_sys::QUERY_RESULT_64_BIT,
+			)
+		};
+		if result != vk_sys::SUCCESS {
+			return None;
+		}
+
+		let mut durations = [Duration::default(); 3];
+		for (i, duration) in durations.iter_mut().enumerate() {
+			let ticks = stamps[i + 1].wrapping_sub(stamps[i]);
+			*duration = Duration::from_nanos((ticks as f64 * self.period) as u64);
+		}
+		Some(durations)
+	}
+}
+
+/// The queries of one frame. Every stamp must be written exactly once, in order.
+pub(crate) struct FrameQueries {
+	pool: Arc<UnsafeQueryPool>,
+	qfam: u32,
+	first: u32,
+}
+impl FrameQueries {
+	/// Writes `stamp` once every command before it has finished. `subpass` is the current subpass, which must have
+	/// been started for secondary command buffers, or `None` outside of a render pass. `Stamp::Start` also resets the
+	/// frame's queries, so it must be written outside of a render pass.
+	pub(crate) fn write(
+		&self,
+		command_buffer: AutoCommandBufferBuilder,
+		stamp: Stamp,
+		subpass: Option<Subpass<Arc<dyn RenderPassAbstract + Send + Sync>>>,
+	) -> AutoCommandBufferBuilder {
+		let device = self.pool.device();
+		let qfam = device.physical_device().queue_family_by_id(self.qfam).unwrap();
+		let pool = Device::standard_command_pool(device, qfam);
+		let render_pass = subpass.map(|subpass| {
+			assert!(stamp != Stamp::Start, "Stamp::Start must be written outside of a render pass");
+			KindSecondaryRenderPass { subpass, framebuffer: None::<Arc<dyn FramebufferAbstract + Send + Sync>> }
+		});
+		let kind = Kind::Secondary {
+			render_pass,
+			occlusion_query: KindOcclusionQuery::Forbidden,
+			query_statistics_flags: QueryPipelineStatisticFlags::none(),
+		};
+
+		let index = self.first + stamp as u32;
+		let inner = unsafe {
+			let mut cmds = UnsafeCommandBufferBuilder::new(&pool, kind, Flags::OneTimeSubmit).unwrap();
+			if stamp == Stamp::Start {
+				cmds.reset_query_pool(self.pool.queries_range(self.first, STAMPS).unwrap());
+			}
+			cmds.write_timestamp(
+				self.pool.query(index).unwrap(),
+				PipelineStages { bottom_of_pipe: true, ..PipelineStages::none() },
+			);
+			cmds.build().unwrap()
+		};
+
+		let timestamp = TimestampCommandBuffer { inner, pool: self.pool.clone() };
+		unsafe { command_buffer.execute_commands(timestamp) }.unwrap()
+	}
+}
+
+/// Only writes to a query pool, so it never conflicts with any other resource.
+struct TimestampCommandBuffer {
+	inner: UnsafeCommandBuffer<StandardCommandPoolAlloc>,
+	pool: Arc<UnsafeQueryPool>,
+}
+unsafe impl CommandBuffer for TimestampCommandBuffer {
+	type PoolAlloc = StandardCommandPoolAlloc;
+
+	#[inline]
+	fn inner(&self) -> &UnsafeCommandBuffer<StandardCommandPoolAlloc> {
+		&self.inner
+	}
+
+	#[inline]
+	fn lock_submit(&self, _future: &dyn GpuFuture, _queue: &Queue) -> Result<(), CommandBufferExecError> {
+		Ok(())
+	}
+
+	#[inline]
+	unsafe fn unlock(&self) {}
+
+	#[inline]
+	fn check_buffer_access(
+		&self,
+		_buffer: &dyn BufferAccess,
+		_exclusive: bool,
+		_queue: &Queue,
+	) -> Result<Option<(PipelineStages, AccessFlagBits)>, AccessCheckError> {
+		Err(AccessCheckError::Unknown)
+	}
+
+	#[inline]
+	fn check_image_access(
+		&self,
+		_image: &dyn ImageAccess,
+		_layout: ImageLayout,
+		_exclusive: bool,
+		_queue: &Queue,
+	) -> Result<Option<(PipelineStages, AccessFlagBits)>, AccessCheckError> {
+		Err(AccessCheckError::Unknown)
+	}
+}
+unsafe impl DeviceOwned for TimestampCommandBuffer {
+	fn device(&self) -> &Arc<Device> {
+		self.pool.device()
+	}
+}
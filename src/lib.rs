@@ -1,32 +1,44 @@
 pub mod camera;
+mod context_builder;
 pub mod direct_light;
+pub mod field_data;
+pub mod frame_stats;
+mod gpu_timer;
 pub mod mesh;
 pub mod mesh_data;
 pub mod mesh_group;
 pub mod pipelines;
 pub mod resources;
+pub mod screenshot;
 pub mod surface;
 pub mod texture;
 pub mod threads;
 pub mod transform;
+pub mod vfs;
 #[cfg(feature = "window")]
 pub mod window;
 
+pub use crate::context_builder::{ContextBuilder, ContextCreationError, DeviceInfo, DeviceSelection};
 use crate::{
-	pipelines::{deferred::DeferredPipelineDef, PipelineContext, PipelineDef},
+	pipelines::{DeferredPipelineDef, PipelineContext, PipelineDef},
 	resources::Resources,
 };
-use log::info;
-use maplit::hashset;
-use std::{collections::HashSet, sync::Arc};
+use std::{
+	any::TypeId,
+	collections::HashMap,
+	sync::{Arc, Mutex},
+	thread,
+	time::Duration,
+};
 #[cfg(debug_assertions)]
 use vulkano::instance::debug::DebugCallback;
 use vulkano::{
-	device::{Device, DeviceExtensions, Features, Queue},
-	instance::{self, ApplicationInfo, Instance, InstanceExtensions, PhysicalDevice},
+	device::{Device, Queue},
+	instance::Instance,
 };
 pub use vulkano::{
-	instance::{InstanceCreationError, Version},
+	device::Features,
+	instance::{InstanceCreationError, PhysicalDeviceType, Version},
 	sync::GpuFuture,
 };
 
@@ -37,112 +49,63 @@ pub struct Context {
 	#[allow(dead_code)]
 	#[cfg(debug_assertions)]
 	debug_callback: DebugCallback,
+	headless: bool,
 	device: Arc<Device>,
 	queue: Arc<Queue>,
-	pipeline_ctx: Box<dyn PipelineContext>,
-	resources: Resources,
+	pipeline_ctx: Arc<dyn PipelineContext>,
+	pipeline_ctxs: Mutex<HashMap<TypeId, Arc<dyn PipelineContext>>>,
+	resources: Arc<Resources>,
 }
 impl Context {
+	/// Creates a context on the best available device. Use `ContextBuilder` to choose a device explicitly.
 	pub fn new(
 		name: Option<&str>,
 		version: Option<Version>,
-	) -> Result<(Arc<Self>, impl GpuFuture), InstanceCreationError> {
-		let app_info = ApplicationInfo {
-			application_name: name.map(|x| x.into()),
-			application_version: version,
-			engine_name: Some("nIce Game".into()),
-			engine_version: Some(Version {
-				major: env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap(),
-				minor: env!("CARGO_PKG_VERSION_MINOR").parse().unwrap(),
-				patch: env!("CARGO_PKG_VERSION_PATCH").parse().unwrap(),
-			}),
-		};
-
-		let exts = InstanceExtensions {
-			khr_surface: true,
-			khr_xlib_surface: true,
-			khr_xcb_surface: true,
-			khr_wayland_surface: true,
-			khr_android_surface: true,
-			khr_win32_surface: true,
-			mvk_ios_surface: true,
-			mvk_macos_surface: true,
-			#[cfg(debug_assertions)]
-			ext_debug_utils: true,
-			..InstanceExtensions::none()
-		};
-
-		let exts = match InstanceExtensions::supported_by_core() {
-			Ok(supported) => supported.intersection(&exts),
-			Err(_) => InstanceExtensions::none(),
-		};
-
-		#[cfg(debug_assertions)]
-		let layers = hashset! {
-			// "VK_LAYER_KHRONOS_validation".to_owned(),
-			"VK_LAYER_LUNARG_monitor".to_owned(),
-		};
-		#[cfg(not(debug_assertions))]
-		let layers = hashset! {
-			"VK_LAYER_LUNARG_monitor".to_owned(),
-		};
-
-		let instance_layers =
-			instance::layers_list().unwrap().map(|l| l.name().to_owned()).collect::<HashSet<String>>();
-		let layers = instance_layers.intersection(&layers).map(|s| s as &str);
-
-		let instance = Instance::new(Some(&app_info), &exts, layers)?;
-
-		#[cfg(debug_assertions)]
-		let debug_callback = DebugCallback::errors_and_warnings(&instance, |msg| {
-			if msg.severity.error {
-				log::error!("[{}]{}", msg.layer_prefix, msg.description);
-			} else {
-				log::warn!("[{}]{}", msg.layer_prefix, msg.description);
-			}
-		})
-		.unwrap();
-
-		let features = Features { sampler_anisotropy: true, ..Features::none() };
-		let pdevice = PhysicalDevice::enumerate(&instance)
-			.max_by_key(|pd| pd.supported_features().superset_of(&features))
-			.unwrap();
-		info!("Using device: {} ({:?})", pdevice.name(), pdevice.ty());
-
-		let features = pdevice.supported_features().intersection(&features);
-		let qfam =
-			pdevice.queue_families().find(|&q| q.supports_graphics()).expect("failed to find a graphical queue family");
-		let (device, mut queues) = Device::new(
-			pdevice,
-			&features,
-			&DeviceExtensions { khr_swapchain: true, ..DeviceExtensions::none() },
-			[(qfam, 1.0)].iter().cloned(),
-		)
-		.expect("failed to create device");
-		let queue = queues.next().unwrap();
+	) -> Result<(Arc<Self>, impl GpuFuture), ContextCreationError> {
+		Self::with_pipeline::<DeferredPipelineDef>(name, version)
+	}
 
-		let (pipeline_ctx, pipeline_ctx_future) = DeferredPipelineDef::make_context(&device, &queue);
+	/// Like `new`, but surfaces created with this context will use the pipeline `P` by default.
+	pub fn with_pipeline<P: PipelineDef + 'static>(
+		name: Option<&str>,
+		version: Option<Version>,
+	) -> Result<(Arc<Self>, impl GpuFuture), ContextCreationError> {
+		ContextBuilder::new(name, version)?.build_with_pipeline::<P>()
+	}
 
-		let (resources, resources_future) = Resources::new(queue.clone(), pipeline_ctx.layout_desc().clone());
+	/// Creates a context that can't present to surfaces, for use without a display server. See
+	/// `ContextBuilder::headless`.
+	pub fn headless(
+		name: Option<&str>,
+		version: Option<Version>,
+	) -> Result<(Arc<Self>, impl GpuFuture), ContextCreationError> {
+		ContextBuilder::headless(name, version)?.build()
+	}
 
-		Ok((
-			Arc::new(Self {
-				instance,
-				#[cfg(debug_assertions)]
-				debug_callback,
-				device,
-				queue,
-				pipeline_ctx,
-				resources,
-			}),
-			pipeline_ctx_future.join(resources_future),
-		))
+	pub fn is_headless(&self) -> bool {
+		self.headless
 	}
 
 	pub fn resources(&self) -> &Resources {
 		&self.resources
 	}
 
+	/// Starts a thread that calls `Resources::reload_changed` and `Resources::enforce_texture_budget` every `interval`,
+	/// until the context is dropped.
+	pub fn watch_resources(&self, interval: Duration) {
+		let resources = Arc::downgrade(&self.resources);
+		thread::spawn(move || loop {
+			thread::sleep(interval);
+			match resources.upgrade() {
+				Some(resources) => {
+					resources.reload_changed();
+					resources.enforce_texture_budget();
+				},
+				None => break,
+			}
+		});
+	}
+
 	pub fn device(&self) -> &Arc<Device> {
 		&self.device
 	}
@@ -151,8 +114,21 @@ impl Context {
 		&self.queue
 	}
 
-	fn pipeline_ctx(&self) -> &dyn PipelineContext {
-		self.pipeline_ctx.as_ref()
+	fn pipeline_ctx(&self) -> &Arc<dyn PipelineContext> {
+		&self.pipeline_ctx
+	}
+
+	/// Returns the context for pipeline `P`, creating it the first time it's requested.
+	fn pipeline_ctx_for<P: PipelineDef + 'static>(&self) -> Arc<dyn PipelineContext> {
+		let mut pipeline_ctxs = self.pipeline_ctxs.lock().unwrap();
+		pipeline_ctxs
+			.entry(TypeId::of::<P>())
+			.or_insert_with(|| {
+				let (pipeline_ctx, future) = P::make_context(&self.device, &self.queue);
+				future.then_signal_fence_and_flush().unwrap().wait(None).unwrap();
+				pipeline_ctx.into()
+			})
+			.clone()
 	}
 }
 
@@ -1,26 +1,38 @@
-use crate::{mesh_data::MeshData, mesh_group::MeshGroup, texture::Texture, transform::Transform, Context};
+use crate::{
+	field_data::FieldData, mesh_data::MeshData, mesh_group::MeshGroup, resources::Model, texture::Texture,
+	transform::Transform, Context,
+};
 use array_init::array_init;
+use cgmath::{prelude::*, Vector4};
 use log::trace;
 use std::{
 	ops::Range,
 	sync::{
 		atomic::{AtomicUsize, Ordering},
-		Arc, RwLock,
+		Arc, RwLock, RwLockReadGuard,
 	},
 };
 use vulkano::{
-	VulkanObject,
 	descriptor::{descriptor_set::PersistentDescriptorSet, DescriptorSet, PipelineLayoutAbstract},
 	sampler::Sampler,
 };
 
 const LAYERS: usize = 7;
+const LIGHTMAP_FLAT_LAYER: usize = 3;
+const LIGHTMAP_ANGLE_LAYERS: Range<usize> = 4..7;
+
+// must match LIGHTMAP_* in lightmap.glsl
+const LIGHTMAP_NONE: u32 = 0;
+const LIGHTMAP_FLAT: u32 = 1;
+const LIGHTMAP_DIRECTIONAL: u32 = 2;
 static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
 
 pub struct Mesh {
 	id: usize,
 	mesh_group: Arc<MeshGroup>,
 	inner: Arc<RwLock<MeshInner>>,
+	/// Keeps the model this mesh was loaded from cached.
+	_model: Option<Arc<Model>>,
 }
 impl Mesh {
 	pub fn new(ctx: &Context, mesh_group: Arc<MeshGroup>) -> Self {
@@ -29,7 +41,7 @@ impl Mesh {
 		let layout_desc = ctx.pipeline_ctx().layout_desc().clone();
 		let resources = ctx.resources();
 		let sampler = resources.sampler().clone();
-		Self::new_inner(mesh_group, layout_desc, resources.white_pixel(), sampler)
+		Self::new_inner(mesh_group, layout_desc, resources.white_pixel(), sampler, None)
 	}
 
 	pub(crate) fn new_inner(
@@ -37,22 +49,29 @@ impl Mesh {
 		layout_desc: Arc<dyn PipelineLayoutAbstract + Send + Sync>,
 		white_pixel: &Arc<dyn Texture + Send + Sync>,
 		sampler: Arc<Sampler>,
+		model: Option<Arc<Model>>,
 	) -> Self {
-		let textures = array_init(|_| white_pixel.clone() as _);
+		let textures: [Arc<dyn Texture + Send + Sync>; LAYERS] = array_init(|_| white_pixel.clone() as _);
+		let texture_versions = array_init(|i| textures[i].version());
 		let desc = make_desc_set(layout_desc.clone(), &textures, sampler.clone());
 		let inner = Arc::new(RwLock::new(MeshInner {
 			layout_desc,
 			sampler,
 			mesh_data: None,
+			field_data: None,
 			range: 0..0,
 			transform: Transform::default(),
 			textures,
+			textures_set: [false; LAYERS],
+			texture_versions,
 			desc,
+			blend_mode: BlendMode::default(),
+			generation: 0,
 		}));
 
 		let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
 		mesh_group.meshes().lock().unwrap().insert(id, inner.clone());
-		Self { id, mesh_group, inner }
+		Self { id, mesh_group, inner, _model: model }
 	}
 
 	pub fn inner(&self) -> &Arc<RwLock<MeshInner>> {
@@ -69,10 +88,17 @@ pub struct MeshInner {
 	layout_desc: Arc<dyn PipelineLayoutAbstract + Send + Sync>,
 	sampler: Arc<Sampler>,
 	mesh_data: Option<Arc<MeshData>>,
+	field_data: Option<Arc<FieldData>>,
 	range: Range<usize>,
 	transform: Transform,
 	textures: [Arc<dyn Texture + Send + Sync + 'static>; LAYERS],
+	textures_set: [bool; LAYERS],
+	/// The versions of the textures when the descriptor set was made.
+	texture_versions: [u64; LAYERS],
 	desc: Arc<dyn DescriptorSet + Send + Sync>,
+	blend_mode: BlendMode,
+	/// Incremented whenever anything that indirect draws store on the GPU changes.
+	generation: u64,
 }
 impl MeshInner {
 	pub fn transform(&self) -> &Transform {
@@ -81,6 +107,7 @@ impl MeshInner {
 
 	pub fn set_transform(&mut self, transform: Transform) {
 		self.transform = transform;
+		self.generation += 1;
 	}
 
 	pub fn mesh_data(&self) -> Option<&Arc<MeshData>> {
@@ -89,6 +116,16 @@ impl MeshInner {
 
 	pub fn set_mesh_data(&mut self, mesh_data: Option<Arc<MeshData>>) {
 		self.mesh_data = mesh_data;
+		self.generation += 1;
+	}
+
+	/// Field data is drawn instead of mesh data when both are set. Pipelines that can't ray march fields skip the mesh.
+	pub fn field_data(&self) -> Option<&Arc<FieldData>> {
+		self.field_data.as_ref()
+	}
+
+	pub fn set_field_data(&mut self, field_data: Option<Arc<FieldData>>) {
+		self.field_data = field_data;
 	}
 
 	pub fn range(&self) -> Range<usize> {
@@ -97,6 +134,7 @@ impl MeshInner {
 
 	pub fn set_range(&mut self, range: Range<usize>) {
 		self.range = range;
+		self.generation += 1;
 	}
 
 	pub fn desc(&self) -> &Arc<dyn DescriptorSet + Send + Sync> {
@@ -105,19 +143,117 @@ impl MeshInner {
 
 	pub fn set_tex(&mut self, tex_i: usize, tex: Arc<dyn Texture + Send + Sync>) {
 		self.textures[tex_i] = tex;
-		self.desc = make_desc_set(self.layout_desc.clone(), &self.textures, self.sampler.clone());
+		self.textures_set[tex_i] = true;
+		self.generation += 1;
+		self.update_desc();
+	}
+
+	pub fn blend_mode(&self) -> BlendMode {
+		self.blend_mode
+	}
+
+	pub fn set_blend_mode(&mut self, blend_mode: BlendMode) {
+		self.blend_mode = blend_mode;
+		self.generation += 1;
+	}
+
+	/// Directional lightmaps are used if all three angle layers have been set, otherwise the flat lightmap is used if
+	/// it has been set. Meshes without lightmaps are lit by the skybox instead.
+	pub(crate) fn lightmap_mode(&self) -> u32 {
+		if self.textures_set[LIGHTMAP_ANGLE_LAYERS].iter().all(|&set| set) {
+			LIGHTMAP_DIRECTIONAL
+		} else if self.textures_set[LIGHTMAP_FLAT_LAYER] {
+			LIGHTMAP_FLAT
+		} else {
+			LIGHTMAP_NONE
+		}
+	}
+
+	/// Meshes with equal keys only differ in their transforms, so they can be drawn as instances of each other. Meshes
+	/// without mesh data have no key.
+	pub(crate) fn instance_key(&self) -> Option<InstanceKey> {
+		let mesh_data = self.mesh_data.as_ref()?;
+		Some(InstanceKey {
+			mesh_data: &**mesh_data as *const MeshData as usize,
+			range: (self.range.start, self.range.end),
+			textures: array_init(|i| &*self.textures[i] as *const _ as *const () as usize),
+			lightmap: self.lightmap_mode(),
+			blend_mode: self.blend_mode,
+		})
+	}
+
+	pub(crate) fn generation(&self) -> u64 {
+		self.generation
 	}
 
-	pub(crate) fn refresh(&mut self) {
-		for i in 0..LAYERS {
-			let lhs_id = self.textures[i].image().inner().internal_object();
-			let rhs_id = self.desc.image(i).unwrap().0.inner().internal_object();
-			if lhs_id != rhs_id {
-				self.desc = make_desc_set(self.layout_desc.clone(), &self.textures, self.sampler.clone());
-				break;
+	/// Read locks a mesh for drawing. Its textures are hinted with a priority based on their distance to the camera,
+	/// and if any of them changed since the descriptor set was made, the mesh is briefly write locked to remake it.
+	pub(crate) fn read_refreshed(mesh: &RwLock<MeshInner>, cam_pos: Vector4<f32>) -> RwLockReadGuard<MeshInner> {
+		{
+			let inner = mesh.read().unwrap();
+			let priority = 1.0 / (1.0 + (inner.transform.pos - cam_pos).truncate().magnitude());
+			for texture in &inner.textures {
+				texture.hint_priority(priority);
 			}
+			if !inner.textures_changed() {
+				return inner;
+			}
+		}
+
+		mesh.write().unwrap().update_desc();
+		mesh.read().unwrap()
+	}
+
+	fn textures_changed(&self) -> bool {
+		self.textures.iter().zip(&self.texture_versions).any(|(texture, &version)| texture.version() != version)
+	}
+
+	fn update_desc(&mut self) {
+		// read the versions first, so a texture that changes while the set is made is noticed next time
+		self.texture_versions = array_init(|i| self.textures[i].version());
+		self.desc = make_desc_set(self.layout_desc.clone(), &self.textures, self.sampler.clone());
+	}
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct InstanceKey {
+	mesh_data: usize,
+	range: (usize, usize),
+	textures: [usize; LAYERS],
+	lightmap: u32,
+	blend_mode: BlendMode,
+}
+
+/// How the alpha channel of a mesh's color texture is used.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BlendMode {
+	/// Alpha is ignored.
+	Opaque,
+	/// Fragments with an alpha below 0.125 are discarded. This is the default.
+	AlphaTested,
+	/// Drawn over everything else after lighting, from back to front, and lit by up to 32 direct lights.
+	AlphaBlended,
+	/// Like `AlphaBlended`, but its color is multiplied by its alpha and added to what's behind it.
+	Additive,
+}
+impl BlendMode {
+	/// Transparent meshes are drawn in a separate pass after lighting.
+	pub(crate) fn is_transparent(self) -> bool {
+		match self {
+			BlendMode::Opaque | BlendMode::AlphaTested => false,
+			BlendMode::AlphaBlended | BlendMode::Additive => true,
 		}
 	}
+
+	/// Whether the geometry shaders discard fragments with a low alpha.
+	pub(crate) fn alpha_test(self) -> u32 {
+		(self == BlendMode::AlphaTested) as u32
+	}
+}
+impl Default for BlendMode {
+	fn default() -> Self {
+		BlendMode::AlphaTested
+	}
 }
 
 fn make_desc_set<L>(
@@ -130,19 +266,19 @@ where
 {
 	Arc::new(
 		PersistentDescriptorSet::start(layout, 0)
-			.add_sampled_image(image_views[0].image().clone(), sampler.clone())
+			.add_sampled_image(image_views[0].image(), sampler.clone())
 			.unwrap()
-			.add_sampled_image(image_views[1].image().clone(), sampler.clone())
+			.add_sampled_image(image_views[1].image(), sampler.clone())
 			.unwrap()
-			.add_sampled_image(image_views[2].image().clone(), sampler.clone())
+			.add_sampled_image(image_views[2].image(), sampler.clone())
 			.unwrap()
-			.add_sampled_image(image_views[3].image().clone(), sampler.clone())
+			.add_sampled_image(image_views[3].image(), sampler.clone())
 			.unwrap()
-			.add_sampled_image(image_views[4].image().clone(), sampler.clone())
+			.add_sampled_image(image_views[4].image(), sampler.clone())
 			.unwrap()
-			.add_sampled_image(image_views[5].image().clone(), sampler.clone())
+			.add_sampled_image(image_views[5].image(), sampler.clone())
 			.unwrap()
-			.add_sampled_image(image_views[6].image().clone(), sampler.clone())
+			.add_sampled_image(image_views[6].image(), sampler.clone())
 			.unwrap()
 			.build()
 			.unwrap(),
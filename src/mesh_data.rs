@@ -83,6 +83,11 @@ impl MeshData {
 		&self.indices
 	}
 
+	/// The size of the vertex and index buffers in bytes.
+	pub fn size(&self) -> usize {
+		self.vertices.size() + self.indices.size()
+	}
+
 	pub fn topology(&self) -> PrimitiveTopology {
 		self.topology
 	}
@@ -100,6 +105,13 @@ impl IndexBuffer {
 			Self::U32(buf) => buf.len(),
 		}
 	}
+
+	pub fn size(&self) -> usize {
+		match self {
+			Self::U16(buf) => buf.size(),
+			Self::U32(buf) => buf.size(),
+		}
+	}
 }
 
 #[derive(Default, Debug, Clone, Copy)]
@@ -1,57 +1,197 @@
-use crate::{mesh::MeshInner, texture::Texture, Context};
+mod indirect;
+
+pub(crate) use self::indirect::{IndirectDraws, IndirectGroup};
+
+use crate::{
+	direct_light::DirectLight,
+	mesh::MeshInner,
+	pipelines::irradiance::{Irradiance, IrradianceFuture, Sky},
+	texture::Texture,
+	Context,
+};
+use log::warn;
 use std::{
 	collections::HashMap,
 	sync::{Arc, Mutex, RwLock},
+	time::Duration,
 };
 use vulkano::{
+	buffer::DeviceLocalBuffer,
 	descriptor::{descriptor_set::PersistentDescriptorSet, DescriptorSet, PipelineLayoutAbstract},
+	device::Device,
+	image::Dimensions,
 	sampler::Sampler,
+	VulkanObject,
 };
 
 pub struct MeshGroup {
 	meshes: Mutex<HashMap<usize, Arc<RwLock<MeshInner>>>>,
-	skybox: Mutex<Arc<dyn DescriptorSet + Send + Sync>>,
-	swap_layout_desc: Arc<dyn PipelineLayoutAbstract + Send + Sync>,
-	white_pixel: Arc<dyn Texture + Send + Sync>,
-	sampler: Arc<Sampler>,
+	lights: Mutex<Vec<DirectLight>>,
+	skybox: Mutex<Skybox>,
+	skybox_resources: SkyboxResources,
+	irradiance_futures: Mutex<Vec<IrradianceFuture>>,
+	device: Arc<Device>,
+	indirect: Mutex<Option<IndirectDraws>>,
 }
 impl MeshGroup {
 	pub fn new(ctx: &Context) -> Arc<Self> {
 		let resources = ctx.resources();
-		let swap_layout_desc = ctx.pipeline_ctx().swap_layout_desc().clone();
-		let white_pixel = resources.white_pixel().clone();
-		let sampler = resources.sampler().clone();
-		let skybox = Mutex::new(make_desc_set(swap_layout_desc.clone(), &white_pixel, sampler.clone()));
-		Arc::new(Self { meshes: Mutex::default(), skybox, swap_layout_desc, white_pixel, sampler })
+		let skybox_resources = SkyboxResources {
+			swap_layout_desc: ctx.pipeline_ctx().swap_layout_desc().clone(),
+			white_pixel: resources.white_pixel().clone(),
+			white_cube: resources.white_cube().clone(),
+			sampler: resources.sampler().clone(),
+			irradiance: resources.irradiance().clone(),
+		};
+		let (skybox, irradiance_future) = skybox_resources.make_skybox(None, None);
+		Arc::new(Self {
+			meshes: Mutex::default(),
+			lights: Mutex::default(),
+			skybox: Mutex::new(skybox),
+			skybox_resources,
+			irradiance_futures: Mutex::new(irradiance_future.into_iter().collect()),
+			device: ctx.device().clone(),
+			indirect: Mutex::default(),
+		})
 	}
 
+	/// Sets the texture drawn behind everything else. It can be either an equirectangular panorama or a cubemap.
+	/// Without one, the sky is white and meshes get flat ambient light.
 	pub fn set_skybox(&self, skybox: Option<&Arc<dyn Texture + Send + Sync>>) {
-		*self.skybox.lock().unwrap() =
-			make_desc_set(self.swap_layout_desc.clone(), skybox.unwrap_or(&self.white_pixel), self.sampler.clone());
+		let (skybox, irradiance_future) = self.skybox_resources.make_skybox(skybox.cloned(), None);
+		*self.skybox.lock().unwrap() = skybox;
+		self.irradiance_futures.lock().unwrap().extend(irradiance_future);
+	}
+
+	/// Dynamic lights, drawn on top of the skybox and lightmap lighting. See `DirectLight` for how many can overlap.
+	pub fn lights(&self) -> &Mutex<Vec<DirectLight>> {
+		&self.lights
+	}
+
+	/// Keeps mesh transforms and draw commands in GPU buffers that are only written when a mesh changes, and draws each
+	/// mesh with an indirect draw instead of push constants. Only the deferred pipeline supports this, the forward
+	/// pipeline ignores it.
+	///
+	/// Each draw finds its transform through its first instance, so this needs the `draw_indirect_first_instance`
+	/// feature. Returns whether indirect drawing is enabled, which is always false without it.
+	pub fn set_indirect(&self, enabled: bool) -> bool {
+		let enabled = if enabled && !self.device.enabled_features().draw_indirect_first_instance {
+			warn!("indirect drawing isn't available, because draw_indirect_first_instance isn't enabled");
+			false
+		} else {
+			enabled
+		};
+
+		let mut indirect = self.indirect.lock().unwrap();
+		if enabled != indirect.is_some() {
+			*indirect = if enabled { Some(IndirectDraws::new(self.device.clone())) } else { None };
+		}
+		enabled
+	}
+
+	pub(crate) fn indirect(&self) -> &Mutex<Option<IndirectDraws>> {
+		&self.indirect
 	}
 
 	pub(crate) fn meshes(&self) -> &Mutex<HashMap<usize, Arc<RwLock<MeshInner>>>> {
 		&self.meshes
 	}
 
-	pub(crate) fn skybox(&self) -> &Mutex<Arc<dyn DescriptorSet + Send + Sync>> {
-		&self.skybox
+	/// Returns the skybox descriptor set, which also holds the irradiance used for ambient light.
+	pub(crate) fn skybox(&self) -> Arc<dyn DescriptorSet + Send + Sync> {
+		let mut skybox = self.skybox.lock().unwrap();
+
+		// The texture may have finished loading since the descriptor set was made.
+		let binding = if skybox.cubemap { 1 } else { 0 };
+		let lhs_id = skybox.tex.image().inner().internal_object();
+		let rhs_id = skybox.desc.image(binding).unwrap().0.inner().internal_object();
+		if lhs_id != rhs_id {
+			// more mip levels streaming in barely changes the irradiance, so it's only recomputed once the texture
+			// replaces a placeholder, or when it's reloaded or evicted
+			let reuse = !skybox.placeholder && skybox.tex.generation() == skybox.generation;
+			let irradiance = if reuse { Some(skybox.irradiance.clone()) } else { None };
+			let tex = Some(skybox.tex.clone());
+			let (new_skybox, irradiance_future) = self.skybox_resources.make_skybox(tex, irradiance);
+			*skybox = new_skybox;
+			self.irradiance_futures.lock().unwrap().extend(irradiance_future);
+		}
+
+		skybox.desc.clone()
+	}
+
+	/// Irradiance computations that may still be running. Frames that draw this mesh group must be joined with them,
+	/// because the skybox descriptor set reads their results.
+	pub(crate) fn irradiance_futures(&self) -> Vec<IrradianceFuture> {
+		let mut futures = self.irradiance_futures.lock().unwrap();
+		// waiting on a finished computation releases its lock on the buffer, so later frames don't need it any more
+		futures.retain(|future| future.wait(Some(Duration::from_secs(0))).is_err());
+		futures.clone()
 	}
 }
 
-fn make_desc_set<L>(
-	layout: L,
-	image_view: &Arc<dyn Texture + Send + Sync>,
+struct Skybox {
+	tex: Arc<dyn Texture + Send + Sync>,
+	desc: Arc<dyn DescriptorSet + Send + Sync>,
+	cubemap: bool,
+	irradiance: Arc<DeviceLocalBuffer<Sky>>,
+	/// Whether `tex` was still showing `white_pixel` or `white_cube` when the irradiance was computed.
+	placeholder: bool,
+	/// The generation of `tex` when the irradiance was computed.
+	generation: u64,
+}
+
+struct SkyboxResources {
+	swap_layout_desc: Arc<dyn PipelineLayoutAbstract + Send + Sync>,
+	white_pixel: Arc<dyn Texture + Send + Sync>,
+	white_cube: Arc<dyn Texture + Send + Sync>,
 	sampler: Arc<Sampler>,
-) -> Arc<dyn DescriptorSet + Send + Sync>
-where
-	L: PipelineLayoutAbstract + Send + Sync + 'static,
-{
-	Arc::new(
-		PersistentDescriptorSet::start(layout, 1)
-			.add_sampled_image(image_view.image().clone(), sampler.clone())
-			.unwrap()
-			.build()
-			.unwrap(),
-	)
+	irradiance: Arc<Irradiance>,
+}
+impl SkyboxResources {
+	/// Reuses `irradiance` if it's given, otherwise starts computing it and returns the future of the computation.
+	/// Without `tex`, the sky is `white_pixel`.
+	fn make_skybox(
+		&self,
+		tex: Option<Arc<dyn Texture + Send + Sync>>,
+		irradiance: Option<Arc<DeviceLocalBuffer<Sky>>>,
+	) -> (Skybox, Option<IrradianceFuture>) {
+		let unset = tex.is_none();
+		let tex = tex.unwrap_or_else(|| self.white_pixel.clone());
+		// read before the image, so a reload in between only causes another recomputation
+		let generation = tex.generation();
+		let image = tex.image();
+		let cubemap = match image.dimensions() {
+			Dimensions::Cubemap { .. } => true,
+			_ => false,
+		};
+		let is_image = |other: &Arc<dyn Texture + Send + Sync>| {
+			image.inner().internal_object() == other.image().inner().internal_object()
+		};
+		let placeholder = is_image(&self.white_pixel) || is_image(&self.white_cube);
+		let (equirect, cube) = if cubemap { (&self.white_pixel, &tex) } else { (&tex, &self.white_cube) };
+		let (equirect, cube) = (equirect.image(), cube.image());
+
+		let (irradiance, future) = match irradiance {
+			Some(irradiance) => (irradiance, None),
+			None => {
+				let (irradiance, future) =
+					self.irradiance.compute(equirect.clone(), cube.clone(), cubemap, unset, self.sampler.clone());
+				(irradiance, Some(future))
+			},
+		};
+
+		let desc = Arc::new(
+			PersistentDescriptorSet::start(self.swap_layout_desc.clone(), 1)
+				.add_sampled_image(equirect, self.sampler.clone())
+				.unwrap()
+				.add_sampled_image(cube, self.sampler.clone())
+				.unwrap()
+				.add_buffer(irradiance.clone())
+				.unwrap()
+				.build()
+				.unwrap(),
+		);
+
+		(Skybox { tex, desc, cubemap, irradiance, placeholder, generation }, future)
+	}
 }
@@ -0,0 +1,255 @@
+use crate::{frame_stats::triangles, mesh::MeshInner, mesh_data::MeshData};
+use std::{
+	collections::HashMap,
+	sync::{Arc, RwLock},
+};
+use vulkano::{
+	buffer::{BufferSlice, BufferUsage, CpuAccessibleBuffer, DeviceLocalBuffer, TypedBufferAccess},
+	command_buffer::{AutoCommandBufferBuilder, DrawIndexedIndirectCommand},
+	descriptor::DescriptorSet,
+	device::Device,
+};
+
+const INITIAL_CAPACITY: usize = 256;
+
+/// The per-mesh data read by the indirect geometry pass. Must match `Draw` in geom_indirect.glslv.
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+pub(crate) struct IndirectDraw {
+	mesh_pos: [f32; 4],
+	mesh_rot: [f32; 4],
+	/// The lightmap mode and whether to alpha test, padded to 16 bytes.
+	flags: [u32; 4],
+}
+
+pub(crate) type IndirectCommands =
+	BufferSlice<[DrawIndexedIndirectCommand], Arc<DeviceLocalBuffer<[DrawIndexedIndirectCommand]>>>;
+
+/// The transforms and draw commands of every mesh in a group, kept in device-local buffers that are only written when a
+/// mesh changes. Each mesh owns a slot in the transform buffer. Its command draws a single instance whose index is the
+/// slot, which the vertex shader uses to find its transform.
+///
+/// Commands are ordered so that meshes sharing mesh data and a descriptor set are adjacent, and each such group is
+/// drawn with a single multi-draw. The command buffer is rewritten whenever a command or a group changes.
+///
+/// Both buffers are also storage buffers, so a compute pass could cull meshes by zeroing their instance counts.
+pub(crate) struct IndirectDraws {
+	device: Arc<Device>,
+	draws: Arc<DeviceLocalBuffer<[IndirectDraw]>>,
+	commands: Arc<DeviceLocalBuffer<[DrawIndexedIndirectCommand]>>,
+	capacity: usize,
+	/// Keyed by mesh id.
+	slots: HashMap<usize, Slot>,
+	free: Vec<usize>,
+	groups: Vec<IndirectGroup>,
+}
+impl IndirectDraws {
+	pub(crate) fn new(device: Arc<Device>) -> Self {
+		let (draws, commands) = create_buffers(&device, INITIAL_CAPACITY);
+		Self {
+			device,
+			draws,
+			commands,
+			capacity: INITIAL_CAPACITY,
+			slots: HashMap::new(),
+			free: (0..INITIAL_CAPACITY).rev().collect(),
+			groups: vec![],
+		}
+	}
+
+	/// Records writes for meshes that were added or changed since the last update, and frees the slots of removed
+	/// meshes. This has to happen outside of a render pass.
+	///
+	/// Only opaque meshes with mesh data and no field data are drawn indirectly, the same ones the deferred pipeline
+	/// would draw with push constants otherwise.
+	pub(crate) fn update(
+		&mut self,
+		mut command_buffer: AutoCommandBufferBuilder,
+		meshes: &HashMap<usize, Arc<RwLock<MeshInner>>>,
+	) -> AutoCommandBufferBuilder {
+		let free = &mut self.free;
+		let mut regroup = false;
+		self.slots.retain(|id, slot| {
+			let keep = meshes.contains_key(id);
+			if !keep {
+				free.push(slot.index);
+				regroup = true;
+			}
+			keep
+		});
+
+		if meshes.len() > self.capacity {
+			let capacity = meshes.len().next_power_of_two();
+			let (draws, commands) = create_buffers(&self.device, capacity);
+			self.draws = draws;
+			self.commands = commands;
+			self.free.extend((self.capacity..capacity).rev());
+			self.capacity = capacity;
+			// the new buffers are empty, so every slot has to be written again
+			for slot in self.slots.values_mut() {
+				slot.generation = None;
+			}
+		}
+
+		for (id, mesh) in meshes {
+			let mesh = mesh.read().unwrap();
+			let free = &mut self.free;
+			let slot = self.slots.entry(*id).or_insert_with(|| Slot {
+				index: free.pop().unwrap(),
+				generation: None,
+				command: DrawIndexedIndirectCommand {
+					index_count: 0,
+					instance_count: 0,
+					first_index: 0,
+					vertex_offset: 0,
+					first_instance: 0,
+				},
+				group: None,
+			});
+
+			// descriptor sets are remade without changing the generation when a texture is reloaded
+			let group = match mesh.mesh_data() {
+				Some(mesh_data) if mesh.field_data().is_none() && !mesh.blend_mode().is_transparent() => {
+					Some((mesh_data.clone(), mesh.desc().clone()))
+				},
+				_ => None,
+			};
+			if !same_group(&slot.group, &group) {
+				slot.group = group;
+				regroup = true;
+			}
+
+			if slot.generation == Some(mesh.generation()) {
+				continue;
+			}
+			slot.generation = Some(mesh.generation());
+			regroup = true;
+
+			let transform = mesh.transform();
+			let draw = IndirectDraw {
+				mesh_pos: transform.pos.into(),
+				mesh_rot: transform.rot.into(),
+				flags: [mesh.lightmap_mode(), mesh.blend_mode().alpha_test(), 0, 0],
+			};
+			let range = mesh.range();
+			slot.command = DrawIndexedIndirectCommand {
+				index_count: range.len() as u32,
+				instance_count: 1,
+				first_index: range.start as u32,
+				vertex_offset: 0,
+				first_instance: slot.index as u32,
+			};
+
+			command_buffer = command_buffer
+				.update_buffer(self.draws.clone().into_buffer_slice().index(slot.index).unwrap(), draw)
+				.unwrap();
+		}
+
+		if regroup {
+			command_buffer = self.regroup(command_buffer);
+		}
+		command_buffer
+	}
+
+	/// Rewrites every command, ordered by topology, mesh data and descriptor set, and splits them into groups.
+	fn regroup(&mut self, command_buffer: AutoCommandBufferBuilder) -> AutoCommandBufferBuilder {
+		let mut slots = self
+			.slots
+			.values()
+			.filter_map(|slot| slot.group.as_ref().map(|(mesh_data, desc)| (slot, mesh_data, desc)))
+			.collect::<Vec<_>>();
+		slots.sort_by_key(|&(slot, mesh_data, desc)| {
+			(
+				mesh_data.topology() as u32,
+				&**mesh_data as *const MeshData as usize,
+				&**desc as *const _ as *const () as usize,
+				slot.index,
+			)
+		});
+
+		self.groups.clear();
+		if slots.is_empty() {
+			return command_buffer;
+		}
+
+		let mut start = 0;
+		for end in 1..=slots.len() {
+			let (_, mesh_data, desc) = slots[start];
+			let split = end == slots.len() || {
+				let (_, next_mesh_data, next_desc) = slots[end];
+				!Arc::ptr_eq(mesh_data, next_mesh_data) || !Arc::ptr_eq(desc, next_desc)
+			};
+			if !split {
+				continue;
+			}
+
+			let topology = mesh_data.topology();
+			let triangles =
+				slots[start..end].iter().map(|(slot, ..)| triangles(topology, slot.command.index_count as usize)).sum();
+			self.groups.push(IndirectGroup {
+				mesh_data: mesh_data.clone(),
+				desc: desc.clone(),
+				commands: self.commands.clone().into_buffer_slice().slice(start..end).unwrap(),
+				triangles,
+			});
+			start = end;
+		}
+
+		let commands = CpuAccessibleBuffer::from_iter(
+			self.device.clone(),
+			BufferUsage::transfer_source(),
+			slots.iter().map(|(slot, ..)| slot.command),
+		)
+		.unwrap();
+		command_buffer.copy_buffer(commands, self.commands.clone()).unwrap()
+	}
+
+	pub(crate) fn draws(&self) -> &Arc<DeviceLocalBuffer<[IndirectDraw]>> {
+		&self.draws
+	}
+
+	/// Meshes that can be drawn together, as of the last update.
+	pub(crate) fn groups(&self) -> &[IndirectGroup] {
+		&self.groups
+	}
+}
+
+/// Meshes with the same mesh data and descriptor set, whose commands are adjacent.
+#[derive(Clone)]
+pub(crate) struct IndirectGroup {
+	pub(crate) mesh_data: Arc<MeshData>,
+	pub(crate) desc: Arc<dyn DescriptorSet + Send + Sync>,
+	pub(crate) commands: IndirectCommands,
+	pub(crate) triangles: u64,
+}
+
+struct Slot {
+	index: usize,
+	/// The generation of the mesh when the slot was last written.
+	generation: Option<u64>,
+	command: DrawIndexedIndirectCommand,
+	/// What the mesh is grouped by, or `None` if it isn't drawn indirectly.
+	group: Option<(Arc<MeshData>, Arc<dyn DescriptorSet + Send + Sync>)>,
+}
+
+fn same_group(
+	a: &Option<(Arc<MeshData>, Arc<dyn DescriptorSet + Send + Sync>)>,
+	b: &Option<(Arc<MeshData>, Arc<dyn DescriptorSet + Send + Sync>)>,
+) -> bool {
+	match (a, b) {
+		(Some((a_data, a_desc)), Some((b_data, b_desc))) => Arc::ptr_eq(a_data, b_data) && Arc::ptr_eq(a_desc, b_desc),
+		(None, None) => true,
+		_ => false,
+	}
+}
+
+fn create_buffers(
+	device: &Arc<Device>,
+	capacity: usize,
+) -> (Arc<DeviceLocalBuffer<[IndirectDraw]>>, Arc<DeviceLocalBuffer<[DrawIndexedIndirectCommand]>>) {
+	let usage =
+		BufferUsage { storage_buffer: true, indirect_buffer: true, transfer_destination: true, ..BufferUsage::none() };
+	let draws = DeviceLocalBuffer::array(device.clone(), capacity, usage, device.active_queue_families()).unwrap();
+	let commands = DeviceLocalBuffer::array(device.clone(), capacity, usage, device.active_queue_families()).unwrap();
+	(draws, commands)
+}
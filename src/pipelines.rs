@@ -1,6 +1,16 @@
 pub(crate) mod deferred;
+pub(crate) mod forward;
+mod fxaa;
+pub(crate) mod irradiance;
+mod ssao;
 
-use crate::{camera::Camera, direct_light::DirectLight};
+pub use self::{deferred::DeferredPipelineDef, forward::ForwardPipelineDef};
+
+use crate::{
+	camera::Camera, direct_light::DirectLight, frame_stats::DrawCounts, gpu_timer::FrameQueries, mesh::BlendMode,
+	transform::Transform,
+};
+use log::warn;
 use std::sync::Arc;
 use vulkano::{
 	command_buffer::AutoCommandBuffer,
@@ -8,14 +18,26 @@ use vulkano::{
 	device::{Device, Queue},
 	image::ImageViewAccess,
 	instance::QueueFamily,
+	pipeline::{
+		blend::{AttachmentBlend, BlendFactor, BlendOp},
+		input_assembly::PrimitiveTopology,
+		GraphicsPipelineAbstract,
+	},
 	sync::GpuFuture,
 };
 
-pub(crate) trait PipelineDef {
+// must match MAX_LIGHTS in forward.glslf
+const MAX_LIGHTS: usize = 32;
+
+/// A rendering technique that can be selected when creating a `Context` or `Surface`.
+///
+/// Every pipeline must use the same descriptor set layout for mesh materials (set 0 of `layout_desc`) and for the
+/// skybox (set 1 of `swap_layout_desc`), so meshes and mesh groups can be drawn by any pipeline.
+pub trait PipelineDef {
 	fn make_context(device: &Arc<Device>, queue: &Arc<Queue>) -> (Box<dyn PipelineContext>, Box<dyn GpuFuture>);
 }
 
-pub(crate) trait PipelineContext {
+pub trait PipelineContext {
 	fn make_pipeline(
 		&self,
 		images: Vec<Arc<dyn ImageViewAccess + Send + Sync>>,
@@ -25,7 +47,119 @@ pub(crate) trait PipelineContext {
 	fn swap_layout_desc(&self) -> &Arc<dyn PipelineLayoutAbstract + Send + Sync>;
 }
 
-pub(crate) trait Pipeline {
-	fn draw(&self, image_num: usize, qfam: QueueFamily, cam: &Camera, lights: &[DirectLight]) -> AutoCommandBuffer;
+pub trait Pipeline {
+	/// Writes `Stamp::Geometry` and `Stamp::Lighting` to `queries`, if given.
+	fn draw(
+		&self,
+		image_num: usize,
+		qfam: QueueFamily,
+		cam: &Camera,
+		lights: &[DirectLight],
+		queries: Option<&FrameQueries>,
+	) -> (AutoCommandBuffer, DrawCounts);
 	fn resize(&mut self, images: Vec<Arc<dyn ImageViewAccess + Send + Sync>>, dimensions: [u32; 2]);
+	fn set_anti_aliasing(&mut self, aa: AntiAliasing);
+	fn set_ambient_occlusion(&mut self, ao: AmbientOcclusion);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AntiAliasing {
+	None,
+	/// Fast approximate anti-aliasing, applied as a post pass after the scene has been tonemapped.
+	Fxaa,
+}
+impl Default for AntiAliasing {
+	fn default() -> Self {
+		AntiAliasing::None
+	}
+}
+
+/// Screen-space ambient occlusion, which darkens ambient light in creases and corners on top of each mesh's baked
+/// `ambient_occlusion` texture. Only light from the sky is darkened, since lightmaps already include occlusion. It's
+/// computed from the G-buffer, so the forward pipeline ignores it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AmbientOcclusion {
+	/// Skips the SSAO passes. The deferred pipeline still writes the sky's ambient light to a separate 32-bit per pixel
+	/// target and clears and stores it every frame, so this saves less bandwidth than not having SSAO at all.
+	None,
+	/// 8 samples per pixel, with a 3x3 blur.
+	Low,
+	/// 16 samples per pixel, with a 5x5 blur.
+	Medium,
+	/// 32 samples per pixel, with a 7x7 blur.
+	High,
+}
+impl Default for AmbientOcclusion {
+	fn default() -> Self {
+		AmbientOcclusion::None
+	}
+}
+
+#[derive(Default, Debug, Clone, Copy)]
+#[repr(C)]
+pub(crate) struct Vert2D {
+	pub(crate) pos: [f32; 2],
+	pub(crate) texc: [f32; 2],
+}
+vulkano::impl_vertex!(Vert2D, pos, texc);
+
+#[derive(Default, Debug, Clone, Copy)]
+#[repr(C)]
+pub(crate) struct Vert3D {
+	pub(crate) pos: [f32; 3],
+}
+vulkano::impl_vertex!(Vert3D, pos);
+
+/// The per-instance vertex input of instanced mesh draws.
+#[derive(Default, Debug, Clone, Copy)]
+#[repr(C)]
+pub(crate) struct InstanceTransform {
+	pub(crate) mesh_pos: [f32; 4],
+	pub(crate) mesh_rot: [f32; 4],
+}
+vulkano::impl_vertex!(InstanceTransform, mesh_pos, mesh_rot);
+impl From<&Transform> for InstanceTransform {
+	fn from(transform: &Transform) -> Self {
+		Self { mesh_pos: transform.pos.into(), mesh_rot: transform.rot.into() }
+	}
+}
+
+/// Picks the pipeline for triangle lists or strips. Meshes with any other topology can't be drawn, so this warns and
+/// returns `None` for them, and they're skipped.
+fn topology_pipeline(
+	topology: PrimitiveTopology,
+	soup: &Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	strip: &Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+) -> Option<Arc<dyn GraphicsPipelineAbstract + Send + Sync>> {
+	match topology {
+		PrimitiveTopology::TriangleList => Some(soup.clone()),
+		PrimitiveTopology::TriangleStrip => Some(strip.clone()),
+		topology => {
+			warn!("skipping a mesh with unsupported topology {:?}", topology);
+			None
+		},
+	}
+}
+
+/// The blend state of pipelines that draw transparent meshes into a light buffer. Alpha blending accumulates coverage
+/// in the light buffer's alpha channel, so the swap pass can show the sky through it.
+fn transparent_blend(blend_mode: BlendMode) -> AttachmentBlend {
+	let (color_destination, alpha_source, alpha_destination) = match blend_mode {
+		BlendMode::AlphaBlended => (BlendFactor::OneMinusSrcAlpha, BlendFactor::One, BlendFactor::OneMinusSrcAlpha),
+		BlendMode::Additive => (BlendFactor::One, BlendFactor::Zero, BlendFactor::One),
+		BlendMode::Opaque | BlendMode::AlphaTested => unreachable!(),
+	};
+	AttachmentBlend {
+		enabled: true,
+		color_op: BlendOp::Add,
+		color_source: BlendFactor::SrcAlpha,
+		color_destination,
+		alpha_op: BlendOp::Add,
+		alpha_source,
+		alpha_destination,
+		mask_red: true,
+		mask_green: true,
+		mask_blue: true,
+		mask_alpha: true,
+	}
 }
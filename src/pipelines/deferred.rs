@@ -1,4 +1,5 @@
 mod context;
+mod geometry;
 mod pipeline;
 
 use self::context::DeferredPipelineContext;
@@ -14,11 +15,20 @@ use vulkano::{
 const DEPTH_FORMAT: Format = Format::D32Sfloat;
 // const ALT_DEPTH_FORMAT: Format = Format::X8_D24UnormPack32;
 const COLOR_FORMAT: Format = Format::A2B10G10R10UnormPack32;
-const POSITION_FORMAT: Format = Format::R32G32B32A32Sfloat;
-const NORMAL_FORMAT: Format = Format::R32G32B32A32Sfloat;
-const LIGHT_FORMAT: Format = Format::R32G32B32A32Sfloat;
+// octahedral encoded normals, see oct_encode in util.glsl. Positions are reconstructed from depth.
+const NORMAL_FORMAT: Format = Format::R16G16Sfloat;
+const LIGHT_FORMAT: Format = Format::R16G16B16A16Sfloat;
+// the part of the light buffer that SSAO darkens. It's allocated and written even without SSAO, so that the render
+// passes and every pipeline built on them don't depend on the setting.
+const AMBIENT_FORMAT: Format = Format::B10G11R11UfloatPack32;
+// must match clusters.glsl and the workgroup size in light_cull.glslc
+const CLUSTERS: u32 = 16 * 9 * 24;
+const CLUSTER_STRIDE: u32 = 64;
+const CLUSTER_WORKGROUP_SIZE: u32 = 64;
 
-pub(crate) struct DeferredPipelineDef;
+/// Renders geometry into a G-buffer, then shades it with lights that were culled into view-space clusters. Supports
+/// meshes with field data and SSAO, but needs much more memory bandwidth than `ForwardPipelineDef`.
+pub struct DeferredPipelineDef;
 impl PipelineDef for DeferredPipelineDef {
 	fn make_context(device: &Arc<Device>, queue: &Arc<Queue>) -> (Box<dyn PipelineContext>, Box<dyn GpuFuture>) {
 		let (pctx, future) = DeferredPipelineContext::new(device, queue);
@@ -26,20 +36,27 @@ impl PipelineDef for DeferredPipelineDef {
 	}
 }
 
-#[derive(Default, Debug, Clone, Copy)]
-#[repr(C)]
-struct Vert2D {
-	pos: [f32; 2],
-	texc: [f32; 2],
-}
-vulkano::impl_vertex!(Vert2D, pos, texc);
-
 mod geom_vshader {
 	vulkano_shaders::shader! { ty: "vertex", path: "src/pipelines/shaders/geom.glslv" }
 }
+mod geom_instanced_vshader {
+	vulkano_shaders::shader! { ty: "vertex", path: "src/pipelines/shaders/geom_instanced.glslv" }
+}
+mod geom_indirect_vshader {
+	vulkano_shaders::shader! { ty: "vertex", path: "src/pipelines/shaders/geom_indirect.glslv" }
+}
 mod geom_fshader {
 	vulkano_shaders::shader! { ty: "fragment", path: "src/pipelines/shaders/geom.glslf" }
 }
+mod transparent_fshader {
+	vulkano_shaders::shader! { ty: "fragment", path: "src/pipelines/shaders/forward.glslf" }
+}
+mod field_vshader {
+	vulkano_shaders::shader! { ty: "vertex", path: "src/pipelines/shaders/field.glslv" }
+}
+mod field_fshader {
+	vulkano_shaders::shader! { ty: "fragment", path: "src/pipelines/shaders/field.glslf" }
+}
 mod swap_vshader {
 	vulkano_shaders::shader! { ty: "vertex", path: "src/pipelines/shaders/swap.glslv" }
 }
@@ -52,3 +69,9 @@ mod light_vshader {
 mod light_fshader {
 	vulkano_shaders::shader! { ty: "fragment", path: "src/pipelines/shaders/light.glslf" }
 }
+mod ssao_apply_fshader {
+	vulkano_shaders::shader! { ty: "fragment", path: "src/pipelines/shaders/ssao_apply.glslf" }
+}
+mod light_cull_cshader {
+	vulkano_shaders::shader! { ty: "compute", path: "src/pipelines/shaders/light_cull.glslc" }
+}
@@ -1,19 +1,26 @@
 use super::{
-	geom_fshader, geom_vshader, light_fshader, light_vshader, pipeline::DeferredPipeline, swap_fshader, swap_vshader,
-	Vert2D, COLOR_FORMAT, DEPTH_FORMAT, LIGHT_FORMAT, NORMAL_FORMAT, POSITION_FORMAT,
+	field_fshader, field_vshader, geom_fshader, geom_indirect_vshader, geom_instanced_vshader, geom_vshader,
+	light_cull_cshader, light_fshader, light_vshader, pipeline::DeferredPipeline, ssao_apply_fshader, swap_fshader,
+	swap_vshader, transparent_fshader, AMBIENT_FORMAT, COLOR_FORMAT, DEPTH_FORMAT, LIGHT_FORMAT, NORMAL_FORMAT,
 };
 use crate::{
-	pipelines::{Pipeline, PipelineContext},
+	pipelines::{fxaa::FxaaContext, ssao::SsaoContext, InstanceTransform, Pipeline, PipelineContext, Vert2D, Vert3D},
 	surface::SWAP_FORMAT,
 };
 use log::trace;
 use std::sync::Arc;
 use vulkano::{
-	buffer::{BufferAccess, BufferUsage, ImmutableBuffer, TypedBufferAccess},
-	descriptor::{descriptor::ShaderStages, pipeline_layout::PipelineLayoutDesc, PipelineLayoutAbstract},
+	buffer::{BufferAccess, BufferUsage, CpuBufferPool, ImmutableBuffer, TypedBufferAccess},
+	descriptor::{
+		descriptor::ShaderStages,
+		pipeline_layout::{PipelineLayout, PipelineLayoutDesc},
+		PipelineLayoutAbstract,
+	},
 	device::{Device, Queue},
 	framebuffer::RenderPassAbstract,
 	image::ImageViewAccess,
+	pipeline::ComputePipeline,
+	sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode},
 	sync::GpuFuture,
 };
 
@@ -22,20 +29,36 @@ pub(super) struct DeferredPipelineContext {
 }
 impl DeferredPipelineContext {
 	pub(super) fn new(device: &Arc<Device>, queue: &Arc<Queue>) -> (Self, impl GpuFuture) {
+		// the G-buffer is stored between the two render passes, so SSAO can sample neighboring depths and normals
+		let geom_pass = Arc::new(
+			vulkano::single_pass_renderpass!(
+				device.clone(),
+				attachments: {
+					depth:		{ load: Clear,	store: Store,	format: DEPTH_FORMAT,	samples: 1, },
+					diffuse:	{ load: Clear,	store: Store,	format: COLOR_FORMAT,	samples: 1, },
+					normal:		{ load: Clear,	store: Store,	format: NORMAL_FORMAT,	samples: 1, },
+					light:		{ load: Clear,	store: Store,	format: LIGHT_FORMAT,	samples: 1, },
+					ambient:	{ load: Clear,	store: Store,	format: AMBIENT_FORMAT,	samples: 1, }
+				},
+				pass: { color: [diffuse, light, normal, ambient], depth_stencil: {depth} }
+			)
+			.unwrap(),
+		);
 		let render_pass = Arc::new(
 			vulkano::ordered_passes_renderpass!(
 				device.clone(),
 				attachments: {
-					depth:		{ load: Clear,	store: DontCare,	format: DEPTH_FORMAT,		samples: 1, },
-					diffuse:	{ load: Clear,	store: DontCare,	format: COLOR_FORMAT,		samples: 1, },
-					normal:		{ load: Clear,	store: DontCare,	format: NORMAL_FORMAT,		samples: 1, },
-					position:	{ load: Clear,	store: DontCare,	format: POSITION_FORMAT,	samples: 1, },
-					light:		{ load: Clear,	store: DontCare,	format: LIGHT_FORMAT,		samples: 1, },
-					color:		{ load: Clear,	store: Store,		format: SWAP_FORMAT,		samples: 1, }
+					depth:		{ load: Load,	store: DontCare,	format: DEPTH_FORMAT,	samples: 1, },
+					diffuse:	{ load: Load,	store: DontCare,	format: COLOR_FORMAT,	samples: 1, },
+					normal:		{ load: Load,	store: DontCare,	format: NORMAL_FORMAT,	samples: 1, },
+					light:		{ load: Load,	store: DontCare,	format: LIGHT_FORMAT,	samples: 1, },
+					ambient:	{ load: Load,	store: DontCare,	format: AMBIENT_FORMAT,	samples: 1, },
+					color:		{ load: Clear,	store: Store,		format: SWAP_FORMAT,	samples: 1, }
 				},
 				passes: [
-					{ color: [diffuse, light, normal, position], depth_stencil: {depth}, input: [] },
-					{ color: [light], depth_stencil: {}, input: [diffuse, normal, position] },
+					{ color: [light], depth_stencil: {}, input: [ambient] },
+					{ color: [light], depth_stencil: {}, input: [depth, diffuse, normal] },
+					{ color: [light], depth_stencil: {depth}, input: [] },
 					{ color: [color], depth_stencil: {}, input: [depth, light] }
 				]
 			)
@@ -47,6 +70,28 @@ impl DeferredPipelineContext {
 		let vs_layout = geom_vshader::Layout(ShaderStages { vertex: true, ..ShaderStages::none() });
 		let fs_layout = geom_fshader::Layout(ShaderStages { fragment: true, ..ShaderStages::none() });
 		let layout_desc = Arc::new(vs_layout.union(fs_layout).build(device.clone()).unwrap());
+		let geom_instanced_vshader = geom_instanced_vshader::Shader::load(device.clone()).unwrap();
+		let instance_pool = CpuBufferPool::vertex_buffer(device.clone());
+		let geom_indirect_vshader = geom_indirect_vshader::Shader::load(device.clone()).unwrap();
+		let transparent_fshader = transparent_fshader::Shader::load(device.clone()).unwrap();
+		let lights_pool = CpuBufferPool::uniform_buffer(device.clone());
+
+		let field_vshader = field_vshader::Shader::load(device.clone()).unwrap();
+		let field_fshader = field_fshader::Shader::load(device.clone()).unwrap();
+		let field_sampler = Sampler::new(
+			device.clone(),
+			Filter::Linear,
+			Filter::Linear,
+			MipmapMode::Nearest,
+			SamplerAddressMode::ClampToEdge,
+			SamplerAddressMode::ClampToEdge,
+			SamplerAddressMode::ClampToEdge,
+			0.0,
+			1.0,
+			0.0,
+			0.0,
+		)
+		.unwrap();
 
 		let swap_vshader = swap_vshader::Shader::load(device.clone()).unwrap();
 		let swap_fshader = swap_fshader::Shader::load(device.clone()).unwrap();
@@ -59,6 +104,18 @@ impl DeferredPipelineContext {
 		let light_vs_layout = light_vshader::Layout(ShaderStages { vertex: true, ..ShaderStages::none() });
 		let light_fs_layout = light_fshader::Layout(ShaderStages { fragment: true, ..ShaderStages::none() });
 		let light_layout_desc = Arc::new(light_vs_layout.union(light_fs_layout).build(device.clone()).unwrap());
+		let light_cull_cshader = light_cull_cshader::Shader::load(device.clone()).unwrap();
+		let light_cull_pipeline =
+			Arc::new(ComputePipeline::new(device.clone(), &light_cull_cshader.main_entry_point(), &()).unwrap());
+		let light_buffer_pool =
+			CpuBufferPool::new(device.clone(), BufferUsage { storage_buffer: true, ..BufferUsage::none() });
+		let light_bounds_pool =
+			CpuBufferPool::new(device.clone(), BufferUsage { storage_buffer: true, ..BufferUsage::none() });
+
+		let ssao_apply_fshader = ssao_apply_fshader::Shader::load(device.clone()).unwrap();
+
+		let fxaa = FxaaContext::new(device);
+		let ssao = SsaoContext::new(device);
 
 		let vertdata = [
 			Vert2D { pos: [-1.0, 1.0], texc: [0.0, 0.0] },
@@ -71,25 +128,44 @@ impl DeferredPipelineContext {
 		let (indices, indices_future) =
 			ImmutableBuffer::from_iter(vec![0, 1, 2, 2, 3, 0].into_iter(), BufferUsage::index_buffer(), queue.clone())
 				.unwrap();
+		let (cube_vertices, cube_vertices_future) =
+			ImmutableBuffer::from_iter(cube_vertices().into_iter(), BufferUsage::vertex_buffer(), queue.clone())
+				.unwrap();
 
 		(
 			Self {
 				inner: Arc::new(DeferredPipelineContextInner {
+					geom_pass,
 					render_pass,
 					geom_vshader,
 					geom_fshader,
 					layout_desc,
+					geom_instanced_vshader,
+					instance_pool,
+					geom_indirect_vshader,
+					transparent_fshader,
+					lights_pool,
+					field_vshader,
+					field_fshader,
+					field_sampler,
 					swap_vshader,
 					swap_fshader,
 					swap_layout_desc,
 					light_vshader,
 					light_fshader,
 					light_layout_desc,
+					light_cull_pipeline,
+					light_buffer_pool,
+					light_bounds_pool,
+					ssao_apply_fshader,
+					fxaa,
+					ssao,
 					vertices,
 					indices,
+					cube_vertices,
 				}),
 			},
-			vertices_future.join(indices_future),
+			vertices_future.join(indices_future).join(cube_vertices_future),
 		)
 	}
 }
@@ -113,10 +189,20 @@ impl PipelineContext for DeferredPipelineContext {
 }
 
 pub(super) struct DeferredPipelineContextInner {
+	pub(super) geom_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
 	pub(super) render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
 	pub(super) geom_vshader: geom_vshader::Shader,
 	pub(super) geom_fshader: geom_fshader::Shader,
 	pub(super) layout_desc: Arc<dyn PipelineLayoutAbstract + Send + Sync>,
+	pub(super) geom_instanced_vshader: geom_instanced_vshader::Shader,
+	pub(super) instance_pool: CpuBufferPool<InstanceTransform>,
+	pub(super) geom_indirect_vshader: geom_indirect_vshader::Shader,
+	pub(super) transparent_fshader: transparent_fshader::Shader,
+	pub(super) lights_pool: CpuBufferPool<transparent_fshader::ty::Lights>,
+
+	pub(super) field_vshader: field_vshader::Shader,
+	pub(super) field_fshader: field_fshader::Shader,
+	pub(super) field_sampler: Arc<Sampler>,
 
 	pub(super) swap_vshader: swap_vshader::Shader,
 	pub(super) swap_fshader: swap_fshader::Shader,
@@ -125,7 +211,25 @@ pub(super) struct DeferredPipelineContextInner {
 	pub(super) light_vshader: light_vshader::Shader,
 	pub(super) light_fshader: light_fshader::Shader,
 	pub(super) light_layout_desc: Arc<dyn PipelineLayoutAbstract + Send + Sync>,
+	pub(super) light_cull_pipeline: Arc<ComputePipeline<PipelineLayout<light_cull_cshader::Layout>>>,
+	pub(super) light_buffer_pool: CpuBufferPool<light_cull_cshader::ty::Light>,
+	pub(super) light_bounds_pool: CpuBufferPool<light_cull_cshader::ty::LightBounds>,
+
+	pub(super) ssao_apply_fshader: ssao_apply_fshader::Shader,
+
+	pub(super) fxaa: Arc<FxaaContext>,
+	pub(super) ssao: Arc<SsaoContext>,
 
 	pub(super) vertices: Arc<dyn BufferAccess + Send + Sync>,
 	pub(super) indices: Arc<dyn TypedBufferAccess<Content = [u32]> + Send + Sync>,
+	pub(super) cube_vertices: Arc<dyn BufferAccess + Send + Sync>,
+}
+
+/// A unit cube centered on the origin, as a triangle list.
+fn cube_vertices() -> Vec<Vert3D> {
+	let corner = |i: usize| Vert3D {
+		pos: [(i & 1) as f32 - 0.5, ((i >> 1) & 1) as f32 - 0.5, ((i >> 2) & 1) as f32 - 0.5],
+	};
+	let quads = [[0, 1, 3, 2], [4, 6, 7, 5], [0, 4, 5, 1], [2, 3, 7, 6], [0, 2, 6, 4], [1, 5, 7, 3]];
+	quads.iter().flat_map(|q| vec![q[0], q[1], q[2], q[2], q[3], q[0]]).map(corner).collect()
 }
@@ -0,0 +1,312 @@
+use super::{
+	context::DeferredPipelineContextInner, field_vshader, geom_indirect_vshader, geom_instanced_vshader, geom_vshader,
+};
+use crate::{
+	field_data::FieldData,
+	frame_stats::DrawCounts,
+	mesh::MeshInner,
+	mesh_data::{IndexBuffer, MeshData},
+	mesh_group::IndirectGroup,
+	pipelines::{topology_pipeline, InstanceTransform},
+	threads::RECORD_THREADS,
+	transform::Transform,
+};
+use cgmath::Vector4;
+use futures::{future::lazy, task::SpawnExt};
+use std::{
+	ops::Range,
+	sync::{mpsc, Arc},
+};
+use vulkano::{
+	buffer::BufferAccess,
+	command_buffer::{AutoCommandBuffer, AutoCommandBufferBuilder},
+	descriptor::{
+		descriptor_set::{DescriptorSetsCollection, PersistentDescriptorSet},
+		DescriptorSet,
+	},
+	framebuffer::Subpass,
+	pipeline::{input_assembly::PrimitiveTopology, GraphicsPipelineAbstract},
+};
+
+/// Partitions smaller than this aren't worth sending to another thread.
+const MIN_PARTITION: usize = 256;
+
+/// A draw in the geometry subpass.
+pub(super) enum GeomDraw {
+	Batch(Batch),
+	Indirect(IndirectGroup),
+	Field(Arc<FieldData>, Arc<dyn DescriptorSet + Send + Sync>, Transform),
+}
+
+/// Meshes that are drawn together, with one instance per transform.
+pub(super) struct Batch {
+	pub(super) mesh_data: Arc<MeshData>,
+	pub(super) range: Range<usize>,
+	pub(super) desc: Arc<dyn DescriptorSet + Send + Sync>,
+	pub(super) lightmap: u32,
+	pub(super) alpha_test: u32,
+	pub(super) transforms: Vec<Transform>,
+}
+impl Batch {
+	pub(super) fn new(mesh: &MeshInner) -> Self {
+		Self {
+			mesh_data: mesh.mesh_data().unwrap().clone(),
+			range: mesh.range(),
+			desc: mesh.desc().clone(),
+			lightmap: mesh.lightmap_mode(),
+			alpha_test: mesh.blend_mode().alpha_test(),
+			transforms: vec![],
+		}
+	}
+
+	pub(super) fn sort_key(&self) -> (u32, bool, usize, usize) {
+		let topology = match self.mesh_data.topology() {
+			PrimitiveTopology::TriangleList => 0,
+			PrimitiveTopology::TriangleStrip => 1,
+			_ => 2,
+		};
+		let mesh_data = &*self.mesh_data as *const MeshData as usize;
+		let desc = &*self.desc as *const _ as *const () as usize;
+		(topology, self.transforms.len() > 1, mesh_data, desc)
+	}
+}
+
+/// Everything needed to record the geometry subpass, so that parts of it can be recorded on other threads.
+pub(super) struct GeomRecorder {
+	pub(super) ctx: Arc<DeferredPipelineContextInner>,
+	pub(super) qfam: u32,
+	pub(super) pipeline_soup: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	pub(super) pipeline_strip: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	pub(super) pipeline_soup_instanced: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	pub(super) pipeline_strip_instanced: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	pub(super) pipeline_soup_indirect: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	pub(super) pipeline_strip_indirect: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	pub(super) field_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	pub(super) skybox: Arc<dyn DescriptorSet + Send + Sync>,
+	pub(super) draws_desc: Option<Arc<dyn DescriptorSet + Send + Sync>>,
+	pub(super) cam_proj: Vector4<f32>,
+	pub(super) cam_transform: Transform,
+}
+impl GeomRecorder {
+	/// Splits `draws` into contiguous partitions, records each one into a secondary command buffer on
+	/// `RECORD_THREADS`, and returns them in order. Executing them in order draws exactly what recording `draws` into a
+	/// single command buffer would.
+	pub(super) fn record_parallel(self: Arc<Self>, mut draws: Vec<GeomDraw>) -> Vec<(AutoCommandBuffer, DrawCounts)> {
+		let partitions = num_cpus::get().min((draws.len() + MIN_PARTITION - 1) / MIN_PARTITION).max(1);
+		if partitions == 1 {
+			return vec![self.record(&draws)];
+		}
+
+		let partition_size = (draws.len() + partitions - 1) / partitions;
+		let mut chunks = vec![];
+		while draws.len() > partition_size {
+			let rest = draws.split_off(partition_size);
+			chunks.push(draws);
+			draws = rest;
+		}
+		chunks.push(draws);
+
+		let (sender, receiver) = mpsc::channel();
+		{
+			let mut threads = RECORD_THREADS.lock().unwrap();
+			for (i, chunk) in chunks.into_iter().enumerate() {
+				let recorder = self.clone();
+				let sender = sender.clone();
+				threads.spawn(lazy(move |_| sender.send((i, recorder.record(&chunk))).unwrap())).unwrap();
+			}
+		}
+		drop(sender);
+
+		let mut results = receiver.iter().collect::<Vec<_>>();
+		results.sort_by_key(|&(i, _)| i);
+		results.into_iter().map(|(_, result)| result).collect()
+	}
+
+	fn record(&self, draws: &[GeomDraw]) -> (AutoCommandBuffer, DrawCounts) {
+		let device = self.ctx.geom_pass.device().clone();
+		let qfam = device.physical_device().queue_family_by_id(self.qfam).unwrap();
+		let subpass = Subpass::from(self.ctx.geom_pass.clone(), 0).unwrap();
+		let mut command_buffer =
+			AutoCommandBufferBuilder::secondary_graphics_one_time_submit(device.clone(), qfam, subpass).unwrap();
+		let mut counts = DrawCounts::default();
+
+		for draw in draws {
+			command_buffer = match draw {
+				GeomDraw::Batch(batch) => self.draw_batch(command_buffer, batch, &mut counts),
+				GeomDraw::Indirect(group) => self.draw_indirect(command_buffer, group, &mut counts),
+				GeomDraw::Field(field_data, desc, transform) => {
+					self.draw_field(command_buffer, field_data, desc, transform, &mut counts)
+				},
+			};
+		}
+
+		(command_buffer.build().unwrap(), counts)
+	}
+
+	fn draw_batch(
+		&self,
+		command_buffer: AutoCommandBufferBuilder,
+		batch: &Batch,
+		counts: &mut DrawCounts,
+	) -> AutoCommandBufferBuilder {
+		let topology = batch.mesh_data.topology();
+		let instanced = batch.transforms.len() > 1;
+		let (soup, strip) = if instanced {
+			(&self.pipeline_soup_instanced, &self.pipeline_strip_instanced)
+		} else {
+			(&self.pipeline_soup, &self.pipeline_strip)
+		};
+		let pipeline = match topology_pipeline(topology, soup, strip) {
+			Some(pipeline) => pipeline,
+			None => return command_buffer,
+		};
+		let sets = (batch.desc.clone(), self.skybox.clone());
+		counts.add_instanced(topology, batch.range.len(), batch.transforms.len());
+
+		if instanced {
+			let instances = self.ctx.instance_pool.chunk(batch.transforms.iter().map(InstanceTransform::from)).unwrap();
+			let vertex_buffers: Vec<Arc<dyn BufferAccess + Send + Sync>> =
+				vec![batch.mesh_data.vertices().clone(), Arc::new(instances)];
+			let pc = geom_instanced_vshader::ty::PushConsts {
+				cam_proj: self.cam_proj.into(),
+				cam_pos: self.cam_transform.pos.into(),
+				cam_rot: self.cam_transform.rot.into(),
+				lightmap: batch.lightmap,
+				alpha_test: batch.alpha_test,
+			};
+			draw_indexed(command_buffer, pipeline, vertex_buffers, batch, sets, pc)
+		} else {
+			let transform = &batch.transforms[0];
+			let pc = geom_vshader::ty::PushConsts {
+				cam_proj: self.cam_proj.into(),
+				cam_pos: self.cam_transform.pos.into(),
+				cam_rot: self.cam_transform.rot.into(),
+				mesh_pos: transform.pos.into(),
+				mesh_rot: transform.rot.into(),
+				lightmap: batch.lightmap,
+				alpha_test: batch.alpha_test,
+			};
+			draw_indexed(command_buffer, pipeline, vec![batch.mesh_data.vertices().clone()], batch, sets, pc)
+		}
+	}
+
+	/// Draws every mesh in `group` with one multi-draw, or with one draw each if the device doesn't support them.
+	fn draw_indirect(
+		&self,
+		mut command_buffer: AutoCommandBufferBuilder,
+		group: &IndirectGroup,
+		counts: &mut DrawCounts,
+	) -> AutoCommandBufferBuilder {
+		let topology = group.mesh_data.topology();
+		let pipeline = match topology_pipeline(topology, &self.pipeline_soup_indirect, &self.pipeline_strip_indirect) {
+			Some(pipeline) => pipeline,
+			None => return command_buffer,
+		};
+		let multi_draw = self.ctx.geom_pass.device().enabled_features().multi_draw_indirect;
+		let draws = if multi_draw {
+			vec![group.commands.clone()]
+		} else {
+			(0..group.commands.len()).map(|i| group.commands.clone().slice(i..i + 1).unwrap()).collect()
+		};
+		counts.add_draw(group.triangles);
+		counts.draw_calls += draws.len() as u32 - 1;
+
+		let dynamic = Default::default();
+		let sets = (group.desc.clone(), self.skybox.clone(), self.draws_desc.clone().unwrap());
+		let pc = geom_indirect_vshader::ty::PushConsts {
+			cam_proj: self.cam_proj.into(),
+			cam_pos: self.cam_transform.pos.into(),
+			cam_rot: self.cam_transform.rot.into(),
+		};
+		for commands in draws {
+			let pipeline = pipeline.clone();
+			let vertex_buffer = vec![group.mesh_data.vertices().clone()];
+			let sets = sets.clone();
+			command_buffer = match group.mesh_data.indices() {
+				IndexBuffer::U16(buf) => command_buffer
+					.draw_indexed_indirect(pipeline, &dynamic, vertex_buffer, buf.clone(), commands, sets, pc)
+					.unwrap(),
+				IndexBuffer::U32(buf) => command_buffer
+					.draw_indexed_indirect(pipeline, &dynamic, vertex_buffer, buf.clone(), commands, sets, pc)
+					.unwrap(),
+			};
+		}
+		command_buffer
+	}
+
+	fn draw_field(
+		&self,
+		command_buffer: AutoCommandBufferBuilder,
+		field_data: &FieldData,
+		desc: &Arc<dyn DescriptorSet + Send + Sync>,
+		transform: &Transform,
+		counts: &mut DrawCounts,
+	) -> AutoCommandBufferBuilder {
+		let field_desc = field_data.desc(|| {
+			Arc::new(
+				PersistentDescriptorSet::start(self.field_pipeline.clone(), 2)
+					.add_sampled_image(field_data.image().clone(), self.ctx.field_sampler.clone())
+					.unwrap()
+					.build()
+					.unwrap(),
+			)
+		});
+		let extent = field_data.extent();
+		let distance_transform = field_data.distance_transform();
+		counts.add_draw(12);
+
+		command_buffer
+			.draw(
+				self.field_pipeline.clone(),
+				&Default::default(),
+				vec![self.ctx.cube_vertices.clone()],
+				(desc.clone(), self.skybox.clone(), field_desc),
+				field_vshader::ty::PushConsts {
+					cam_proj: self.cam_proj.into(),
+					cam_pos: self.cam_transform.pos.into(),
+					cam_rot: self.cam_transform.rot.into(),
+					mesh_pos: transform.pos.into(),
+					mesh_rot: transform.rot.into(),
+					extent: [extent[0], extent[1], extent[2], 0.0],
+					distance_transform: [distance_transform[0], distance_transform[1], 0.0, 0.0],
+				},
+			)
+			.unwrap()
+	}
+}
+
+pub(super) fn draw_indexed<S, Pc>(
+	command_buffer: AutoCommandBufferBuilder,
+	pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	vertex_buffers: Vec<Arc<dyn BufferAccess + Send + Sync>>,
+	batch: &Batch,
+	sets: S,
+	pc: Pc,
+) -> AutoCommandBufferBuilder
+where
+	S: DescriptorSetsCollection,
+{
+	let dynamic = Default::default();
+	match batch.mesh_data.indices() {
+		IndexBuffer::U16(buf) => command_buffer
+			.draw_indexed(
+				pipeline,
+				&dynamic,
+				vertex_buffers,
+				buf.clone().into_buffer_slice().slice(batch.range.clone()).unwrap(),
+				sets,
+				pc,
+			)
+			.unwrap(),
+		IndexBuffer::U32(buf) => command_buffer
+			.draw_indexed(
+				pipeline,
+				&dynamic,
+				vertex_buffers,
+				buf.clone().into_buffer_slice().slice(batch.range.clone()).unwrap(),
+				sets,
+				pc,
+			)
+			.unwrap(),
+	}
+}
@@ -1,27 +1,40 @@
 use super::{
-	context::DeferredPipelineContextInner, geom_fshader, geom_vshader, light_fshader, light_vshader, swap_fshader,
-	swap_vshader, Vert2D, COLOR_FORMAT, DEPTH_FORMAT, LIGHT_FORMAT, NORMAL_FORMAT, POSITION_FORMAT,
+	context::DeferredPipelineContextInner,
+	field_fshader, field_vshader, geom_fshader, geom_indirect_vshader, geom_instanced_vshader, geom_vshader,
+	geometry::{draw_indexed, Batch, GeomDraw, GeomRecorder},
+	light_cull_cshader, light_fshader, light_vshader, ssao_apply_fshader, swap_fshader, swap_vshader,
+	transparent_fshader, AMBIENT_FORMAT, CLUSTERS, CLUSTER_STRIDE, CLUSTER_WORKGROUP_SIZE, COLOR_FORMAT, DEPTH_FORMAT,
+	LIGHT_FORMAT, NORMAL_FORMAT,
 };
 use crate::{
 	camera::Camera,
-	direct_light::DirectLight,
-	mesh::MeshInner,
-	mesh_data::{IndexBuffer, Pntl_32F},
-	pipelines::Pipeline,
+	direct_light::{DirectLight, LightBounds},
+	frame_stats::DrawCounts,
+	gpu_timer::{FrameQueries, Stamp},
+	mesh::{BlendMode, InstanceKey, MeshInner},
+	mesh_data::Pntl_32F,
+	pipelines::{
+		fxaa::Fxaa, ssao::Ssao, topology_pipeline, transparent_blend, AmbientOcclusion, AntiAliasing, InstanceTransform,
+		Pipeline, Vert2D, Vert3D, MAX_LIGHTS,
+	},
 };
-use std::sync::Arc;
+use cgmath::prelude::*;
+use std::{collections::HashMap, sync::Arc};
 use vulkano::{
-	buffer::BufferAccess,
-	command_buffer::{AutoCommandBuffer, AutoCommandBufferBuilder},
+	buffer::{BufferUsage, DeviceLocalBuffer},
+	command_buffer::{AutoCommandBuffer, AutoCommandBufferBuilder, DynamicState},
 	descriptor::{descriptor_set::PersistentDescriptorSet, DescriptorSet, PipelineLayoutAbstract},
 	device::Device,
+	format::ClearValue,
 	framebuffer::{Framebuffer, FramebufferAbstract, RenderPassAbstract, Subpass},
-	image::{AttachmentImage, ImageViewAccess},
+	image::{AttachmentImage, ImageUsage, ImageViewAccess},
 	instance::QueueFamily,
 	pipeline::{
 		blend::{AttachmentBlend, BlendFactor, BlendOp},
+		depth_stencil::DepthStencil,
 		input_assembly::PrimitiveTopology,
-		viewport::Viewport,
+		viewport::{Scissor, Viewport},
+		vertex::OneVertexOneInstanceDefinition,
 		GraphicsPipeline, GraphicsPipelineAbstract,
 	},
 };
@@ -30,10 +43,23 @@ pub(super) struct DeferredPipeline {
 	ctx: Arc<DeferredPipelineContextInner>,
 	geom_pipeline_soup: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
 	geom_pipeline_strip: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	geom_pipeline_soup_instanced: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	geom_pipeline_strip_instanced: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	geom_pipeline_soup_indirect: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	geom_pipeline_strip_indirect: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	field_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	blend_pipeline_soup: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	blend_pipeline_strip: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	additive_pipeline_soup: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	additive_pipeline_strip: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
 	light_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	ssao_apply_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
 	swap_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
-	framebuffers: Vec<Arc<dyn FramebufferAbstract + Send + Sync>>,
-	gbuffers_desc: Arc<dyn DescriptorSet + Send + Sync>,
+	images: Vec<Arc<dyn ImageViewAccess + Send + Sync>>,
+	targets: Targets,
+	clusters: Arc<DeviceLocalBuffer<[u32]>>,
+	aa: AntiAliasing,
+	ao: AmbientOcclusion,
 	dimensions: [u32; 2],
 }
 impl DeferredPipeline {
@@ -43,107 +69,306 @@ impl DeferredPipeline {
 		dimensions: [u32; 2],
 	) -> Self {
 		let (geom_pipeline_soup, geom_pipeline_strip) =
-			create_geom_pipelines(&ctx.geom_vshader, &ctx.geom_fshader, &ctx.render_pass, dimensions);
+			create_geom_pipelines(&ctx.geom_vshader, &ctx.geom_fshader, &ctx.geom_pass, dimensions);
+		let (geom_pipeline_soup_instanced, geom_pipeline_strip_instanced) = create_instanced_geom_pipelines(
+			&ctx.geom_instanced_vshader,
+			&ctx.geom_fshader,
+			&ctx.geom_pass,
+			dimensions,
+		);
+		let (geom_pipeline_soup_indirect, geom_pipeline_strip_indirect) = create_indirect_geom_pipelines(
+			&ctx.geom_indirect_vshader,
+			&ctx.geom_fshader,
+			&ctx.geom_pass,
+			dimensions,
+		);
+		let field_pipeline =
+			create_field_pipeline(&ctx.field_vshader, &ctx.field_fshader, ctx.geom_pass.clone(), dimensions);
+		let (blend_pipeline_soup, blend_pipeline_strip) = create_transparent_pipelines(
+			&ctx.geom_vshader,
+			&ctx.transparent_fshader,
+			&ctx.render_pass,
+			dimensions,
+			BlendMode::AlphaBlended,
+		);
+		let (additive_pipeline_soup, additive_pipeline_strip) = create_transparent_pipelines(
+			&ctx.geom_vshader,
+			&ctx.transparent_fshader,
+			&ctx.render_pass,
+			dimensions,
+			BlendMode::Additive,
+		);
 		let light_pipeline =
 			create_light_pipeline(&ctx.light_vshader, &ctx.light_fshader, ctx.render_pass.clone(), dimensions);
+		let ssao_apply_pipeline = create_ssao_apply_pipeline(
+			&ctx.light_vshader,
+			&ctx.ssao_apply_fshader,
+			ctx.render_pass.clone(),
+			dimensions,
+		);
 		let swap_pipeline =
 			create_swap_pipeline(&ctx.swap_vshader, &ctx.swap_fshader, ctx.render_pass.clone(), dimensions);
 
-		let gbuffers = create_gbuffers(ctx.render_pass.device(), dimensions);
-		let framebuffers = create_framebuffers(&ctx.render_pass, &gbuffers, images);
-		let gbuffers_desc = make_gbuffers_desc(ctx.light_layout_desc.clone(), &gbuffers);
+		let aa = AntiAliasing::default();
+		let ao = AmbientOcclusion::default();
+		let targets = create_targets(&ctx, &images, dimensions, aa, ao, &ssao_apply_pipeline);
+
+		let device = ctx.render_pass.device();
+		let clusters = DeviceLocalBuffer::array(
+			device.clone(),
+			(CLUSTERS * CLUSTER_STRIDE) as usize,
+			BufferUsage { storage_buffer: true, ..BufferUsage::none() },
+			device.active_queue_families(),
+		)
+		.unwrap();
 
 		Self {
 			ctx,
 			geom_pipeline_soup,
 			geom_pipeline_strip,
+			geom_pipeline_soup_instanced,
+			geom_pipeline_strip_instanced,
+			geom_pipeline_soup_indirect,
+			geom_pipeline_strip_indirect,
+			field_pipeline,
+			blend_pipeline_soup,
+			blend_pipeline_strip,
+			additive_pipeline_soup,
+			additive_pipeline_strip,
 			swap_pipeline,
 			light_pipeline,
-			framebuffers,
-			gbuffers_desc,
+			ssao_apply_pipeline,
+			images,
+			targets,
+			clusters,
+			aa,
+			ao,
 			dimensions,
 		}
 	}
-}
-impl Pipeline for DeferredPipeline {
-	fn draw(&self, image_num: usize, qfam: QueueFamily, cam: &Camera, lights: &[DirectLight]) -> AutoCommandBuffer {
-		let clear_values = vec![
-			1.0.into(),
-			[0.0, 0.0, 0.0, 1.0].into(),
-			[0.0; 4].into(),
-			[0.0; 4].into(),
-			[0.0; 4].into(),
-			[0.0; 4].into(),
-		];
-
-		let make_pc = |mesh: &MeshInner| geom_vshader::ty::PushConsts {
-			cam_proj: cam.projection().into(),
+
+	fn make_lights_desc(&self, cam: &Camera, lights: &[DirectLight]) -> Arc<dyn DescriptorSet + Send + Sync> {
+		let empty = transparent_fshader::ty::Light { position: [0.0; 4], color: [0.0; 4] };
+		let mut data = transparent_fshader::ty::Lights {
 			cam_pos: cam.transform().pos.into(),
-			cam_rot: cam.transform().rot.into(),
-			mesh_pos: mesh.transform().pos.into(),
-			mesh_rot: mesh.transform().rot.into(),
+			count: [lights.len().min(MAX_LIGHTS) as u32, 0, 0, 0],
+			lights: [empty; MAX_LIGHTS],
 		};
+		for (dst, light) in data.lights.iter_mut().zip(lights) {
+			let (position, color) = light.packed();
+			*dst = transparent_fshader::ty::Light { position, color };
+		}
 
-		let mut command_buffer =
-			AutoCommandBufferBuilder::primary_one_time_submit(self.ctx.render_pass.device().clone(), qfam)
+		Arc::new(
+			PersistentDescriptorSet::start(self.blend_pipeline_soup.clone(), 1)
+				.add_buffer(self.ctx.lights_pool.next(data).unwrap())
 				.unwrap()
-				.begin_render_pass(self.framebuffers[image_num].clone(), false, clear_values)
+				.build()
+				.unwrap(),
+		)
+	}
+}
+impl Pipeline for DeferredPipeline {
+	fn draw(
+		&self,
+		image_num: usize,
+		qfam: QueueFamily,
+		cam: &Camera,
+		lights: &[DirectLight],
+		queries: Option<&FrameQueries>,
+	) -> (AutoCommandBuffer, DrawCounts) {
+		let mut counts = DrawCounts::default();
+		let geom_clear_values =
+			vec![1.0.into(), [0.0, 0.0, 0.0, 1.0].into(), [0.0; 4].into(), [0.0; 4].into(), [0.0; 4].into()];
+		// the G-buffer is loaded from the geometry pass
+		let mut clear_values = vec![ClearValue::None; 5];
+		clear_values.push([0.0; 4].into());
+
+		let skybox = cam.mesh_group().skybox();
+
+		let meshes = cam.mesh_group().meshes().lock().unwrap();
+		let mut indirect = cam.mesh_group().indirect().lock().unwrap();
+
+		let mut command_buffer =
+			AutoCommandBufferBuilder::primary_one_time_submit(self.ctx.render_pass.device().clone(), qfam).unwrap();
+
+		// lights that can't reach the screen are skipped, and the light pass only covers what the rest can reach
+		let mut visible = vec![];
+		let mut bounds: Option<LightBounds> = None;
+		for light in lights {
+			if let Some(light_bounds) = light.bounds(cam.projection(), cam.transform()) {
+				visible.push((light, light_bounds));
+				bounds = Some(bounds.map_or(light_bounds, |bounds| bounds.union(light_bounds)));
+			}
+		}
+
+		// bin the lights into clusters before the render pass, so the light pass only shades each pixel with the lights
+		// that reach its cluster. Each light is only binned into clusters within its screen and depth bounds.
+		let lights_desc = if visible.is_empty() {
+			None
+		} else {
+			let packed = visible.iter().map(|(light, _)| {
+				let (position, color) = light.packed();
+				light_cull_cshader::ty::Light { position, color }
+			});
+			let light_buffer = self.ctx.light_buffer_pool.chunk(packed).unwrap();
+			let packed_bounds = visible.iter().map(|(_, bounds)| light_cull_cshader::ty::LightBounds {
+				rect: [bounds.min.x, bounds.min.y, bounds.max.x, bounds.max.y],
+				depth: [bounds.near, bounds.far, 0.0, 0.0],
+			});
+			let bounds_buffer = self.ctx.light_bounds_pool.chunk(packed_bounds).unwrap();
+			let cull_desc = Arc::new(
+				PersistentDescriptorSet::start(self.ctx.light_cull_pipeline.clone(), 0)
+					.add_buffer(light_buffer.clone())
+					.unwrap()
+					.add_buffer(self.clusters.clone())
+					.unwrap()
+					.add_buffer(bounds_buffer)
+					.unwrap()
+					.build()
+					.unwrap(),
+			);
+			command_buffer = command_buffer
+				.dispatch(
+					[(CLUSTERS + CLUSTER_WORKGROUP_SIZE - 1) / CLUSTER_WORKGROUP_SIZE, 1, 1],
+					self.ctx.light_cull_pipeline.clone(),
+					cull_desc,
+					light_cull_cshader::ty::PushConsts {
+						inv_proj: cam.inv_proj().into(),
+						cam_pos: cam.transform().pos.into(),
+						cam_rot: cam.transform().rot.into(),
+						light_count: visible.len() as u32,
+					},
+				)
 				.unwrap();
-		for mesh in cam.mesh_group().meshes().lock().unwrap().values() {
-			let mut mesh = mesh.write().unwrap();
-			mesh.refresh();
 
-			let mesh_data = if let Some(mesh_data) = mesh.mesh_data() { mesh_data } else { continue };
+			Some(Arc::new(
+				PersistentDescriptorSet::start(self.light_pipeline.clone(), 1)
+					.add_buffer(light_buffer)
+					.unwrap()
+					.add_buffer(self.clusters.clone())
+					.unwrap()
+					.build()
+					.unwrap(),
+			))
+		};
 
-			let pipeline = match mesh_data.topology() {
-				PrimitiveTopology::TriangleList => self.geom_pipeline_soup.clone(),
-				PrimitiveTopology::TriangleStrip => self.geom_pipeline_strip.clone(),
-				_ => unimplemented!(),
-			};
-			let dynamic = Default::default();
-			let vertex_buffer = vec![mesh_data.vertices().clone()];
-			let desc = mesh.desc().clone();
-			let pc = make_pc(&mesh);
-			match mesh_data.indices() {
-				IndexBuffer::U16(buf) => {
-					command_buffer = command_buffer
-						.draw_indexed(
-							pipeline,
-							&dynamic,
-							vertex_buffer,
-							buf.clone().into_buffer_slice().slice(mesh.range()).unwrap(),
-							desc,
-							pc,
-						)
-						.unwrap()
-				},
-				IndexBuffer::U32(buf) => {
-					command_buffer = command_buffer
-						.draw_indexed(
-							pipeline,
-							&dynamic,
-							vertex_buffer,
-							buf.clone().into_buffer_slice().slice(mesh.range()).unwrap(),
-							desc,
-							pc,
-						)
-						.unwrap();
-				},
+		let mut batches = HashMap::<InstanceKey, Batch>::new();
+		let mut fields = vec![];
+		let mut transparent = vec![];
+		for mesh in meshes.values() {
+			let mesh = MeshInner::read_refreshed(mesh, cam.transform().pos);
+			if let Some(field_data) = mesh.field_data() {
+				fields.push(GeomDraw::Field(field_data.clone(), mesh.desc().clone(), *mesh.transform()));
+			} else if mesh.mesh_data().is_none() {
+				continue;
+			} else if mesh.blend_mode().is_transparent() {
+				let distance = (mesh.transform().pos - cam.transform().pos).truncate().magnitude();
+				let mut batch = Batch::new(&mesh);
+				batch.transforms.push(*mesh.transform());
+				transparent.push((distance, mesh.blend_mode(), batch));
+			} else if indirect.is_some() {
+				// drawn in groups below
+				continue;
+			} else if let Some(key) = mesh.instance_key() {
+				batches.entry(key).or_insert_with(|| Batch::new(&mesh)).transforms.push(*mesh.transform());
 			}
 		}
 
+		// updated after the loop above, so it groups meshes by their refreshed descriptor sets
+		let (draws_desc, indirect_groups) = match indirect.as_mut() {
+			Some(indirect) => {
+				command_buffer = indirect.update(command_buffer, &meshes);
+				let draws_desc: Arc<dyn DescriptorSet + Send + Sync> = Arc::new(
+					PersistentDescriptorSet::start(self.geom_pipeline_soup_indirect.clone(), 2)
+						.add_buffer(indirect.draws().clone())
+						.unwrap()
+						.build()
+						.unwrap(),
+				);
+				(Some(draws_desc), indirect.groups().to_vec())
+			},
+			None => (None, vec![]),
+		};
+		drop(meshes);
+		drop(indirect);
+
+		command_buffer =
+			command_buffer.begin_render_pass(self.targets.geom_framebuffer.clone(), true, geom_clear_values).unwrap();
+
+		// Sorting by pipeline, then mesh data, then descriptor set keeps identical state together, and the command
+		// buffer builder skips binding state that is already bound.
+		let mut batches = batches.into_iter().map(|(_, batch)| batch).collect::<Vec<_>>();
+		batches.sort_by_key(Batch::sort_key);
+
+		let mut draws = batches.into_iter().map(GeomDraw::Batch).collect::<Vec<_>>();
+		// indirect groups are already sorted the same way
+		draws.extend(indirect_groups.into_iter().map(GeomDraw::Indirect));
+		draws.extend(fields);
+
+		let recorder = Arc::new(GeomRecorder {
+			ctx: self.ctx.clone(),
+			qfam: qfam.id(),
+			pipeline_soup: self.geom_pipeline_soup.clone(),
+			pipeline_strip: self.geom_pipeline_strip.clone(),
+			pipeline_soup_instanced: self.geom_pipeline_soup_instanced.clone(),
+			pipeline_strip_instanced: self.geom_pipeline_strip_instanced.clone(),
+			pipeline_soup_indirect: self.geom_pipeline_soup_indirect.clone(),
+			pipeline_strip_indirect: self.geom_pipeline_strip_indirect.clone(),
+			field_pipeline: self.field_pipeline.clone(),
+			skybox: skybox.clone(),
+			draws_desc,
+			cam_proj: cam.projection(),
+			cam_transform: *cam.transform(),
+		});
+		for (secondary, secondary_counts) in recorder.record_parallel(draws) {
+			// the secondary command buffers are recorded for the geometry render pass
+			command_buffer = unsafe { command_buffer.execute_commands(secondary) }.unwrap();
+			counts.merge(secondary_counts);
+		}
+		command_buffer = command_buffer.end_render_pass().unwrap();
+		if let Some(queries) = queries {
+			command_buffer = queries.write(command_buffer, Stamp::Geometry, None);
+		}
+
+		if let Some((ssao, _)) = &self.targets.ssao {
+			// an occlusion pass and a blur pass
+			command_buffer = ssao.draw(command_buffer, cam);
+			counts.add_draw(1);
+			counts.add_draw(1);
+		}
+
+		let framebuffer = self.targets.framebuffers[image_num].clone();
+		command_buffer = command_buffer.begin_render_pass(framebuffer, false, clear_values).unwrap();
+		// only the sky's ambient light is darkened, lightmaps are already occluded and direct light comes later
+		if let Some((_, ssao_desc)) = &self.targets.ssao {
+			counts.add_draw(2);
+			command_buffer = command_buffer
+				.draw_indexed(
+					self.ssao_apply_pipeline.clone(),
+					&Default::default(),
+					vec![self.ctx.vertices.clone()],
+					self.ctx.indices.clone(),
+					ssao_desc.clone(),
+					(),
+				)
+				.unwrap();
+		}
+
 		command_buffer = command_buffer.next_subpass(false).unwrap();
-		for light in lights {
-			let light_cutoff = 0.003035269835488375;
-			let radius_squared = light.radius * light.radius;
+		if let (Some(lights_desc), Some(bounds)) = (lights_desc, bounds) {
+			counts.add_draw(2);
+			counts.lights = visible.len() as u32;
 
+			let dynamic_state =
+				DynamicState { scissors: Some(vec![scissor(&bounds, self.dimensions)]), ..DynamicState::none() };
 			command_buffer = command_buffer
 				.draw_indexed(
 					self.light_pipeline.clone(),
-					&Default::default(),
+					&dynamic_state,
 					vec![self.ctx.vertices.clone()],
 					self.ctx.indices.clone(),
-					self.gbuffers_desc.clone(),
+					(self.targets.light_desc.clone(), lights_desc),
 					light_fshader::ty::PushConsts {
 						Resolution: [
 							self.dimensions[0] as f32,
@@ -151,37 +376,127 @@ impl Pipeline for DeferredPipeline {
 							1.0 / (self.dimensions[0] as f32),
 							1.0 / (self.dimensions[1] as f32),
 						],
-						Projection: cam.projection().into(),
+						InverseProjection: cam.inv_proj().into(),
 						CameraRotation: cam.transform().rot.into(),
 						CameraOffset: cam.transform().pos.into(),
-						LightPosition: [light.position.x, light.position.y, light.position.z, 1.0 / radius_squared],
-						LightColor: [light.color.x, light.color.y, light.color.z, light_cutoff * radius_squared],
+						DepthBounds: [bounds.near, bounds.far],
 					},
 				)
 				.unwrap();
 		}
 
-		command_buffer = command_buffer
-			.next_subpass(false)
-			.unwrap()
-			.draw_indexed(
-				self.swap_pipeline.clone(),
-				&Default::default(),
-				vec![self.ctx.vertices.clone()],
-				self.ctx.indices.clone(),
-				(self.gbuffers_desc.clone(), cam.mesh_group().skybox().lock().unwrap().clone()),
-				swap_fshader::ty::PushConsts { inv_proj: cam.inv_proj().into(), cam_rot: cam.transform().rot.into() },
-			)
-			.unwrap();
-
-		command_buffer.end_render_pass().unwrap().build().unwrap()
+		// transparent meshes are drawn from back to front, over the lit scene
+		command_buffer = command_buffer.next_subpass(false).unwrap();
+		transparent.sort_by(|(lhs, ..), (rhs, ..)| rhs.partial_cmp(lhs).unwrap());
+		let lights_desc = self.make_lights_desc(cam, lights);
+		for (_, blend_mode, batch) in &transparent {
+			let (soup, strip) = match blend_mode {
+				BlendMode::AlphaBlended => (&self.blend_pipeline_soup, &self.blend_pipeline_strip),
+				BlendMode::Additive => (&self.additive_pipeline_soup, &self.additive_pipeline_strip),
+				BlendMode::Opaque | BlendMode::AlphaTested => unreachable!(),
+			};
+			let topology = batch.mesh_data.topology();
+			let pipeline = match topology_pipeline(topology, soup, strip) {
+				Some(pipeline) => pipeline,
+				None => continue,
+			};
+			let transform = &batch.transforms[0];
+			let pc = geom_vshader::ty::PushConsts {
+				cam_proj: cam.projection().into(),
+				cam_pos: cam.transform().pos.into(),
+				cam_rot: cam.transform().rot.into(),
+				mesh_pos: transform.pos.into(),
+				mesh_rot: transform.rot.into(),
+				lightmap: batch.lightmap,
+				alpha_test: batch.alpha_test,
+			};
+			let vertex_buffers = vec![batch.mesh_data.vertices().clone()];
+			let sets = (batch.desc.clone(), lights_desc.clone(), skybox.clone());
+			counts.add_indexed(topology, batch.range.len());
+			command_buffer = draw_indexed(command_buffer, pipeline, vertex_buffers, batch, sets, pc);
+		}
+
+		// the swap subpass is recorded into a secondary command buffer, so the lighting timestamp can be written there
+		let swap_subpass = Subpass::from(self.ctx.render_pass.clone(), 3).unwrap();
+		command_buffer = command_buffer.next_subpass(true).unwrap();
+		if let Some(queries) = queries {
+			command_buffer = queries.write(command_buffer, Stamp::Lighting, Some(swap_subpass.clone()));
+		}
+		let swap = AutoCommandBufferBuilder::secondary_graphics_one_time_submit(
+			self.ctx.render_pass.device().clone(),
+			qfam,
+			swap_subpass,
+		)
+		.unwrap()
+		.draw_indexed(
+			self.swap_pipeline.clone(),
+			&Default::default(),
+			vec![self.ctx.vertices.clone()],
+			self.ctx.indices.clone(),
+			(self.targets.swap_desc.clone(), skybox),
+			swap_fshader::ty::PushConsts { inv_proj: cam.inv_proj().into(), cam_rot: cam.transform().rot.into() },
+		)
+		.unwrap()
+		.build()
+		.unwrap();
+		command_buffer = unsafe { command_buffer.execute_commands(swap) }.unwrap();
+
+		command_buffer = command_buffer.end_render_pass().unwrap();
+		counts.add_draw(2);
+		if let Some(fxaa) = &self.targets.fxaa {
+			command_buffer = fxaa.draw(command_buffer, image_num);
+			counts.add_draw(1);
+		}
+
+		(command_buffer.build().unwrap(), counts)
 	}
 
 	fn resize(&mut self, images: Vec<Arc<dyn ImageViewAccess + Send + Sync>>, dimensions: [u32; 2]) {
 		let (geom_pipeline_soup, geom_pipeline_strip) =
-			create_geom_pipelines(&self.ctx.geom_vshader, &self.ctx.geom_fshader, &self.ctx.render_pass, dimensions);
+			create_geom_pipelines(&self.ctx.geom_vshader, &self.ctx.geom_fshader, &self.ctx.geom_pass, dimensions);
 		self.geom_pipeline_soup = geom_pipeline_soup;
 		self.geom_pipeline_strip = geom_pipeline_strip;
+		let (geom_pipeline_soup_instanced, geom_pipeline_strip_instanced) = create_instanced_geom_pipelines(
+			&self.ctx.geom_instanced_vshader,
+			&self.ctx.geom_fshader,
+			&self.ctx.geom_pass,
+			dimensions,
+		);
+		self.geom_pipeline_soup_instanced = geom_pipeline_soup_instanced;
+		self.geom_pipeline_strip_instanced = geom_pipeline_strip_instanced;
+		let (geom_pipeline_soup_indirect, geom_pipeline_strip_indirect) = create_indirect_geom_pipelines(
+			&self.ctx.geom_indirect_vshader,
+			&self.ctx.geom_fshader,
+			&self.ctx.geom_pass,
+			dimensions,
+		);
+		self.geom_pipeline_soup_indirect = geom_pipeline_soup_indirect;
+		self.geom_pipeline_strip_indirect = geom_pipeline_strip_indirect;
+
+		self.field_pipeline = create_field_pipeline(
+			&self.ctx.field_vshader,
+			&self.ctx.field_fshader,
+			self.ctx.geom_pass.clone(),
+			dimensions,
+		);
+		let (blend_pipeline_soup, blend_pipeline_strip) = create_transparent_pipelines(
+			&self.ctx.geom_vshader,
+			&self.ctx.transparent_fshader,
+			&self.ctx.render_pass,
+			dimensions,
+			BlendMode::AlphaBlended,
+		);
+		self.blend_pipeline_soup = blend_pipeline_soup;
+		self.blend_pipeline_strip = blend_pipeline_strip;
+		let (additive_pipeline_soup, additive_pipeline_strip) = create_transparent_pipelines(
+			&self.ctx.geom_vshader,
+			&self.ctx.transparent_fshader,
+			&self.ctx.render_pass,
+			dimensions,
+			BlendMode::Additive,
+		);
+		self.additive_pipeline_soup = additive_pipeline_soup;
+		self.additive_pipeline_strip = additive_pipeline_strip;
 
 		self.light_pipeline = create_light_pipeline(
 			&self.ctx.light_vshader,
@@ -189,6 +504,12 @@ impl Pipeline for DeferredPipeline {
 			self.ctx.render_pass.clone(),
 			dimensions,
 		);
+		self.ssao_apply_pipeline = create_ssao_apply_pipeline(
+			&self.ctx.light_vshader,
+			&self.ctx.ssao_apply_fshader,
+			self.ctx.render_pass.clone(),
+			dimensions,
+		);
 		self.swap_pipeline = create_swap_pipeline(
 			&self.ctx.swap_vshader,
 			&self.ctx.swap_fshader,
@@ -196,12 +517,107 @@ impl Pipeline for DeferredPipeline {
 			dimensions,
 		);
 
-		let gbuffers = create_gbuffers(self.ctx.render_pass.device(), dimensions);
-		self.framebuffers = create_framebuffers(&self.ctx.render_pass, &gbuffers, images);
-		self.gbuffers_desc = make_gbuffers_desc(self.ctx.swap_layout_desc.clone(), &gbuffers);
+		self.targets = create_targets(&self.ctx, &images, dimensions, self.aa, self.ao, &self.ssao_apply_pipeline);
+		self.images = images;
+		self.dimensions = dimensions;
+	}
+
+	fn set_anti_aliasing(&mut self, aa: AntiAliasing) {
+		if aa != self.aa {
+			self.aa = aa;
+			self.targets =
+				create_targets(&self.ctx, &self.images, self.dimensions, aa, self.ao, &self.ssao_apply_pipeline);
+		}
+	}
+
+	fn set_ambient_occlusion(&mut self, ao: AmbientOcclusion) {
+		if ao != self.ao {
+			self.ao = ao;
+			self.targets =
+				create_targets(&self.ctx, &self.images, self.dimensions, self.aa, ao, &self.ssao_apply_pipeline);
+		}
+	}
+}
+
+/// Everything that depends on the swapchain images and the post processing settings.
+struct Targets {
+	geom_framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+	framebuffers: Vec<Arc<dyn FramebufferAbstract + Send + Sync>>,
+	/// The G-buffers read by the light subpass.
+	light_desc: Arc<dyn DescriptorSet + Send + Sync>,
+	/// The depth and light buffers read by the swap subpass.
+	swap_desc: Arc<dyn DescriptorSet + Send + Sync>,
+	fxaa: Option<Fxaa>,
+	ssao: Option<(Ssao, Arc<dyn DescriptorSet + Send + Sync>)>,
+}
+
+fn create_targets(
+	ctx: &DeferredPipelineContextInner,
+	images: &[Arc<dyn ImageViewAccess + Send + Sync>],
+	dimensions: [u32; 2],
+	aa: AntiAliasing,
+	ao: AmbientOcclusion,
+	ssao_apply_pipeline: &Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+) -> Targets {
+	let fxaa = match aa {
+		AntiAliasing::None => None,
+		AntiAliasing::Fxaa => Some(Fxaa::new(&ctx.fxaa, images.to_vec(), dimensions)),
+	};
+	// with a post pass, the scene is rendered into the same intermediate image every frame instead of the swapchain
+	let targets = match &fxaa {
+		Some(fxaa) => vec![fxaa.target().clone(); images.len()],
+		None => images.to_vec(),
+	};
+
+	let gbuffers = create_gbuffers(ctx.render_pass.device(), dimensions);
+	let ssao = match ao {
+		AmbientOcclusion::None => None,
+		_ => {
+			let ssao = Ssao::new(&ctx.ssao, gbuffers.depth.clone(), gbuffers.normal.clone(), dimensions, ao);
+			let desc: Arc<dyn DescriptorSet + Send + Sync> = Arc::new(
+				PersistentDescriptorSet::start(ssao_apply_pipeline.clone(), 0)
+					.add_sampled_image(ssao.output().clone(), ctx.ssao.sampler().clone())
+					.unwrap()
+					.add_image(gbuffers.ambient.clone())
+					.unwrap()
+					.build()
+					.unwrap(),
+			);
+			Some((ssao, desc))
+		},
+	};
+
+	Targets {
+		geom_framebuffer: create_geom_framebuffer(&ctx.geom_pass, &gbuffers),
+		framebuffers: create_framebuffers(&ctx.render_pass, &gbuffers, targets),
+		light_desc: make_light_desc(ctx.light_layout_desc.clone(), &gbuffers),
+		swap_desc: make_swap_desc(ctx.swap_layout_desc.clone(), &gbuffers),
+		fxaa,
+		ssao,
 	}
 }
 
+fn create_geom_framebuffer(
+	geom_pass: &Arc<dyn RenderPassAbstract + Send + Sync>,
+	gbuffers: &GBuffers,
+) -> Arc<dyn FramebufferAbstract + Send + Sync> {
+	Arc::new(
+		Framebuffer::start(geom_pass.clone())
+			.add(gbuffers.depth.clone())
+			.unwrap()
+			.add(gbuffers.diffuse.clone())
+			.unwrap()
+			.add(gbuffers.normal.clone())
+			.unwrap()
+			.add(gbuffers.light.clone())
+			.unwrap()
+			.add(gbuffers.ambient.clone())
+			.unwrap()
+			.build()
+			.unwrap(),
+	)
+}
+
 fn create_framebuffers(
 	swap_pass: &Arc<dyn RenderPassAbstract + Send + Sync>,
 	gbuffers: &GBuffers,
@@ -218,10 +634,10 @@ fn create_framebuffers(
 					.unwrap()
 					.add(gbuffers.normal.clone())
 					.unwrap()
-					.add(gbuffers.position.clone())
-					.unwrap()
 					.add(gbuffers.light.clone())
 					.unwrap()
+					.add(gbuffers.ambient.clone())
+					.unwrap()
 					.add(image)
 					.unwrap()
 					.build()
@@ -232,26 +648,25 @@ fn create_framebuffers(
 }
 
 fn create_gbuffers(device: &Arc<Device>, dimensions: [u32; 2]) -> GBuffers {
-	let depth =
-		Arc::new(AttachmentImage::transient_input_attachment(device.clone(), dimensions, DEPTH_FORMAT).unwrap());
-	let diffuse =
-		Arc::new(AttachmentImage::transient_input_attachment(device.clone(), dimensions, COLOR_FORMAT).unwrap());
-	let normal =
-		Arc::new(AttachmentImage::transient_input_attachment(device.clone(), dimensions, NORMAL_FORMAT).unwrap());
-	let position =
-		Arc::new(AttachmentImage::transient_input_attachment(device.clone(), dimensions, POSITION_FORMAT).unwrap());
-	let light =
-		Arc::new(AttachmentImage::transient_input_attachment(device.clone(), dimensions, LIGHT_FORMAT).unwrap());
-
-	GBuffers { diffuse, normal, depth, position, light }
+	// depth and normals are also sampled by SSAO
+	let usage = ImageUsage { input_attachment: true, ..ImageUsage::none() };
+	let sampled = ImageUsage { sampled: true, ..usage };
+	let depth = AttachmentImage::with_usage(device.clone(), dimensions, DEPTH_FORMAT, sampled).unwrap();
+	let diffuse = AttachmentImage::with_usage(device.clone(), dimensions, COLOR_FORMAT, usage).unwrap();
+	let normal = AttachmentImage::with_usage(device.clone(), dimensions, NORMAL_FORMAT, sampled).unwrap();
+	let light = AttachmentImage::with_usage(device.clone(), dimensions, LIGHT_FORMAT, usage).unwrap();
+	let ambient = AttachmentImage::with_usage(device.clone(), dimensions, AMBIENT_FORMAT, usage).unwrap();
+
+	GBuffers { diffuse, normal, depth, light, ambient }
 }
 
 struct GBuffers {
 	depth: Arc<dyn ImageViewAccess + Send + Sync>,
 	diffuse: Arc<dyn ImageViewAccess + Send + Sync>,
 	normal: Arc<dyn ImageViewAccess + Send + Sync>,
-	position: Arc<dyn ImageViewAccess + Send + Sync>,
 	light: Arc<dyn ImageViewAccess + Send + Sync>,
+	/// The part of `light` that SSAO darkens.
+	ambient: Arc<dyn ImageViewAccess + Send + Sync>,
 }
 
 fn create_geom_pipelines(
@@ -290,6 +705,144 @@ fn create_geom_pipeline(
 	)
 }
 
+fn create_instanced_geom_pipelines(
+	vshader: &geom_instanced_vshader::Shader,
+	fshader: &geom_fshader::Shader,
+	render_pass: &Arc<dyn RenderPassAbstract + Send + Sync>,
+	dimensions: [u32; 2],
+) -> (Arc<dyn GraphicsPipelineAbstract + Send + Sync>, Arc<dyn GraphicsPipelineAbstract + Send + Sync>) {
+	(
+		create_instanced_geom_pipeline(vshader, fshader, render_pass, dimensions, PrimitiveTopology::TriangleList),
+		create_instanced_geom_pipeline(vshader, fshader, render_pass, dimensions, PrimitiveTopology::TriangleStrip),
+	)
+}
+
+/// Like the geometry pipeline, but with mesh transforms read from a per-instance vertex buffer.
+fn create_instanced_geom_pipeline(
+	vshader: &geom_instanced_vshader::Shader,
+	fshader: &geom_fshader::Shader,
+	render_pass: &Arc<dyn RenderPassAbstract + Send + Sync>,
+	dimensions: [u32; 2],
+	topology: PrimitiveTopology,
+) -> Arc<dyn GraphicsPipelineAbstract + Send + Sync> {
+	let dimensions = [dimensions[0] as f32, dimensions[1] as f32];
+	let device = render_pass.device().clone();
+	Arc::new(
+		GraphicsPipeline::start()
+			.vertex_input(OneVertexOneInstanceDefinition::<Pntl_32F, InstanceTransform>::new())
+			.vertex_shader(vshader.main_entry_point(), ())
+			.fragment_shader(fshader.main_entry_point(), ())
+			.primitive_topology(topology)
+			.cull_mode_back()
+			.viewports(vec![Viewport { origin: [0.0, 0.0], dimensions, depth_range: 0.0..1.0 }])
+			.render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
+			.depth_stencil_simple_depth()
+			.build(device)
+			.unwrap(),
+	)
+}
+
+fn create_indirect_geom_pipelines(
+	vshader: &geom_indirect_vshader::Shader,
+	fshader: &geom_fshader::Shader,
+	render_pass: &Arc<dyn RenderPassAbstract + Send + Sync>,
+	dimensions: [u32; 2],
+) -> (Arc<dyn GraphicsPipelineAbstract + Send + Sync>, Arc<dyn GraphicsPipelineAbstract + Send + Sync>) {
+	(
+		create_indirect_geom_pipeline(vshader, fshader, render_pass, dimensions, PrimitiveTopology::TriangleList),
+		create_indirect_geom_pipeline(vshader, fshader, render_pass, dimensions, PrimitiveTopology::TriangleStrip),
+	)
+}
+
+/// Like the geometry pipeline, but with mesh transforms read from the mesh group's storage buffer.
+fn create_indirect_geom_pipeline(
+	vshader: &geom_indirect_vshader::Shader,
+	fshader: &geom_fshader::Shader,
+	render_pass: &Arc<dyn RenderPassAbstract + Send + Sync>,
+	dimensions: [u32; 2],
+	topology: PrimitiveTopology,
+) -> Arc<dyn GraphicsPipelineAbstract + Send + Sync> {
+	let dimensions = [dimensions[0] as f32, dimensions[1] as f32];
+	let device = render_pass.device().clone();
+	Arc::new(
+		GraphicsPipeline::start()
+			.vertex_input_single_buffer::<Pntl_32F>()
+			.vertex_shader(vshader.main_entry_point(), ())
+			.fragment_shader(fshader.main_entry_point(), ())
+			.primitive_topology(topology)
+			.cull_mode_back()
+			.viewports(vec![Viewport { origin: [0.0, 0.0], dimensions, depth_range: 0.0..1.0 }])
+			.render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
+			.depth_stencil_simple_depth()
+			.build(device)
+			.unwrap(),
+	)
+}
+
+/// Draws the bounding box of a field with both sides visible, so it can be ray marched from inside or outside.
+fn create_field_pipeline(
+	vshader: &field_vshader::Shader,
+	fshader: &field_fshader::Shader,
+	render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+	dimensions: [u32; 2],
+) -> Arc<dyn GraphicsPipelineAbstract + Send + Sync> {
+	let dimensions = [dimensions[0] as f32, dimensions[1] as f32];
+	let device = render_pass.device().clone();
+	Arc::new(
+		GraphicsPipeline::start()
+			.vertex_input_single_buffer::<Vert3D>()
+			.vertex_shader(vshader.main_entry_point(), ())
+			.fragment_shader(fshader.main_entry_point(), ())
+			.triangle_list()
+			.cull_mode_disabled()
+			.viewports(vec![Viewport { origin: [0.0, 0.0], dimensions, depth_range: 0.0..1.0 }])
+			.render_pass(Subpass::from(render_pass, 0).unwrap())
+			.depth_stencil_simple_depth()
+			.build(device)
+			.unwrap(),
+	)
+}
+
+fn create_transparent_pipelines(
+	vshader: &geom_vshader::Shader,
+	fshader: &transparent_fshader::Shader,
+	render_pass: &Arc<dyn RenderPassAbstract + Send + Sync>,
+	dimensions: [u32; 2],
+	blend_mode: BlendMode,
+) -> (Arc<dyn GraphicsPipelineAbstract + Send + Sync>, Arc<dyn GraphicsPipelineAbstract + Send + Sync>) {
+	let create =
+		|topology| create_transparent_pipeline(vshader, fshader, render_pass, dimensions, blend_mode, topology);
+	(create(PrimitiveTopology::TriangleList), create(PrimitiveTopology::TriangleStrip))
+}
+
+/// Like the geometry pipeline, but blended into the light buffer with forward lighting, and tested against the depth
+/// buffer without writing to it.
+fn create_transparent_pipeline(
+	vshader: &geom_vshader::Shader,
+	fshader: &transparent_fshader::Shader,
+	render_pass: &Arc<dyn RenderPassAbstract + Send + Sync>,
+	dimensions: [u32; 2],
+	blend_mode: BlendMode,
+	topology: PrimitiveTopology,
+) -> Arc<dyn GraphicsPipelineAbstract + Send + Sync> {
+	let dimensions = [dimensions[0] as f32, dimensions[1] as f32];
+	let device = render_pass.device().clone();
+	Arc::new(
+		GraphicsPipeline::start()
+			.vertex_input_single_buffer::<Pntl_32F>()
+			.vertex_shader(vshader.main_entry_point(), ())
+			.fragment_shader(fshader.main_entry_point(), ())
+			.primitive_topology(topology)
+			.cull_mode_back()
+			.blend_collective(transparent_blend(blend_mode))
+			.viewports(vec![Viewport { origin: [0.0, 0.0], dimensions, depth_range: 0.0..1.0 }])
+			.render_pass(Subpass::from(render_pass.clone(), 2).unwrap())
+			.depth_stencil(DepthStencil { depth_write: false, ..DepthStencil::simple_depth_test() })
+			.build(device)
+			.unwrap(),
+	)
+}
+
 fn create_swap_pipeline(
 	vshader: &swap_vshader::Shader,
 	fshader: &swap_fshader::Shader,
@@ -305,7 +858,7 @@ fn create_swap_pipeline(
 			.fragment_shader(fshader.main_entry_point(), ())
 			.triangle_list()
 			.viewports(vec![Viewport { origin: [0.0, 0.0], dimensions, depth_range: 0.0..1.0 }])
-			.render_pass(Subpass::from(render_pass, 2).unwrap())
+			.render_pass(Subpass::from(render_pass, 3).unwrap())
 			.build(device)
 			.unwrap(),
 	)
@@ -338,14 +891,60 @@ fn create_light_pipeline(
 				mask_blue: true,
 				mask_alpha: true,
 			})
-			.viewports(vec![Viewport { origin: [0.0, 0.0], dimensions, depth_range: 0.0..1.0 }])
+			.viewports_fixed_scissors_dynamic(vec![Viewport { origin: [0.0, 0.0], dimensions, depth_range: 0.0..1.0 }])
 			.render_pass(Subpass::from(render_pass, 1).unwrap())
 			.build(device)
 			.unwrap(),
 	)
 }
 
-fn make_gbuffers_desc<L>(layout: L, gbuffers: &GBuffers) -> Arc<dyn DescriptorSet + Send + Sync>
+fn create_ssao_apply_pipeline(
+	vshader: &light_vshader::Shader,
+	fshader: &ssao_apply_fshader::Shader,
+	render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+	dimensions: [u32; 2],
+) -> Arc<dyn GraphicsPipelineAbstract + Send + Sync> {
+	let dimensions = [dimensions[0] as f32, dimensions[1] as f32];
+	let device = render_pass.device().clone();
+	Arc::new(
+		GraphicsPipeline::start()
+			.vertex_input_single_buffer::<Vert2D>()
+			.vertex_shader(vshader.main_entry_point(), ())
+			.fragment_shader(fshader.main_entry_point(), ())
+			.triangle_list()
+			// subtracts the occluded ambient light from the light buffer's color, and leaves the alpha alone
+			.blend_collective(AttachmentBlend {
+				enabled: true,
+				color_op: BlendOp::ReverseSubtract,
+				color_source: BlendFactor::One,
+				color_destination: BlendFactor::One,
+				alpha_op: BlendOp::Add,
+				alpha_source: BlendFactor::Zero,
+				alpha_destination: BlendFactor::One,
+				mask_red: true,
+				mask_green: true,
+				mask_blue: true,
+				mask_alpha: false,
+			})
+			.viewports(vec![Viewport { origin: [0.0, 0.0], dimensions, depth_range: 0.0..1.0 }])
+			.render_pass(Subpass::from(render_pass, 0).unwrap())
+			.build(device)
+			.unwrap(),
+	)
+}
+
+/// The pixels covered by `bounds`, rounded outwards.
+fn scissor(bounds: &LightBounds, dimensions: [u32; 2]) -> Scissor {
+	let to_pixels = |ndc: f32, size: u32| ((ndc * 0.5 + 0.5) * size as f32).max(0.0).min(size as f32);
+	let min = [to_pixels(bounds.min.x, dimensions[0]).floor(), to_pixels(bounds.min.y, dimensions[1]).floor()];
+	let max = [to_pixels(bounds.max.x, dimensions[0]).ceil(), to_pixels(bounds.max.y, dimensions[1]).ceil()];
+	Scissor {
+		origin: [min[0] as i32, min[1] as i32],
+		dimensions: [(max[0] - min[0]) as u32, (max[1] - min[1]) as u32],
+	}
+}
+
+fn make_light_desc<L>(layout: L, gbuffers: &GBuffers) -> Arc<dyn DescriptorSet + Send + Sync>
 where
 	L: PipelineLayoutAbstract + Send + Sync + 'static,
 {
@@ -357,7 +956,18 @@ where
 			.unwrap()
 			.add_image(gbuffers.normal.clone())
 			.unwrap()
-			.add_image(gbuffers.position.clone())
+			.build()
+			.unwrap(),
+	)
+}
+
+fn make_swap_desc<L>(layout: L, gbuffers: &GBuffers) -> Arc<dyn DescriptorSet + Send + Sync>
+where
+	L: PipelineLayoutAbstract + Send + Sync + 'static,
+{
+	Arc::new(
+		PersistentDescriptorSet::start(layout, 0)
+			.add_image(gbuffers.depth.clone())
 			.unwrap()
 			.add_image(gbuffers.light.clone())
 			.unwrap()
@@ -0,0 +1,37 @@
+mod context;
+mod pipeline;
+
+use self::context::ForwardPipelineContext;
+use crate::pipelines::{PipelineContext, PipelineDef};
+use std::sync::Arc;
+use vulkano::{
+	device::{Device, Queue},
+	format::Format,
+	sync::GpuFuture,
+};
+
+const DEPTH_FORMAT: Format = Format::D32Sfloat;
+const LIGHT_FORMAT: Format = Format::R16G16B16A16Sfloat;
+
+/// Shades up to 32 lights per fragment while drawing geometry. It needs no G-buffer, so it's much cheaper than
+/// `DeferredPipelineDef` on integrated and low-memory GPUs. Meshes with field data are not drawn.
+pub struct ForwardPipelineDef;
+impl PipelineDef for ForwardPipelineDef {
+	fn make_context(device: &Arc<Device>, queue: &Arc<Queue>) -> (Box<dyn PipelineContext>, Box<dyn GpuFuture>) {
+		let (pctx, future) = ForwardPipelineContext::new(device, queue);
+		(Box::new(pctx), Box::new(future))
+	}
+}
+
+mod geom_vshader {
+	vulkano_shaders::shader! { ty: "vertex", path: "src/pipelines/shaders/geom.glslv" }
+}
+mod geom_fshader {
+	vulkano_shaders::shader! { ty: "fragment", path: "src/pipelines/shaders/forward.glslf" }
+}
+mod swap_vshader {
+	vulkano_shaders::shader! { ty: "vertex", path: "src/pipelines/shaders/swap.glslv" }
+}
+mod swap_fshader {
+	vulkano_shaders::shader! { ty: "fragment", path: "src/pipelines/shaders/forward_swap.glslf" }
+}
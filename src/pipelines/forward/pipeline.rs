@@ -0,0 +1,488 @@
+use super::{
+	context::ForwardPipelineContextInner, geom_fshader, geom_vshader, swap_fshader, swap_vshader, DEPTH_FORMAT,
+	LIGHT_FORMAT,
+};
+use crate::{
+	camera::Camera,
+	direct_light::DirectLight,
+	frame_stats::DrawCounts,
+	gpu_timer::{FrameQueries, Stamp},
+	mesh::{BlendMode, MeshInner},
+	mesh_data::{IndexBuffer, MeshData, Pntl_32F},
+	pipelines::{
+		fxaa::Fxaa, topology_pipeline, transparent_blend, AmbientOcclusion, AntiAliasing, Pipeline, Vert2D, MAX_LIGHTS,
+	},
+};
+use cgmath::prelude::*;
+use std::sync::Arc;
+use vulkano::{
+	command_buffer::{AutoCommandBuffer, AutoCommandBufferBuilder},
+	descriptor::{
+		descriptor_set::{DescriptorSetsCollection, PersistentDescriptorSet},
+		DescriptorSet, PipelineLayoutAbstract,
+	},
+	device::Device,
+	framebuffer::{Framebuffer, FramebufferAbstract, RenderPassAbstract, Subpass},
+	image::{AttachmentImage, ImageViewAccess},
+	instance::QueueFamily,
+	pipeline::{
+		depth_stencil::DepthStencil, input_assembly::PrimitiveTopology, viewport::Viewport, GraphicsPipeline,
+		GraphicsPipelineAbstract,
+	},
+};
+
+pub(super) struct ForwardPipeline {
+	ctx: Arc<ForwardPipelineContextInner>,
+	geom_pipeline_soup: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	geom_pipeline_strip: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	blend_pipeline_soup: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	blend_pipeline_strip: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	additive_pipeline_soup: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	additive_pipeline_strip: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	swap_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	images: Vec<Arc<dyn ImageViewAccess + Send + Sync>>,
+	framebuffers: Vec<Arc<dyn FramebufferAbstract + Send + Sync>>,
+	gbuffers_desc: Arc<dyn DescriptorSet + Send + Sync>,
+	aa: AntiAliasing,
+	fxaa: Option<Fxaa>,
+	dimensions: [u32; 2],
+}
+impl ForwardPipeline {
+	pub(super) fn new(
+		ctx: Arc<ForwardPipelineContextInner>,
+		images: Vec<Arc<dyn ImageViewAccess + Send + Sync>>,
+		dimensions: [u32; 2],
+	) -> Self {
+		let (geom_pipeline_soup, geom_pipeline_strip) =
+			create_geom_pipelines(&ctx.geom_vshader, &ctx.geom_fshader, &ctx.render_pass, dimensions);
+		let (blend_pipeline_soup, blend_pipeline_strip) = create_transparent_pipelines(
+			&ctx.geom_vshader,
+			&ctx.geom_fshader,
+			&ctx.render_pass,
+			dimensions,
+			BlendMode::AlphaBlended,
+		);
+		let (additive_pipeline_soup, additive_pipeline_strip) = create_transparent_pipelines(
+			&ctx.geom_vshader,
+			&ctx.geom_fshader,
+			&ctx.render_pass,
+			dimensions,
+			BlendMode::Additive,
+		);
+		let swap_pipeline =
+			create_swap_pipeline(&ctx.swap_vshader, &ctx.swap_fshader, ctx.render_pass.clone(), dimensions);
+
+		let aa = AntiAliasing::default();
+		let (framebuffers, gbuffers_desc, fxaa) = create_targets(&ctx, &images, dimensions, aa);
+
+		Self {
+			ctx,
+			geom_pipeline_soup,
+			geom_pipeline_strip,
+			blend_pipeline_soup,
+			blend_pipeline_strip,
+			additive_pipeline_soup,
+			additive_pipeline_strip,
+			swap_pipeline,
+			images,
+			framebuffers,
+			gbuffers_desc,
+			aa,
+			fxaa,
+			dimensions,
+		}
+	}
+
+	fn make_lights_desc(&self, cam: &Camera, lights: &[DirectLight]) -> Arc<dyn DescriptorSet + Send + Sync> {
+		let empty = geom_fshader::ty::Light { position: [0.0; 4], color: [0.0; 4] };
+		let mut data = geom_fshader::ty::Lights {
+			cam_pos: cam.transform().pos.into(),
+			count: [lights.len().min(MAX_LIGHTS) as u32, 0, 0, 0],
+			lights: [empty; MAX_LIGHTS],
+		};
+		for (dst, light) in data.lights.iter_mut().zip(lights) {
+			let (position, color) = light.packed();
+			*dst = geom_fshader::ty::Light { position, color };
+		}
+
+		Arc::new(
+			PersistentDescriptorSet::start(self.ctx.layout_desc.clone(), 1)
+				.add_buffer(self.ctx.lights_pool.next(data).unwrap())
+				.unwrap()
+				.build()
+				.unwrap(),
+		)
+	}
+}
+impl Pipeline for ForwardPipeline {
+	fn draw(
+		&self,
+		image_num: usize,
+		qfam: QueueFamily,
+		cam: &Camera,
+		lights: &[DirectLight],
+		queries: Option<&FrameQueries>,
+	) -> (AutoCommandBuffer, DrawCounts) {
+		let mut counts = DrawCounts::default();
+		let clear_values = vec![1.0.into(), [0.0; 4].into(), [0.0; 4].into()];
+
+		let make_pc = |mesh: &MeshInner| geom_vshader::ty::PushConsts {
+			cam_proj: cam.projection().into(),
+			cam_pos: cam.transform().pos.into(),
+			cam_rot: cam.transform().rot.into(),
+			mesh_pos: mesh.transform().pos.into(),
+			mesh_rot: mesh.transform().rot.into(),
+			lightmap: mesh.lightmap_mode(),
+			alpha_test: mesh.blend_mode().alpha_test(),
+		};
+
+		let lights_desc = self.make_lights_desc(cam, lights);
+		counts.lights = lights.len().min(MAX_LIGHTS) as u32;
+		let skybox = cam.mesh_group().skybox();
+
+		let mut command_buffer =
+			AutoCommandBufferBuilder::primary_one_time_submit(self.ctx.render_pass.device().clone(), qfam)
+				.unwrap()
+				.begin_render_pass(self.framebuffers[image_num].clone(), false, clear_values)
+				.unwrap();
+		let meshes = cam.mesh_group().meshes().lock().unwrap();
+		let mut transparent = vec![];
+		for mesh_lock in meshes.values() {
+			let mesh = MeshInner::read_refreshed(mesh_lock, cam.transform().pos);
+
+			if mesh.field_data().is_some() {
+				continue;
+			}
+			let mesh_data = if let Some(mesh_data) = mesh.mesh_data() { mesh_data } else { continue };
+			if mesh.blend_mode().is_transparent() {
+				let distance = (mesh.transform().pos - cam.transform().pos).truncate().magnitude();
+				transparent.push((distance, mesh_lock));
+				continue;
+			}
+
+			let (soup, strip) = (&self.geom_pipeline_soup, &self.geom_pipeline_strip);
+			let pipeline = match topology_pipeline(mesh_data.topology(), soup, strip) {
+				Some(pipeline) => pipeline,
+				None => continue,
+			};
+			let sets = (mesh.desc().clone(), lights_desc.clone(), skybox.clone());
+			counts.add_indexed(mesh_data.topology(), mesh.range().len());
+			command_buffer = draw_mesh(command_buffer, pipeline, &mesh, mesh_data, sets, make_pc(&mesh));
+		}
+
+		// transparent meshes are drawn from back to front after everything opaque
+		transparent.sort_by(|(lhs, _), (rhs, _)| rhs.partial_cmp(lhs).unwrap());
+		for (_, mesh) in transparent {
+			let mesh = mesh.read().unwrap();
+			let mesh_data = mesh.mesh_data().unwrap();
+			let (soup, strip) = match mesh.blend_mode() {
+				BlendMode::AlphaBlended => (&self.blend_pipeline_soup, &self.blend_pipeline_strip),
+				BlendMode::Additive => (&self.additive_pipeline_soup, &self.additive_pipeline_strip),
+				BlendMode::Opaque | BlendMode::AlphaTested => unreachable!(),
+			};
+			let pipeline = match topology_pipeline(mesh_data.topology(), soup, strip) {
+				Some(pipeline) => pipeline,
+				None => continue,
+			};
+			let sets = (mesh.desc().clone(), lights_desc.clone(), skybox.clone());
+			counts.add_indexed(mesh_data.topology(), mesh.range().len());
+			command_buffer = draw_mesh(command_buffer, pipeline, &mesh, mesh_data, sets, make_pc(&mesh));
+		}
+		drop(meshes);
+
+		// the swap subpass is recorded into a secondary command buffer, so timestamps can be written there. geometry
+		// is lit as it's drawn, so both are written at once.
+		let swap_subpass = Subpass::from(self.ctx.render_pass.clone(), 1).unwrap();
+		command_buffer = command_buffer.next_subpass(true).unwrap();
+		if let Some(queries) = queries {
+			command_buffer = queries.write(command_buffer, Stamp::Geometry, Some(swap_subpass.clone()));
+			command_buffer = queries.write(command_buffer, Stamp::Lighting, Some(swap_subpass.clone()));
+		}
+		let swap = AutoCommandBufferBuilder::secondary_graphics_one_time_submit(
+			self.ctx.render_pass.device().clone(),
+			qfam,
+			swap_subpass,
+		)
+		.unwrap()
+		.draw_indexed(
+			self.swap_pipeline.clone(),
+			&Default::default(),
+			vec![self.ctx.vertices.clone()],
+			self.ctx.indices.clone(),
+			(self.gbuffers_desc.clone(), skybox),
+			swap_fshader::ty::PushConsts { inv_proj: cam.inv_proj().into(), cam_rot: cam.transform().rot.into() },
+		)
+		.unwrap()
+		.build()
+		.unwrap();
+		command_buffer = unsafe { command_buffer.execute_commands(swap) }.unwrap();
+
+		command_buffer = command_buffer.end_render_pass().unwrap();
+		counts.add_draw(2);
+		if let Some(fxaa) = &self.fxaa {
+			command_buffer = fxaa.draw(command_buffer, image_num);
+			counts.add_draw(1);
+		}
+
+		(command_buffer.build().unwrap(), counts)
+	}
+
+	fn resize(&mut self, images: Vec<Arc<dyn ImageViewAccess + Send + Sync>>, dimensions: [u32; 2]) {
+		let (geom_pipeline_soup, geom_pipeline_strip) =
+			create_geom_pipelines(&self.ctx.geom_vshader, &self.ctx.geom_fshader, &self.ctx.render_pass, dimensions);
+		self.geom_pipeline_soup = geom_pipeline_soup;
+		self.geom_pipeline_strip = geom_pipeline_strip;
+		let (blend_pipeline_soup, blend_pipeline_strip) = create_transparent_pipelines(
+			&self.ctx.geom_vshader,
+			&self.ctx.geom_fshader,
+			&self.ctx.render_pass,
+			dimensions,
+			BlendMode::AlphaBlended,
+		);
+		self.blend_pipeline_soup = blend_pipeline_soup;
+		self.blend_pipeline_strip = blend_pipeline_strip;
+		let (additive_pipeline_soup, additive_pipeline_strip) = create_transparent_pipelines(
+			&self.ctx.geom_vshader,
+			&self.ctx.geom_fshader,
+			&self.ctx.render_pass,
+			dimensions,
+			BlendMode::Additive,
+		);
+		self.additive_pipeline_soup = additive_pipeline_soup;
+		self.additive_pipeline_strip = additive_pipeline_strip;
+
+		self.swap_pipeline = create_swap_pipeline(
+			&self.ctx.swap_vshader,
+			&self.ctx.swap_fshader,
+			self.ctx.render_pass.clone(),
+			dimensions,
+		);
+
+		let (framebuffers, gbuffers_desc, fxaa) = create_targets(&self.ctx, &images, dimensions, self.aa);
+		self.images = images;
+		self.framebuffers = framebuffers;
+		self.gbuffers_desc = gbuffers_desc;
+		self.fxaa = fxaa;
+		self.dimensions = dimensions;
+	}
+
+	fn set_anti_aliasing(&mut self, aa: AntiAliasing) {
+		if aa != self.aa {
+			self.aa = aa;
+			let (framebuffers, gbuffers_desc, fxaa) = create_targets(&self.ctx, &self.images, self.dimensions, aa);
+			self.framebuffers = framebuffers;
+			self.gbuffers_desc = gbuffers_desc;
+			self.fxaa = fxaa;
+		}
+	}
+
+	/// There's no G-buffer to compute it from, so only each mesh's baked ambient occlusion is used.
+	fn set_ambient_occlusion(&mut self, _ao: AmbientOcclusion) {}
+}
+
+fn create_targets(
+	ctx: &ForwardPipelineContextInner,
+	images: &[Arc<dyn ImageViewAccess + Send + Sync>],
+	dimensions: [u32; 2],
+	aa: AntiAliasing,
+) -> (Vec<Arc<dyn FramebufferAbstract + Send + Sync>>, Arc<dyn DescriptorSet + Send + Sync>, Option<Fxaa>) {
+	let fxaa = match aa {
+		AntiAliasing::None => None,
+		AntiAliasing::Fxaa => Some(Fxaa::new(&ctx.fxaa, images.to_vec(), dimensions)),
+	};
+	// with a post pass, the scene is rendered into the same intermediate image every frame instead of the swapchain
+	let targets = match &fxaa {
+		Some(fxaa) => vec![fxaa.target().clone(); images.len()],
+		None => images.to_vec(),
+	};
+
+	let gbuffers = create_gbuffers(ctx.render_pass.device(), dimensions);
+	let framebuffers = create_framebuffers(&ctx.render_pass, &gbuffers, targets);
+	let gbuffers_desc = make_gbuffers_desc(ctx.swap_layout_desc.clone(), &gbuffers);
+	(framebuffers, gbuffers_desc, fxaa)
+}
+
+fn create_framebuffers(
+	render_pass: &Arc<dyn RenderPassAbstract + Send + Sync>,
+	gbuffers: &GBuffers,
+	images: Vec<Arc<dyn ImageViewAccess + Send + Sync>>,
+) -> Vec<Arc<dyn FramebufferAbstract + Send + Sync>> {
+	images
+		.into_iter()
+		.map(|image| {
+			Arc::new(
+				Framebuffer::start(render_pass.clone())
+					.add(gbuffers.depth.clone())
+					.unwrap()
+					.add(gbuffers.light.clone())
+					.unwrap()
+					.add(image)
+					.unwrap()
+					.build()
+					.unwrap(),
+			) as Arc<dyn FramebufferAbstract + Send + Sync>
+		})
+		.collect()
+}
+
+fn create_gbuffers(device: &Arc<Device>, dimensions: [u32; 2]) -> GBuffers {
+	let depth =
+		Arc::new(AttachmentImage::transient_input_attachment(device.clone(), dimensions, DEPTH_FORMAT).unwrap());
+	let light =
+		Arc::new(AttachmentImage::transient_input_attachment(device.clone(), dimensions, LIGHT_FORMAT).unwrap());
+
+	GBuffers { depth, light }
+}
+
+struct GBuffers {
+	depth: Arc<dyn ImageViewAccess + Send + Sync>,
+	light: Arc<dyn ImageViewAccess + Send + Sync>,
+}
+
+fn create_geom_pipelines(
+	vshader: &geom_vshader::Shader,
+	fshader: &geom_fshader::Shader,
+	render_pass: &Arc<dyn RenderPassAbstract + Send + Sync>,
+	dimensions: [u32; 2],
+) -> (Arc<dyn GraphicsPipelineAbstract + Send + Sync>, Arc<dyn GraphicsPipelineAbstract + Send + Sync>) {
+	(
+		create_geom_pipeline(vshader, fshader, render_pass, dimensions, PrimitiveTopology::TriangleList),
+		create_geom_pipeline(vshader, fshader, render_pass, dimensions, PrimitiveTopology::TriangleStrip),
+	)
+}
+
+fn create_geom_pipeline(
+	vshader: &geom_vshader::Shader,
+	fshader: &geom_fshader::Shader,
+	render_pass: &Arc<dyn RenderPassAbstract + Send + Sync>,
+	dimensions: [u32; 2],
+	topology: PrimitiveTopology,
+) -> Arc<dyn GraphicsPipelineAbstract + Send + Sync> {
+	let dimensions = [dimensions[0] as f32, dimensions[1] as f32];
+	let device = render_pass.device().clone();
+	Arc::new(
+		GraphicsPipeline::start()
+			.vertex_input_single_buffer::<Pntl_32F>()
+			.vertex_shader(vshader.main_entry_point(), ())
+			.fragment_shader(fshader.main_entry_point(), ())
+			.primitive_topology(topology)
+			.cull_mode_back()
+			.viewports(vec![Viewport { origin: [0.0, 0.0], dimensions, depth_range: 0.0..1.0 }])
+			.render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
+			.depth_stencil_simple_depth()
+			.build(device)
+			.unwrap(),
+	)
+}
+
+fn create_transparent_pipelines(
+	vshader: &geom_vshader::Shader,
+	fshader: &geom_fshader::Shader,
+	render_pass: &Arc<dyn RenderPassAbstract + Send + Sync>,
+	dimensions: [u32; 2],
+	blend_mode: BlendMode,
+) -> (Arc<dyn GraphicsPipelineAbstract + Send + Sync>, Arc<dyn GraphicsPipelineAbstract + Send + Sync>) {
+	let create =
+		|topology| create_transparent_pipeline(vshader, fshader, render_pass, dimensions, blend_mode, topology);
+	(create(PrimitiveTopology::TriangleList), create(PrimitiveTopology::TriangleStrip))
+}
+
+/// Like the geometry pipeline, but blended, and tested against the depth buffer without writing to it.
+fn create_transparent_pipeline(
+	vshader: &geom_vshader::Shader,
+	fshader: &geom_fshader::Shader,
+	render_pass: &Arc<dyn RenderPassAbstract + Send + Sync>,
+	dimensions: [u32; 2],
+	blend_mode: BlendMode,
+	topology: PrimitiveTopology,
+) -> Arc<dyn GraphicsPipelineAbstract + Send + Sync> {
+	let dimensions = [dimensions[0] as f32, dimensions[1] as f32];
+	let device = render_pass.device().clone();
+	Arc::new(
+		GraphicsPipeline::start()
+			.vertex_input_single_buffer::<Pntl_32F>()
+			.vertex_shader(vshader.main_entry_point(), ())
+			.fragment_shader(fshader.main_entry_point(), ())
+			.primitive_topology(topology)
+			.cull_mode_back()
+			.blend_collective(transparent_blend(blend_mode))
+			.viewports(vec![Viewport { origin: [0.0, 0.0], dimensions, depth_range: 0.0..1.0 }])
+			.render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
+			.depth_stencil(DepthStencil { depth_write: false, ..DepthStencil::simple_depth_test() })
+			.build(device)
+			.unwrap(),
+	)
+}
+
+fn create_swap_pipeline(
+	vshader: &swap_vshader::Shader,
+	fshader: &swap_fshader::Shader,
+	render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+	dimensions: [u32; 2],
+) -> Arc<dyn GraphicsPipelineAbstract + Send + Sync> {
+	let dimensions = [dimensions[0] as f32, dimensions[1] as f32];
+	let device = render_pass.device().clone();
+	Arc::new(
+		GraphicsPipeline::start()
+			.vertex_input_single_buffer::<Vert2D>()
+			.vertex_shader(vshader.main_entry_point(), ())
+			.fragment_shader(fshader.main_entry_point(), ())
+			.triangle_list()
+			.viewports(vec![Viewport { origin: [0.0, 0.0], dimensions, depth_range: 0.0..1.0 }])
+			.render_pass(Subpass::from(render_pass, 1).unwrap())
+			.build(device)
+			.unwrap(),
+	)
+}
+
+fn make_gbuffers_desc<L>(layout: L, gbuffers: &GBuffers) -> Arc<dyn DescriptorSet + Send + Sync>
+where
+	L: PipelineLayoutAbstract + Send + Sync + 'static,
+{
+	Arc::new(
+		PersistentDescriptorSet::start(layout, 0)
+			.add_image(gbuffers.depth.clone())
+			.unwrap()
+			.add_image(gbuffers.light.clone())
+			.unwrap()
+			.build()
+			.unwrap(),
+	)
+}
+
+fn draw_mesh<S, Pc>(
+	command_buffer: AutoCommandBufferBuilder,
+	pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	mesh: &MeshInner,
+	mesh_data: &MeshData,
+	sets: S,
+	pc: Pc,
+) -> AutoCommandBufferBuilder
+where
+	S: DescriptorSetsCollection,
+{
+	let dynamic = Default::default();
+	let vertex_buffer = vec![mesh_data.vertices().clone()];
+	match mesh_data.indices() {
+		IndexBuffer::U16(buf) => command_buffer
+			.draw_indexed(
+				pipeline,
+				&dynamic,
+				vertex_buffer,
+				buf.clone().into_buffer_slice().slice(mesh.range()).unwrap(),
+				sets,
+				pc,
+			)
+			.unwrap(),
+		IndexBuffer::U32(buf) => command_buffer
+			.draw_indexed(
+				pipeline,
+				&dynamic,
+				vertex_buffer,
+				buf.clone().into_buffer_slice().slice(mesh.range()).unwrap(),
+				sets,
+				pc,
+			)
+			.unwrap(),
+	}
+}
@@ -0,0 +1,144 @@
+use crate::surface::SWAP_FORMAT;
+use std::sync::Arc;
+use vulkano::{
+	command_buffer::AutoCommandBufferBuilder,
+	descriptor::{descriptor_set::PersistentDescriptorSet, DescriptorSet},
+	device::Device,
+	format::ClearValue,
+	framebuffer::{Framebuffer, FramebufferAbstract, RenderPassAbstract, Subpass},
+	image::{AttachmentImage, ImageUsage, ImageViewAccess},
+	pipeline::{
+		vertex::{BufferlessDefinition, BufferlessVertices},
+		viewport::Viewport,
+		GraphicsPipeline, GraphicsPipelineAbstract,
+	},
+	sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode},
+};
+
+/// Resources for the FXAA post pass that can be shared by every surface.
+pub(crate) struct FxaaContext {
+	render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+	vshader: vshader::Shader,
+	fshader: fshader::Shader,
+	sampler: Arc<Sampler>,
+}
+impl FxaaContext {
+	pub(crate) fn new(device: &Arc<Device>) -> Arc<Self> {
+		let render_pass = Arc::new(
+			vulkano::single_pass_renderpass!(
+				device.clone(),
+				attachments: {
+					color: { load: DontCare, store: Store, format: SWAP_FORMAT, samples: 1, }
+				},
+				pass: { color: [color], depth_stencil: {} }
+			)
+			.unwrap(),
+		);
+
+		let vshader = vshader::Shader::load(device.clone()).unwrap();
+		let fshader = fshader::Shader::load(device.clone()).unwrap();
+
+		let sampler = Sampler::new(
+			device.clone(),
+			Filter::Linear,
+			Filter::Linear,
+			MipmapMode::Nearest,
+			SamplerAddressMode::ClampToEdge,
+			SamplerAddressMode::ClampToEdge,
+			SamplerAddressMode::ClampToEdge,
+			0.0,
+			1.0,
+			0.0,
+			0.0,
+		)
+		.unwrap();
+
+		Arc::new(Self { render_pass, vshader, fshader, sampler })
+	}
+}
+
+/// Renders `target` into the swapchain images with FXAA applied.
+pub(crate) struct Fxaa {
+	pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	target: Arc<dyn ImageViewAccess + Send + Sync>,
+	framebuffers: Vec<Arc<dyn FramebufferAbstract + Send + Sync>>,
+	desc: Arc<dyn DescriptorSet + Send + Sync>,
+	dimensions: [u32; 2],
+}
+impl Fxaa {
+	pub(crate) fn new(
+		ctx: &FxaaContext,
+		images: Vec<Arc<dyn ImageViewAccess + Send + Sync>>,
+		dimensions: [u32; 2],
+	) -> Self {
+		let device = ctx.render_pass.device().clone();
+
+		let pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync> = Arc::new(
+			GraphicsPipeline::start()
+				.vertex_input(BufferlessDefinition)
+				.vertex_shader(ctx.vshader.main_entry_point(), ())
+				.fragment_shader(ctx.fshader.main_entry_point(), ())
+				.triangle_list()
+				.viewports(vec![Viewport {
+					origin: [0.0, 0.0],
+					dimensions: [dimensions[0] as f32, dimensions[1] as f32],
+					depth_range: 0.0..1.0,
+				}])
+				.render_pass(Subpass::from(ctx.render_pass.clone(), 0).unwrap())
+				.build(device.clone())
+				.unwrap(),
+		);
+
+		let usage = ImageUsage { color_attachment: true, sampled: true, ..ImageUsage::none() };
+		let target: Arc<dyn ImageViewAccess + Send + Sync> =
+			AttachmentImage::with_usage(device, dimensions, SWAP_FORMAT, usage).unwrap();
+
+		let framebuffers = images
+			.into_iter()
+			.map(|image| {
+				Arc::new(Framebuffer::start(ctx.render_pass.clone()).add(image).unwrap().build().unwrap())
+					as Arc<dyn FramebufferAbstract + Send + Sync>
+			})
+			.collect();
+
+		let desc = Arc::new(
+			PersistentDescriptorSet::start(pipeline.clone(), 0)
+				.add_sampled_image(target.clone(), ctx.sampler.clone())
+				.unwrap()
+				.build()
+				.unwrap(),
+		);
+
+		Self { pipeline, target, framebuffers, desc, dimensions }
+	}
+
+	/// The image the scene must be rendered into before calling `draw`. Its format is `SWAP_FORMAT`.
+	pub(crate) fn target(&self) -> &Arc<dyn ImageViewAccess + Send + Sync> {
+		&self.target
+	}
+
+	pub(crate) fn draw(&self, command_buffer: AutoCommandBufferBuilder, image_num: usize) -> AutoCommandBufferBuilder {
+		command_buffer
+			.begin_render_pass(self.framebuffers[image_num].clone(), false, vec![ClearValue::None])
+			.unwrap()
+			.draw(
+				self.pipeline.clone(),
+				&Default::default(),
+				BufferlessVertices { vertices: 3, instances: 1 },
+				self.desc.clone(),
+				fshader::ty::PushConsts {
+					inv_resolution: [1.0 / self.dimensions[0] as f32, 1.0 / self.dimensions[1] as f32],
+				},
+			)
+			.unwrap()
+			.end_render_pass()
+			.unwrap()
+	}
+}
+
+mod vshader {
+	vulkano_shaders::shader! { ty: "vertex", path: "src/pipelines/shaders/fxaa.glslv" }
+}
+mod fshader {
+	vulkano_shaders::shader! { ty: "fragment", path: "src/pipelines/shaders/fxaa.glslf" }
+}
@@ -0,0 +1,82 @@
+use std::sync::Arc;
+use vulkano::{
+	buffer::{BufferUsage, DeviceLocalBuffer},
+	command_buffer::{AutoCommandBuffer, AutoCommandBufferBuilder, CommandBuffer, CommandBufferExecFuture},
+	descriptor::{descriptor_set::PersistentDescriptorSet, pipeline_layout::PipelineLayout},
+	device::Queue,
+	image::ImageViewAccess,
+	pipeline::ComputePipeline,
+	sampler::Sampler,
+	sync::{FenceSignalFuture, GpuFuture, NowFuture},
+};
+
+pub(crate) use self::cshader::ty::Sky;
+
+/// Signaled once the irradiance buffer has been written. It's already flushed, so it can be joined into any number of
+/// frames.
+pub(crate) type IrradianceFuture = Arc<FenceSignalFuture<CommandBufferExecFuture<NowFuture, AutoCommandBuffer>>>;
+
+/// Projects skyboxes onto spherical harmonics for diffuse ambient lighting.
+pub(crate) struct Irradiance {
+	queue: Arc<Queue>,
+	pipeline: Arc<ComputePipeline<PipelineLayout<cshader::Layout>>>,
+}
+impl Irradiance {
+	pub(crate) fn new(queue: Arc<Queue>) -> Self {
+		let device = queue.device().clone();
+		let cshader = cshader::Shader::load(device.clone()).unwrap();
+		let pipeline = Arc::new(ComputePipeline::new(device, &cshader.main_entry_point(), &()).unwrap());
+		Self { queue, pipeline }
+	}
+
+	/// Starts computing the irradiance of `sky`. Only one of `sky` and `sky_cube` is sampled, depending on `cubemap`.
+	/// `unset` means no skybox was set, so the shaders use flat ambient light instead. The buffer must not be read
+	/// before the returned future is signaled.
+	pub(crate) fn compute(
+		&self,
+		sky: Arc<dyn ImageViewAccess + Send + Sync>,
+		sky_cube: Arc<dyn ImageViewAccess + Send + Sync>,
+		cubemap: bool,
+		unset: bool,
+		sampler: Arc<Sampler>,
+	) -> (Arc<DeviceLocalBuffer<Sky>>, IrradianceFuture) {
+		let device = self.queue.device();
+
+		let usage = BufferUsage { uniform_buffer: true, storage_buffer: true, ..BufferUsage::none() };
+		let buffer = DeviceLocalBuffer::new(device.clone(), usage, device.active_queue_families()).unwrap();
+
+		let desc = Arc::new(
+			PersistentDescriptorSet::start(self.pipeline.clone(), 0)
+				.add_sampled_image(sky, sampler.clone())
+				.unwrap()
+				.add_sampled_image(sky_cube, sampler)
+				.unwrap()
+				.add_buffer(buffer.clone())
+				.unwrap()
+				.build()
+				.unwrap(),
+		);
+
+		let future = AutoCommandBufferBuilder::primary_one_time_submit(device.clone(), self.queue.family())
+			.unwrap()
+			.dispatch(
+				[1, 1, 1],
+				self.pipeline.clone(),
+				desc,
+				cshader::ty::PushConsts { cubemap: cubemap as u32, unset: unset as u32 },
+			)
+			.unwrap()
+			.build()
+			.unwrap()
+			.execute(self.queue.clone())
+			.unwrap()
+			.then_signal_fence_and_flush()
+			.unwrap();
+
+		(buffer, Arc::new(future))
+	}
+}
+
+mod cshader {
+	vulkano_shaders::shader! { ty: "compute", path: "src/pipelines/shaders/irradiance.glslc" }
+}
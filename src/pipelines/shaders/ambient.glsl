@@ -0,0 +1,45 @@
+// requires sky.glsl
+// Irradiance from L2 spherical harmonics that were already convolved with the cosine lobe and divided by pi.
+vec3 sh_irradiance(vec4 sh[9], vec3 n) {
+	vec3 irradiance = sh[0].rgb * 0.282095
+		+ (sh[1].rgb * n.y + sh[2].rgb * n.z + sh[3].rgb * n.x) * 0.488603
+		+ (sh[4].rgb * n.x * n.y + sh[5].rgb * n.y * n.z + sh[7].rgb * n.x * n.z) * 1.092548
+		+ sh[6].rgb * 0.315392 * (3.0 * n.z * n.z - 1.0)
+		+ sh[8].rgb * 0.546274 * (n.x * n.x - n.y * n.y);
+	return max(irradiance, vec3(0.0));
+}
+
+// Ambient light when no skybox is set, which is Sky.cubemap.y. Surfaces without lightmaps get the same flat ambient
+// light as before skyboxes lit anything, and lightmapped ones only their diffuse lightmap light.
+vec3 flat_ambient(bool lightmapped, vec3 irradiance, vec3 color, vec3 albedo, vec3 ambient_occlusion) {
+	return lightmapped ? albedo * irradiance * ambient_occlusion : ambient_occlusion * 0.2 * color;
+}
+
+// Image-based light from the skybox. `irradiance` is either from sh_irradiance or a lightmap. Specular reflections
+// sample the skybox's mip chain by roughness.
+vec3 ambient_light(
+	sampler2D sky,
+	samplerCube sky_cube,
+	bool cubemap,
+	vec3 irradiance,
+	vec3 normal,
+	vec3 view,
+	vec3 color,
+	vec3 ambient_occlusion
+) {
+	// must match direct_light
+	float metal = 0.0;
+	float rough = 0.2;
+
+	vec3 diffuse = color * (1.0 - metal) * irradiance;
+
+	vec3 reflected_dir = reflect(-view, normal);
+	float levels = float(cubemap ? textureQueryLevels(sky_cube) : textureQueryLevels(sky));
+	float lod = rough * (levels - 1.0);
+	vec3 reflected = cubemap ? skybox(sky_cube, reflected_dir, lod) : skybox(sky, reflected_dir, lod);
+	vec3 f0 = mix(vec3(0.04), color, metal);
+	float NdotV = max(0.0, dot(normal, view));
+	vec3 fresnel = f0 + (max(vec3(1.0 - rough), f0) - f0) * pow(1.0 - NdotV, 5.0);
+
+	return (diffuse * (1.0 - fresnel) + reflected * fresnel) * ambient_occlusion;
+}
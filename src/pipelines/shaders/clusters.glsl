@@ -0,0 +1,34 @@
+// Lights are binned into clusters: screen tiles split into slices by view distance. Each cluster holds a count
+// followed by the indices of up to MAX_CLUSTER_LIGHTS lights.
+// Lights past that are dropped from the cluster, which is documented on DirectLight.
+
+// must match CLUSTERS and CLUSTER_STRIDE in deferred.rs
+const uint CLUSTERS_X = 16;
+const uint CLUSTERS_Y = 9;
+const uint CLUSTERS_Z = 24;
+const uint CLUSTER_STRIDE = 64;
+const uint MAX_CLUSTER_LIGHTS = CLUSTER_STRIDE - 1;
+
+// must match `DirectLight::packed` in direct_light.rs
+struct Light {
+	vec4 position;
+	vec4 color;
+};
+
+// the view distances where depth is 0 and 1, where inv_proj is Camera::inv_proj
+vec2 cluster_range(vec4 inv_proj) {
+	return vec2(-inv_proj.z / inv_proj.w, -inv_proj.z / (1.0 + inv_proj.w));
+}
+
+// slices are spaced exponentially, so clusters are roughly as deep as they are wide
+float slice_distance(vec2 range, uint slice) {
+	return range.x * pow(range.y / range.x, float(slice) / float(CLUSTERS_Z));
+}
+
+// uv is the position on the screen in [0, 1]
+uint cluster_index(vec2 range, vec2 uv, float view_distance) {
+	uvec2 tile = min(uvec2(uv * vec2(CLUSTERS_X, CLUSTERS_Y)), uvec2(CLUSTERS_X - 1, CLUSTERS_Y - 1));
+	float slice = log(view_distance / range.x) / log(range.y / range.x) * float(CLUSTERS_Z);
+	uint z = uint(clamp(slice, 0.0, float(CLUSTERS_Z - 1)));
+	return (z * CLUSTERS_Y + tile.y) * CLUSTERS_X + tile.x;
+}
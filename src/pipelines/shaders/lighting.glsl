@@ -0,0 +1,45 @@
+vec3 oren_nayar(vec3 lightDirection, vec3 viewDirection, vec3 surfaceNormal, float rough, vec3 albedo) {
+	float LdotV = dot(lightDirection, viewDirection);
+	float NdotL = dot(lightDirection, surfaceNormal);
+	float NdotV = dot(surfaceNormal, viewDirection);
+	float s = LdotV - NdotL * NdotV;
+	float t = mix(1.0, max(NdotL, NdotV), step(0.0, s));
+	float sigma2 = rough * rough;
+	vec3 A = 1.0 + sigma2 * (albedo / (sigma2 + 0.13) + 0.5 / (sigma2 + 0.33));
+	float B = 0.45 * sigma2 / (sigma2 + 0.09);
+	return albedo * max(0.0, NdotL) * (A + B * s / t) / 3.14159265;
+}
+
+// LightPosition and LightColor are packed by DirectLight::packed
+vec3 direct_light(vec3 position, vec3 normal, vec3 color, vec3 CameraOffset, vec4 LightPosition, vec4 LightColor) {
+	float metal = 0.0;
+	float rough = 0.2;
+
+	/*
+	bool grid = false;
+	if (mod(position.x, 1.0) < 0.5) grid = !grid;
+	if (mod(position.y, 1.0) < 0.5) grid = !grid;
+	if (mod(position.z, 1.0) < 0.5) grid = !grid;
+	if (grid) metal = 1.0;
+	*/
+
+	float specularExponent = 128.0 * pow(2.0, 4.0 - 8.0 * rough);
+	float specularNorm = specularExponent * 0.03978873577297383 + 0.2785211504108169;
+	vec3 diffuseColor = color * (1.0 - metal);
+
+	float lightRadiusSquaredTimesCutoff = LightColor.w;
+	float lightRadiusSquaredInverse = LightPosition.w;
+	vec3 lightOffset = LightPosition.xyz - position;
+	float lightDistanceSquared = dot(lightOffset, lightOffset);
+	float lightFalloff = min(1.0, lightDistanceSquared * lightRadiusSquaredInverse);
+	lightFalloff *= lightFalloff; lightFalloff *= lightFalloff;
+	lightFalloff = 1.0 - lightFalloff;
+	lightFalloff = mix(lightFalloff * lightFalloff, lightFalloff, 0.3095096836885878);
+	lightFalloff *= max(0.0, dot(normal, normalize(lightOffset)));
+	lightFalloff /= 1.0 + lightDistanceSquared;
+	lightFalloff *= lightRadiusSquaredTimesCutoff;
+	vec3 lightPower = LightColor.rgb * lightFalloff;
+	float specularPower = pow(max(0.0, dot(normalize(normalize(LightPosition.xyz - position) + normalize(CameraOffset - position)), normal)), specularExponent) * specularNorm;
+	vec3 specularColor = mix(vec3(0.04), color, metal) * specularPower;
+	return (diffuseColor + specularColor) * lightPower;
+}
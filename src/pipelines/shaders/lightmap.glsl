@@ -0,0 +1,57 @@
+// must match LIGHTMAP_* in mesh.rs
+const uint LIGHTMAP_NONE = 0;
+const uint LIGHTMAP_FLAT = 1;
+const uint LIGHTMAP_DIRECTIONAL = 2;
+
+// Half-Life 2 basis for directional lightmaps, in the tangent space of the lightmap UVs
+const vec3 LIGHTMAP_BASIS[3] = vec3[](
+	vec3(-0.408248, 0.707107, 0.577350),
+	vec3(-0.408248, -0.707107, 0.577350),
+	vec3(0.816497, 0.0, 0.577350)
+);
+
+// Expresses `normal` in the tangent space of the lightmap, built from the face normal and the screen-space
+// derivatives of the lightmap UVs. Must be called from uniform control flow.
+vec3 lightmap_tangent_normal(vec3 position, vec2 lmap, vec3 normal) {
+	vec3 dp1 = dFdx(position);
+	vec3 dp2 = dFdy(position);
+	vec2 duv1 = dFdx(lmap);
+	vec2 duv2 = dFdy(lmap);
+
+	vec3 face_normal = normalize(cross(dp1, dp2));
+	if (dot(face_normal, normal) < 0.0) face_normal = -face_normal;
+
+	vec3 dp2perp = cross(dp2, face_normal);
+	vec3 dp1perp = cross(face_normal, dp1);
+	vec3 tangent = dp2perp * duv1.x + dp1perp * duv2.x;
+	vec3 bitangent = dp2perp * duv1.y + dp1perp * duv2.y;
+	if (dot(tangent, tangent) == 0.0 || dot(bitangent, bitangent) == 0.0) return vec3(0.0, 0.0, 1.0);
+
+	return vec3(dot(normal, normalize(tangent)), dot(normal, normalize(bitangent)), dot(normal, face_normal));
+}
+
+// Baked irradiance, in the same units as sh_irradiance.
+vec3 lightmap_irradiance(
+	uint mode,
+	sampler2D flat_map,
+	sampler2D angle0,
+	sampler2D angle1,
+	sampler2D angle2,
+	vec2 lmap,
+	vec3 tangent_normal
+) {
+	if (mode == LIGHTMAP_DIRECTIONAL) {
+		vec3 weights = vec3(
+			dot(tangent_normal, LIGHTMAP_BASIS[0]),
+			dot(tangent_normal, LIGHTMAP_BASIS[1]),
+			dot(tangent_normal, LIGHTMAP_BASIS[2])
+		);
+		weights = max(weights, vec3(0.0));
+		weights *= weights;
+		weights /= max(weights.x + weights.y + weights.z, 0.0001);
+		return texture(angle0, lmap).rgb * weights.x
+			+ texture(angle1, lmap).rgb * weights.y
+			+ texture(angle2, lmap).rgb * weights.z;
+	}
+	return texture(flat_map, lmap).rgb;
+}
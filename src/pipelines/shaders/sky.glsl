@@ -0,0 +1,18 @@
+const float M_PI = 3.141592653589793;
+
+// requires util.glsl
+vec3 sky_dir(vec4 inv_proj, vec4 cam_rot, vec2 dir) {
+	vec3 skydir = -normalize(inv_proj.xyz * vec3(dir, 1.0));
+	return quat_mul(cam_rot, vec3(skydir.x, -skydir.z, -skydir.y));
+}
+
+// equirectangular panorama
+vec3 skybox(sampler2D sky, vec3 skydir, float lod) {
+	vec2 uv = vec2(atan(skydir.x, -skydir.y) / 2.0, acos(skydir.z)) / M_PI;
+	return textureLod(sky, uv, lod).rgb;
+}
+
+// cubemap with +Y as the up face
+vec3 skybox(samplerCube sky, vec3 skydir, float lod) {
+	return textureLod(sky, vec3(skydir.x, skydir.z, -skydir.y), lod).rgb;
+}
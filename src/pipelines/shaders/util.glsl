@@ -9,3 +9,24 @@ vec4 quat_inv(vec4 quat) {
 vec3 quat_mul(vec4 quat, vec3 vec) {
 	return cross(quat.xyz, cross(quat.xyz, vec) + vec * quat.w) * 2.0 + vec;
 }
+
+// the inverse of perspective, where inv_proj is Camera::inv_proj, ndc is in [-1, 1], and depth is from the depth buffer
+vec3 inv_perspective(vec4 inv_proj, vec2 ndc, float depth) {
+	return vec3(ndc * inv_proj.xy, inv_proj.z) / (depth + inv_proj.w);
+}
+
+vec2 sign_not_zero(vec2 v) {
+	return vec2(v.x >= 0.0 ? 1.0 : -1.0, v.y >= 0.0 ? 1.0 : -1.0);
+}
+
+// packs a unit vector into two components by projecting it onto an octahedron and unfolding the lower half
+vec2 oct_encode(vec3 n) {
+	n /= abs(n.x) + abs(n.y) + abs(n.z);
+	return n.z >= 0.0 ? n.xy : (1.0 - abs(n.yx)) * sign_not_zero(n.xy);
+}
+
+vec3 oct_decode(vec2 e) {
+	vec3 n = vec3(e, 1.0 - abs(e.x) - abs(e.y));
+	if (n.z < 0.0) n.xy = (1.0 - abs(n.yx)) * sign_not_zero(n.xy);
+	return normalize(n);
+}
@@ -0,0 +1,212 @@
+use crate::{camera::Camera, pipelines::AmbientOcclusion};
+use std::sync::Arc;
+use vulkano::{
+	command_buffer::AutoCommandBufferBuilder,
+	descriptor::{descriptor_set::PersistentDescriptorSet, DescriptorSet},
+	device::Device,
+	format::{ClearValue, Format},
+	framebuffer::{Framebuffer, FramebufferAbstract, RenderPassAbstract, Subpass},
+	image::{AttachmentImage, ImageUsage, ImageViewAccess},
+	pipeline::{
+		vertex::{BufferlessDefinition, BufferlessVertices},
+		viewport::Viewport,
+		GraphicsPipeline, GraphicsPipelineAbstract,
+	},
+	sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode},
+};
+
+const OCCLUSION_FORMAT: Format = Format::R8Unorm;
+// in world units
+const RADIUS: f32 = 0.5;
+
+/// Resources for the SSAO passes that can be shared by every surface.
+pub(crate) struct SsaoContext {
+	render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+	vshader: vshader::Shader,
+	fshader: fshader::Shader,
+	blur_fshader: blur_fshader::Shader,
+	sampler: Arc<Sampler>,
+}
+impl SsaoContext {
+	pub(crate) fn new(device: &Arc<Device>) -> Arc<Self> {
+		let render_pass = Arc::new(
+			vulkano::single_pass_renderpass!(
+				device.clone(),
+				attachments: {
+					occlusion: { load: DontCare, store: Store, format: OCCLUSION_FORMAT, samples: 1, }
+				},
+				pass: { color: [occlusion], depth_stencil: {} }
+			)
+			.unwrap(),
+		);
+
+		let vshader = vshader::Shader::load(device.clone()).unwrap();
+		let fshader = fshader::Shader::load(device.clone()).unwrap();
+		let blur_fshader = blur_fshader::Shader::load(device.clone()).unwrap();
+
+		let sampler = Sampler::new(
+			device.clone(),
+			Filter::Nearest,
+			Filter::Nearest,
+			MipmapMode::Nearest,
+			SamplerAddressMode::ClampToEdge,
+			SamplerAddressMode::ClampToEdge,
+			SamplerAddressMode::ClampToEdge,
+			0.0,
+			1.0,
+			0.0,
+			0.0,
+		)
+		.unwrap();
+
+		Arc::new(Self { render_pass, vshader, fshader, blur_fshader, sampler })
+	}
+
+	/// Samples the output of `Ssao`.
+	pub(crate) fn sampler(&self) -> &Arc<Sampler> {
+		&self.sampler
+	}
+}
+
+/// Computes ambient occlusion from a depth buffer and octahedral encoded normals, then blurs it.
+pub(crate) struct Ssao {
+	pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	blur_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	output: Arc<dyn ImageViewAccess + Send + Sync>,
+	framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+	blur_framebuffer: Arc<dyn FramebufferAbstract + Send + Sync>,
+	desc: Arc<dyn DescriptorSet + Send + Sync>,
+	blur_desc: Arc<dyn DescriptorSet + Send + Sync>,
+	samples: u32,
+	blur_radius: i32,
+}
+impl Ssao {
+	/// `depth` and `normal` must be sampleable. Panics if `ao` is `AmbientOcclusion::None`.
+	pub(crate) fn new(
+		ctx: &SsaoContext,
+		depth: Arc<dyn ImageViewAccess + Send + Sync>,
+		normal: Arc<dyn ImageViewAccess + Send + Sync>,
+		dimensions: [u32; 2],
+		ao: AmbientOcclusion,
+	) -> Self {
+		let device = ctx.render_pass.device().clone();
+		let (samples, blur_radius) = match ao {
+			AmbientOcclusion::None => panic!("Ssao::new called with AmbientOcclusion::None"),
+			AmbientOcclusion::Low => (8, 1),
+			AmbientOcclusion::Medium => (16, 2),
+			AmbientOcclusion::High => (32, 3),
+		};
+
+		let viewport = Viewport {
+			origin: [0.0, 0.0],
+			dimensions: [dimensions[0] as f32, dimensions[1] as f32],
+			depth_range: 0.0..1.0,
+		};
+		let pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync> = Arc::new(
+			GraphicsPipeline::start()
+				.vertex_input(BufferlessDefinition)
+				.vertex_shader(ctx.vshader.main_entry_point(), ())
+				.fragment_shader(ctx.fshader.main_entry_point(), ())
+				.triangle_list()
+				.viewports(vec![viewport.clone()])
+				.render_pass(Subpass::from(ctx.render_pass.clone(), 0).unwrap())
+				.build(device.clone())
+				.unwrap(),
+		);
+		let blur_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync> = Arc::new(
+			GraphicsPipeline::start()
+				.vertex_input(BufferlessDefinition)
+				.vertex_shader(ctx.vshader.main_entry_point(), ())
+				.fragment_shader(ctx.blur_fshader.main_entry_point(), ())
+				.triangle_list()
+				.viewports(vec![viewport])
+				.render_pass(Subpass::from(ctx.render_pass.clone(), 0).unwrap())
+				.build(device.clone())
+				.unwrap(),
+		);
+
+		let usage = ImageUsage { color_attachment: true, sampled: true, ..ImageUsage::none() };
+		let raw: Arc<dyn ImageViewAccess + Send + Sync> =
+			AttachmentImage::with_usage(device.clone(), dimensions, OCCLUSION_FORMAT, usage).unwrap();
+		let output: Arc<dyn ImageViewAccess + Send + Sync> =
+			AttachmentImage::with_usage(device, dimensions, OCCLUSION_FORMAT, usage).unwrap();
+
+		let create_framebuffer = |image: &Arc<dyn ImageViewAccess + Send + Sync>| {
+			Arc::new(Framebuffer::start(ctx.render_pass.clone()).add(image.clone()).unwrap().build().unwrap())
+				as Arc<dyn FramebufferAbstract + Send + Sync>
+		};
+		let framebuffer = create_framebuffer(&raw);
+		let blur_framebuffer = create_framebuffer(&output);
+
+		let desc = Arc::new(
+			PersistentDescriptorSet::start(pipeline.clone(), 0)
+				.add_sampled_image(depth.clone(), ctx.sampler.clone())
+				.unwrap()
+				.add_sampled_image(normal, ctx.sampler.clone())
+				.unwrap()
+				.build()
+				.unwrap(),
+		);
+		let blur_desc = Arc::new(
+			PersistentDescriptorSet::start(blur_pipeline.clone(), 0)
+				.add_sampled_image(raw, ctx.sampler.clone())
+				.unwrap()
+				.add_sampled_image(depth, ctx.sampler.clone())
+				.unwrap()
+				.build()
+				.unwrap(),
+		);
+
+		Self { pipeline, blur_pipeline, output, framebuffer, blur_framebuffer, desc, blur_desc, samples, blur_radius }
+	}
+
+	/// The blurred ambient occlusion, written by `draw`. Its format is `R8Unorm`, where 1 is unoccluded.
+	pub(crate) fn output(&self) -> &Arc<dyn ImageViewAccess + Send + Sync> {
+		&self.output
+	}
+
+	/// Must be recorded after the depth and normal images have been written, and outside of a render pass.
+	pub(crate) fn draw(&self, command_buffer: AutoCommandBufferBuilder, cam: &Camera) -> AutoCommandBufferBuilder {
+		command_buffer
+			.begin_render_pass(self.framebuffer.clone(), false, vec![ClearValue::None])
+			.unwrap()
+			.draw(
+				self.pipeline.clone(),
+				&Default::default(),
+				BufferlessVertices { vertices: 3, instances: 1 },
+				self.desc.clone(),
+				fshader::ty::PushConsts {
+					proj: cam.projection().into(),
+					inv_proj: cam.inv_proj().into(),
+					cam_rot: cam.transform().rot.into(),
+					radius: RADIUS,
+					samples: self.samples,
+				},
+			)
+			.unwrap()
+			.end_render_pass()
+			.unwrap()
+			.begin_render_pass(self.blur_framebuffer.clone(), false, vec![ClearValue::None])
+			.unwrap()
+			.draw(
+				self.blur_pipeline.clone(),
+				&Default::default(),
+				BufferlessVertices { vertices: 3, instances: 1 },
+				self.blur_desc.clone(),
+				blur_fshader::ty::PushConsts { inv_proj: cam.inv_proj().into(), radius: self.blur_radius },
+			)
+			.unwrap()
+			.end_render_pass()
+			.unwrap()
+	}
+}
+
+mod vshader {
+	vulkano_shaders::shader! { ty: "vertex", path: "src/pipelines/shaders/fxaa.glslv" }
+}
+mod fshader {
+	vulkano_shaders::shader! { ty: "fragment", path: "src/pipelines/shaders/ssao.glslf" }
+}
+mod blur_fshader {
+	vulkano_shaders::shader! { ty: "fragment", path: "src/pipelines/shaders/ssao_blur.glslf" }
+}
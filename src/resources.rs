@@ -1,20 +1,28 @@
+mod dds;
 mod model;
 mod texture;
 
 use crate::{
-	mesh::Mesh,
+	mesh::{Mesh, MeshInner},
 	mesh_data::MeshData,
 	mesh_group::MeshGroup,
-	texture::{ImmutableTexture, Texture},
+	pipelines::irradiance::Irradiance,
+	texture::{image_size, ImmutableTexture, Texture},
 	threads::FILE_THREAD,
+	vfs::{DirectoryVfs, MountTable, Vfs},
 };
-use atom::AtomSetOnce;
 use futures::{future::lazy, task::SpawnExt};
+use log::{info, warn};
+use self::texture::NiceTexture;
 use std::{
 	collections::HashMap,
 	ops::Range,
 	path::{Path, PathBuf},
-	sync::{Arc, Mutex},
+	sync::{
+		atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering},
+		Arc, Mutex, RwLock, Weak,
+	},
+	time::{Instant, SystemTime},
 };
 use vulkano::{
 	descriptor::PipelineLayoutAbstract,
@@ -30,8 +38,16 @@ pub struct Resources {
 	layout_desc: Arc<dyn PipelineLayoutAbstract + Send + Sync>,
 	sampler: Arc<Sampler>,
 	white_pixel: Arc<dyn Texture + Send + Sync>,
-	meshes: Mutex<HashMap<PathBuf, Arc<Model>>>,
-	textures: Mutex<HashMap<PathBuf, Arc<TextureResource>>>,
+	white_cube: Arc<dyn Texture + Send + Sync>,
+	irradiance: Arc<Irradiance>,
+	/// Models and textures are only cached while something else uses them. Meshes keep their model alive, and models
+	/// keep their textures alive.
+	meshes: Mutex<HashMap<PathBuf, Weak<Model>>>,
+	textures: Mutex<HashMap<PathBuf, Weak<TextureResource>>>,
+	modified: Mutex<HashMap<PathBuf, Option<SystemTime>>>,
+	vfs: Arc<MountTable>,
+	texture_budget: Mutex<Option<usize>>,
+	streams: Arc<StreamQueue>,
 }
 impl Resources {
 	pub(crate) fn new(
@@ -64,99 +80,525 @@ impl Resources {
 		.unwrap();
 		let white_pixel = Arc::new(white_pixel);
 
+		let (white_cube, white_cube_future) = ImmutableTexture::cubemap_from_iter_vk(
+			queue.clone(),
+			vec![[255u8, 255, 255, 255]; 6].into_iter(),
+			1,
+			Format::R8G8B8A8Unorm,
+		)
+		.unwrap();
+		let white_cube = Arc::new(white_cube);
+
+		let irradiance = Arc::new(Irradiance::new(queue.clone()));
+
 		let meshes = Mutex::default();
 		let textures = Mutex::default();
-		(Self { queue, layout_desc, sampler, white_pixel, meshes, textures }, white_pixel_future)
+		let modified = Mutex::default();
+		let vfs = Arc::new(MountTable::default());
+		vfs.mount("", 0, Arc::new(DirectoryVfs::new(".")));
+		let texture_budget = Mutex::default();
+		let streams = Arc::default();
+		(
+			Self {
+				queue,
+				layout_desc,
+				sampler,
+				white_pixel,
+				white_cube,
+				irradiance,
+				meshes,
+				textures,
+				modified,
+				vfs,
+				texture_budget,
+				streams,
+			},
+			white_pixel_future.join(white_cube_future),
+		)
 	}
 
 	pub fn get_model(&self, mesh_group: Arc<MeshGroup>, path: impl AsRef<Path> + Clone + Send + 'static) -> Vec<Mesh> {
 		let path = path.as_ref();
-		let model = self.meshes.lock().unwrap().get(path).cloned();
+		let model = self.meshes.lock().unwrap().get(path).and_then(Weak::upgrade);
 		let model = model.unwrap_or_else(|| {
-			let (mesh_data, mats, mesh_data_future) = model::from_nice_model(&self.queue, path.clone());
+			let (mesh_data, mats, mesh_data_future) = model::from_nice_model(&self.queue, &*self.vfs, path)
+				.unwrap_or_else(|err| panic!("failed to load {}: {}", path.display(), err));
 			mesh_data_future.then_signal_fence_and_flush().unwrap().wait(None).unwrap();
 			let mats = mats
 				.into_iter()
 				.map(|mat| Material {
-					range: mat.range,
+					range: RwLock::new(mat.range),
 					textures: [self.get_texture(mat.tex1), self.get_texture(mat.tex2)],
 				})
 				.collect();
-			let model = Arc::new(Model { mesh_data, mats });
-			self.meshes.lock().unwrap().insert(path.to_owned(), model.clone());
+			let model = Arc::new(Model { mesh_data: RwLock::new(mesh_data), mats, meshes: Mutex::default() });
+			self.meshes.lock().unwrap().insert(path.to_owned(), Arc::downgrade(&model));
+			self.modified.lock().unwrap().insert(path.to_owned(), self.vfs.modified(path));
 			model
 		});
 
-		model.mats.iter().map(|mat| {
-			let mesh = Mesh::new_inner(mesh_group.clone(), self.layout_desc.clone(), &self.white_pixel, self.sampler.clone());
+		let mesh_data = model.mesh_data.read().unwrap();
+		let mut model_meshes = model.meshes.lock().unwrap();
+		model.mats.iter().enumerate().map(|(i, mat)| {
+			let mesh = Mesh::new_inner(
+				mesh_group.clone(),
+				self.layout_desc.clone(),
+				&self.white_pixel,
+				self.sampler.clone(),
+				Some(model.clone()),
+			);
 			{
 				let mut mesh_inner = mesh.inner().write().unwrap();
-				mesh_inner.set_mesh_data(Some(model.mesh_data.clone()));
-				mesh_inner.set_range(mat.range.clone());
+				mesh_inner.set_mesh_data(Some(mesh_data.clone()));
+				mesh_inner.set_range(mat.range.read().unwrap().clone());
 				mesh_inner.set_tex(0, mat.textures[0].clone());
 				mesh_inner.set_tex(1, mat.textures[1].clone());
 			}
+			model_meshes.push((i, Arc::downgrade(mesh.inner())));
 			mesh
 		}).collect()
 	}
 
 	pub fn get_texture(&self, path: impl AsRef<Path> + Clone + Send + 'static) -> Arc<dyn Texture + Send + Sync> {
-		let tex = self.textures.lock().unwrap().get(path.as_ref()).cloned();
+		let tex = self.textures.lock().unwrap().get(path.as_ref()).and_then(Weak::upgrade);
 		tex.unwrap_or_else(|| {
 			let tex = TextureResource::new(self.white_pixel.clone());
-			load_tex(self.queue.clone(), tex.clone(), path.clone());
-			self.textures.lock().unwrap().insert(path.as_ref().to_owned(), tex.clone());
+			load_tex(self.queue.clone(), self.vfs.clone(), self.streams.clone(), tex.clone(), path.clone());
+			self.textures.lock().unwrap().insert(path.as_ref().to_owned(), Arc::downgrade(&tex));
+			self.modified.lock().unwrap().insert(path.as_ref().to_owned(), self.vfs.modified(path.as_ref()));
 			tex
 		})
 	}
 
+	/// Reloads cached models and textures whose files changed since they were loaded, on `FILE_THREAD`. Textures and
+	/// meshes that were already handed out are updated in place.
+	///
+	/// A model's texture paths are only read the first time it's loaded, and a model is only reloaded if it still has
+	/// the same number of materials.
+	pub fn reload_changed(&self) {
+		let changed = self
+			.modified
+			.lock()
+			.unwrap()
+			.iter_mut()
+			.filter_map(|(path, time)| {
+				let new_time = self.vfs.modified(path);
+				if new_time != *time {
+					*time = new_time;
+					Some(path.clone())
+				} else {
+					None
+				}
+			})
+			.collect::<Vec<_>>();
+
+		for path in changed {
+			info!("reloading {}", path.display());
+			// evicted textures are read again when they're reloaded anyway
+			let tex = self.textures.lock().unwrap().get(&path).and_then(Weak::upgrade).filter(|tex| !tex.is_evicted());
+			if let Some(tex) = tex {
+				tex.start_reload();
+				load_tex(self.queue.clone(), self.vfs.clone(), self.streams.clone(), tex, path.clone());
+			}
+			if let Some(model) = self.meshes.lock().unwrap().get(&path).and_then(Weak::upgrade) {
+				reload_model(self.queue.clone(), self.vfs.clone(), model, path);
+			}
+		}
+	}
+
+	/// Removes `path` from the cache, so the next `get_model` or `get_texture` loads it again. A texture also releases
+	/// its image immediately and is drawn as `white_pixel` from then on. A model stays in memory until its meshes are
+	/// dropped.
+	pub fn unload(&self, path: impl AsRef<Path>) {
+		let path = path.as_ref();
+		if let Some(tex) = self.textures.lock().unwrap().remove(path).and_then(|tex| tex.upgrade()) {
+			tex.evict();
+		}
+		self.meshes.lock().unwrap().remove(path);
+		self.modified.lock().unwrap().remove(path);
+	}
+
+	/// Forgets models and textures that were freed because nothing used them anymore.
+	pub fn purge_unused(&self) {
+		let mut meshes = self.meshes.lock().unwrap();
+		let mut textures = self.textures.lock().unwrap();
+		meshes.retain(|_, model| model.strong_count() > 0);
+		textures.retain(|_, tex| tex.strong_count() > 0);
+		self.modified.lock().unwrap().retain(|path, _| meshes.contains_key(path) || textures.contains_key(path));
+	}
+
+	pub fn texture_budget(&self) -> Option<usize> {
+		*self.texture_budget.lock().unwrap()
+	}
+
+	/// Limits the memory used by cached textures to `budget` bytes, or removes the limit if it's `None`.
+	pub fn set_texture_budget(&self, budget: Option<usize>) {
+		*self.texture_budget.lock().unwrap() = budget;
+		self.enforce_texture_budget();
+	}
+
+	/// Evicts the least recently drawn textures until the budget is met. Evicted textures are drawn as `white_pixel`,
+	/// since vulkano can't view an image from a lower mip level. Evicted textures that were drawn since are loaded
+	/// again, if they fit.
+	///
+	/// `Context::watch_resources` calls this regularly. Otherwise it only happens when the budget changes.
+	pub fn enforce_texture_budget(&self) {
+		let budget = match self.texture_budget() {
+			Some(budget) => budget,
+			None => return,
+		};
+
+		let mut textures = self
+			.textures
+			.lock()
+			.unwrap()
+			.iter()
+			.filter_map(|(path, tex)| tex.upgrade().map(|tex| (path.clone(), tex)))
+			.collect::<Vec<_>>();
+		textures.sort_by_key(|(_, tex)| *tex.last_used.lock().unwrap());
+
+		let mut used = textures.iter().filter(|(_, tex)| !tex.is_evicted()).map(|(_, tex)| tex.size()).sum::<usize>();
+		for (path, tex) in &textures {
+			if used <= budget {
+				break;
+			}
+			if !tex.is_evicted() {
+				info!("evicting {}", path.display());
+				used -= tex.size();
+				tex.evict();
+			}
+		}
+
+		for (path, tex) in textures.iter().rev() {
+			if tex.is_evicted() && tex.used_since_eviction() && used + tex.size() <= budget {
+				info!("reloading evicted {}", path.display());
+				used += tex.size();
+				*tex.evicted_at.lock().unwrap() = None;
+				load_tex(self.queue.clone(), self.vfs.clone(), self.streams.clone(), tex.clone(), path.clone());
+			}
+		}
+	}
+
+	/// Lists every cached model and texture.
+	pub fn loaded(&self) -> Vec<LoadedResource> {
+		let meshes = self.meshes.lock().unwrap();
+		let textures = self.textures.lock().unwrap();
+		let models = meshes.iter().filter_map(|(path, model)| {
+			let model = model.upgrade()?;
+			let memory = model.mesh_data.read().unwrap().size();
+			Some(LoadedResource { path: path.clone(), kind: ResourceKind::Model, memory, evicted: false })
+		});
+		let textures = textures.iter().filter_map(|(path, tex)| {
+			let tex = tex.upgrade()?;
+			let evicted = tex.is_evicted();
+			let memory = if evicted { 0 } else { tex.size() };
+			Some(LoadedResource { path: path.clone(), kind: ResourceKind::Texture, memory, evicted })
+		});
+		models.chain(textures).collect()
+	}
+
+	/// The estimated GPU memory used by every cached model and texture, in bytes.
+	pub fn memory_usage(&self) -> usize {
+		self.loaded().iter().map(|res| res.memory).sum()
+	}
+
+	/// Every model and texture is read through this. By default the working directory is mounted at `""` with a
+	/// priority of 0, and packs or other directories can be mounted over it.
+	pub fn vfs(&self) -> &Arc<MountTable> {
+		&self.vfs
+	}
+
 	pub fn white_pixel(&self) -> &Arc<dyn Texture + Send + Sync> {
 		&self.white_pixel
 	}
 
+	pub fn white_cube(&self) -> &Arc<dyn Texture + Send + Sync> {
+		&self.white_cube
+	}
+
 	pub(crate) fn sampler(&self) -> &Arc<Sampler> {
 		&self.sampler
 	}
+
+	pub(crate) fn irradiance(&self) -> &Arc<Irradiance> {
+		&self.irradiance
+	}
 }
 
-fn load_tex(queue: Arc<Queue>, res: Arc<TextureResource>, path: impl AsRef<Path> + Clone + Send + 'static) {
+/// ntx textures larger than this are first shown as a preview of at most this size, and the full resolution is
+/// streamed in later.
+const PREVIEW_SIZE: u32 = 64;
+
+fn load_tex(
+	queue: Arc<Queue>,
+	vfs: Arc<MountTable>,
+	streams: Arc<StreamQueue>,
+	res: Arc<TextureResource>,
+	path: impl AsRef<Path> + Clone + Send + 'static,
+) {
+	// if the texture is evicted before this load finishes, the image is dropped instead of being set
+	let generation = res.generation();
 	FILE_THREAD
 		.lock()
 		.unwrap()
 		.spawn(lazy(move |_| {
-			let (tex, tex_future) = texture::from_nice_texture(&queue, path);
-			tex_future.then_signal_fence_and_flush().unwrap().wait(None).unwrap();
-			res.tex.set_if_none(Box::new(tex));
-			log::debug!("loaded image");
+			let is_dds = path.as_ref().extension().map_or(false, |ext| ext == "dds");
+			if is_dds {
+				match dds::from_dds(&queue, &*vfs, path.clone()) {
+					Ok((tex, tex_future)) => {
+						tex_future.then_signal_fence_and_flush().unwrap().wait(None).unwrap();
+						if res.set_loaded(tex, generation) {
+							log::debug!("loaded image");
+						}
+					},
+					Err(err) => warn!("failed to load {}: {}", path.as_ref().display(), err),
+				}
+				return;
+			}
+
+			// a failed reload keeps the previous image
+			let tex = match NiceTexture::read(&*vfs, path.as_ref()) {
+				Ok(tex) => tex,
+				Err(err) => {
+					warn!("failed to load {}: {}", path.as_ref().display(), err);
+					return;
+				},
+			};
+			match tex.preview(PREVIEW_SIZE) {
+				Some(preview) => {
+					let (preview, preview_future) = preview.upload(&queue);
+					preview_future.then_signal_fence_and_flush().unwrap().wait(None).unwrap();
+					if !res.set_loaded(preview, generation) {
+						return;
+					}
+					log::debug!("loaded preview");
+					streams.push(res, tex, generation);
+					FILE_THREAD.lock().unwrap().spawn(lazy(move |_| streams.upload_next(&queue))).unwrap();
+				},
+				None => {
+					let (tex, tex_future) = tex.upload(&queue);
+					tex_future.then_signal_fence_and_flush().unwrap().wait(None).unwrap();
+					if res.set_loaded(tex, generation) {
+						log::debug!("loaded image");
+					}
+				},
+			}
 		}))
 		.unwrap();
 }
 
-struct Model {
-	mesh_data: Arc<MeshData>,
+/// Full resolution textures waiting to replace their previews. Each one queues a job on `FILE_THREAD`, which uploads
+/// whichever pending texture has the highest priority rather than its own, so textures that are drawn close to the
+/// camera sharpen first.
+#[derive(Default)]
+struct StreamQueue {
+	/// Each texture is queued with the generation its preview was loaded at.
+	pending: Mutex<Vec<(Arc<TextureResource>, NiceTexture, u64)>>,
+}
+impl StreamQueue {
+	fn push(&self, res: Arc<TextureResource>, tex: NiceTexture, generation: u64) {
+		self.pending.lock().unwrap().push((res, tex, generation));
+	}
+
+	fn upload_next(&self, queue: &Arc<Queue>) {
+		let (res, tex, generation) = {
+			let mut pending = self.pending.lock().unwrap();
+			let next = pending
+				.iter()
+				.enumerate()
+				.max_by(|(_, (a, ..)), (_, (b, ..))| a.priority().partial_cmp(&b.priority()).unwrap())
+				.map(|(i, _)| i);
+			let next = match next {
+				Some(next) => pending.swap_remove(next),
+				None => return,
+			};
+			// only priorities hinted after this upload count towards the next one
+			for (res, ..) in pending.iter() {
+				res.priority.store(0, Ordering::Relaxed);
+			}
+			next
+		};
+
+		// skip textures that were dropped or evicted while they waited. they can still be evicted during the upload,
+		// which `set_loaded` checks.
+		if Arc::strong_count(&res) == 1 || res.generation() != generation {
+			return;
+		}
+		let (tex, tex_future) = tex.upload(queue);
+		tex_future.then_signal_fence_and_flush().unwrap().wait(None).unwrap();
+		if res.set_loaded(tex, generation) {
+			log::debug!("streamed image");
+		}
+	}
+}
+
+fn reload_model(queue: Arc<Queue>, vfs: Arc<MountTable>, model: Arc<Model>, path: PathBuf) {
+	FILE_THREAD
+		.lock()
+		.unwrap()
+		.spawn(lazy(move |_| {
+			// the file may still be being written, so a failed reload keeps the previous data until the next change
+			let (mesh_data, mats, mesh_data_future) = match model::from_nice_model(&queue, &*vfs, path.clone()) {
+				Ok(model) => model,
+				Err(err) => {
+					warn!("not reloading {}: {}", path.display(), err);
+					return;
+				},
+			};
+			if mats.len() != model.mats.len() {
+				warn!("not reloading {}, because its number of materials changed", path.display());
+				return;
+			}
+			mesh_data_future.then_signal_fence_and_flush().unwrap().wait(None).unwrap();
+
+			*model.mesh_data.write().unwrap() = mesh_data.clone();
+			for (mat, info) in model.mats.iter().zip(mats) {
+				*mat.range.write().unwrap() = info.range;
+			}
+
+			let mut meshes = model.meshes.lock().unwrap();
+			meshes.retain(|(_, mesh)| mesh.strong_count() > 0);
+			for (i, mesh) in meshes.iter() {
+				if let Some(mesh) = mesh.upgrade() {
+					let mut mesh = mesh.write().unwrap();
+					mesh.set_mesh_data(Some(mesh_data.clone()));
+					mesh.set_range(model.mats[*i].range.read().unwrap().clone());
+				}
+			}
+		}))
+		.unwrap();
+}
+
+pub(crate) struct Model {
+	mesh_data: RwLock<Arc<MeshData>>,
 	mats: Vec<Material>,
+	/// Meshes created from this model, and the index of the material each one uses.
+	meshes: Mutex<Vec<(usize, Weak<RwLock<MeshInner>>)>>,
 }
 
 struct Material {
-	range: Range<usize>,
+	range: RwLock<Range<usize>>,
 	textures: [Arc<dyn Texture + Send + Sync>; 2],
 }
 
+#[derive(Clone, Debug)]
+pub struct LoadedResource {
+	pub path: PathBuf,
+	pub kind: ResourceKind,
+	/// Estimated GPU memory in bytes. Textures that are still loading or were evicted use none.
+	pub memory: usize,
+	pub evicted: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResourceKind {
+	Model,
+	Texture,
+}
+
 pub struct TextureResource {
-	tex: AtomSetOnce<Box<Arc<dyn Texture + Send + Sync>>>,
+	tex: RwLock<Option<Arc<dyn Texture + Send + Sync>>>,
 	white_pixel: Arc<dyn Texture + Send + Sync>,
+	/// The pipelines hint the priority of every texture they draw each frame, so this is when it was last drawn.
+	last_used: Mutex<Instant>,
+	evicted_at: Mutex<Option<Instant>>,
+	/// Incremented by every eviction and reload, so loads that started before one can tell their image is no longer
+	/// wanted.
+	generation: AtomicU64,
+	/// The size of the most recently loaded image, which is kept after eviction.
+	size: AtomicUsize,
+	/// The highest priority hinted since the last streamed upload, as the bits of an `f32`.
+	priority: AtomicU32,
+	version: AtomicU64,
 }
 impl TextureResource {
 	pub fn new(white_pixel: Arc<dyn Texture + Send + Sync>) -> Arc<Self> {
-		Arc::new(Self { tex: AtomSetOnce::empty(), white_pixel })
+		Arc::new(Self {
+			tex: RwLock::new(None),
+			white_pixel,
+			last_used: Mutex::new(Instant::now()),
+			evicted_at: Mutex::new(None),
+			generation: AtomicU64::new(0),
+			size: AtomicUsize::new(0),
+			priority: AtomicU32::new(0),
+			version: AtomicU64::new(0),
+		})
 	}
 
+	/// Sets the texture if it hasn't been set yet. Otherwise `tex` is returned.
 	pub fn set_texture(&self, tex: Arc<dyn Texture + Send + Sync>) -> Option<Arc<dyn Texture + Send + Sync>> {
-		self.tex.set_if_none(Box::new(tex)).map(|tex| *tex)
+		let mut current = self.tex.write().unwrap();
+		if current.is_some() {
+			Some(tex)
+		} else {
+			self.size.store(image_size(&*tex.image()), Ordering::Relaxed);
+			*current = Some(tex);
+			self.version.fetch_add(1, Ordering::Relaxed);
+			None
+		}
+	}
+
+	/// Sets an image loaded by a load that started at `generation`, unless the texture was evicted since. Returns
+	/// whether it was set.
+	fn set_loaded(&self, tex: Arc<dyn Texture + Send + Sync>, generation: u64) -> bool {
+		// holding this lock keeps `evict` from running between the check and the update
+		let mut evicted_at = self.evicted_at.lock().unwrap();
+		if self.generation() != generation {
+			return false;
+		}
+		self.size.store(image_size(&*tex.image()), Ordering::Relaxed);
+		*self.tex.write().unwrap() = Some(tex);
+		*evicted_at = None;
+		self.version.fetch_add(1, Ordering::Relaxed);
+		true
+	}
+
+	fn evict(&self) {
+		let mut evicted_at = self.evicted_at.lock().unwrap();
+		*self.tex.write().unwrap() = None;
+		*evicted_at = Some(Instant::now());
+		self.generation.fetch_add(1, Ordering::Relaxed);
+		self.version.fetch_add(1, Ordering::Relaxed);
+	}
+
+	/// Starts a new generation for a reload, so older loads and streamed uploads of the previous file are dropped.
+	fn start_reload(&self) {
+		let _evicted_at = self.evicted_at.lock().unwrap();
+		self.generation.fetch_add(1, Ordering::Relaxed);
+	}
+
+	fn is_evicted(&self) -> bool {
+		self.evicted_at.lock().unwrap().is_some()
+	}
+
+	fn used_since_eviction(&self) -> bool {
+		self.evicted_at.lock().unwrap().map_or(false, |evicted_at| *self.last_used.lock().unwrap() > evicted_at)
+	}
+
+	fn size(&self) -> usize {
+		self.size.load(Ordering::Relaxed)
+	}
+
+	fn priority(&self) -> f32 {
+		f32::from_bits(self.priority.load(Ordering::Relaxed))
 	}
 }
 impl Texture for TextureResource {
-	fn image(&self) -> &Arc<dyn ImageViewAccess + Send + Sync> {
-		self.tex.get().unwrap_or(&self.white_pixel).image()
+	fn image(&self) -> Arc<dyn ImageViewAccess + Send + Sync> {
+		self.tex.read().unwrap().as_ref().unwrap_or(&self.white_pixel).image()
+	}
+
+	fn hint_priority(&self, priority: f32) {
+		*self.last_used.lock().unwrap() = Instant::now();
+		// the bits of non-negative floats order the same way as their values
+		self.priority.fetch_max(priority.max(0.0).to_bits(), Ordering::Relaxed);
+	}
+
+	fn version(&self) -> u64 {
+		self.version.load(Ordering::Relaxed)
+	}
+
+	fn generation(&self) -> u64 {
+		self.generation.load(Ordering::Relaxed)
 	}
 }
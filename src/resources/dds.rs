@@ -0,0 +1,139 @@
+use crate::{
+	texture::{level_size, ImmutableTexture, Texture, TextureCreationError},
+	vfs::Vfs,
+};
+use byteorder::{ReadBytesExt, LE};
+use log::debug;
+use std::{io::prelude::*, path::Path, sync::Arc};
+use vulkano::{device::Queue, format::Format, image::Dimensions, sync::GpuFuture};
+
+const DDSD_MIPMAPCOUNT: u32 = 0x20000;
+const DDPF_FOURCC: u32 = 0x4;
+const DDSCAPS2_CUBEMAP: u32 = 0x200;
+const DDS_RESOURCE_MISC_TEXTURECUBE: u32 = 0x4;
+
+pub(crate) fn from_dds(
+	queue: &Arc<Queue>,
+	vfs: &dyn Vfs,
+	path: impl AsRef<Path> + Clone + Send,
+) -> Result<(Arc<dyn Texture + Send + Sync>, Box<dyn GpuFuture + Send>), TextureCreationError> {
+	let mut fp = vfs.open(path.as_ref())?;
+
+	let mut magic_number = [0; 4];
+	fp.read_exact(&mut magic_number)?;
+	if &magic_number != b"DDS " {
+		return Err(invalid("not a dds file"));
+	}
+
+	let mut header = [0u32; 31];
+	fp.read_u32_into::<LE>(&mut header)?;
+	if header[0] != 124 {
+		return Err(invalid(format!("header size is {} instead of 124", header[0])));
+	}
+	let flags = header[1];
+	let height = header[2];
+	let width = header[3];
+	let mip_count = if flags & DDSD_MIPMAPCOUNT != 0 { header[6].max(1) } else { 1 };
+	let pf_flags = header[19];
+	let fourcc = header[20];
+	let mut cubemap = header[27] & DDSCAPS2_CUBEMAP != 0;
+	debug!(" => resolution: {}x{}", width, height);
+	if width == 0 || height == 0 {
+		return Err(invalid("the texture is empty"));
+	}
+
+	if pf_flags & DDPF_FOURCC == 0 {
+		return Err(invalid("only FourCC pixel formats are supported"));
+	}
+
+	let format = match &fourcc.to_le_bytes() {
+		b"DXT1" => Format::BC1_RGBAUnormBlock,
+		b"DXT2" | b"DXT3" => Format::BC2UnormBlock,
+		b"DXT4" | b"DXT5" => Format::BC3UnormBlock,
+		b"ATI1" | b"BC4U" => Format::BC4UnormBlock,
+		b"BC4S" => Format::BC4SnormBlock,
+		b"ATI2" | b"BC5U" => Format::BC5UnormBlock,
+		b"BC5S" => Format::BC5SnormBlock,
+		b"DX10" => {
+			let dxgi_format = fp.read_u32::<LE>()?;
+			let mut dx10_header = [0u32; 4];
+			fp.read_u32_into::<LE>(&mut dx10_header)?;
+			cubemap |= dx10_header[1] & DDS_RESOURCE_MISC_TEXTURECUBE != 0;
+			match dxgi_format {
+				2 => Format::R32G32B32A32Sfloat,
+				10 => Format::R16G16B16A16Sfloat,
+				28 => Format::R8G8B8A8Unorm,
+				29 => Format::R8G8B8A8Srgb,
+				71 => Format::BC1_RGBAUnormBlock,
+				72 => Format::BC1_RGBASrgbBlock,
+				74 => Format::BC2UnormBlock,
+				75 => Format::BC2SrgbBlock,
+				77 => Format::BC3UnormBlock,
+				78 => Format::BC3SrgbBlock,
+				80 => Format::BC4UnormBlock,
+				81 => Format::BC4SnormBlock,
+				83 => Format::BC5UnormBlock,
+				84 => Format::BC5SnormBlock,
+				95 => Format::BC6HUfloatBlock,
+				96 => Format::BC6HSfloatBlock,
+				98 => Format::BC7UnormBlock,
+				99 => Format::BC7SrgbBlock,
+				_ => return Err(invalid(format!("unsupported DXGI format {}", dxgi_format))),
+			}
+		},
+		_ => {
+			return Err(invalid(format!("unsupported FourCC {:?}", String::from_utf8_lossy(&fourcc.to_le_bytes()))));
+		},
+	};
+	debug!(" => format: {:?}", format);
+
+	let mut data = vec![];
+	fp.read_to_end(&mut data)?;
+
+	let (tex, tex_future) = if cubemap {
+		if width != height {
+			return Err(invalid("cubemap faces must be square"));
+		}
+		let data = faces_to_levels(data, width, mip_count, format)?;
+		ImmutableTexture::cubemap_from_compressed(queue.clone(), &data, width, format)?
+	} else {
+		// only the levels in the header's mip count are stored, anything after them isn't part of the chain
+		let level_sizes = level_sizes(format, Dimensions::Dim2d { width, height }, mip_count)?;
+		data.truncate(level_sizes.iter().sum());
+		ImmutableTexture::from_compressed(queue.clone(), &data, [width, height], format)?
+	};
+
+	Ok((Arc::new(tex), tex_future))
+}
+
+fn invalid(msg: impl Into<String>) -> TextureCreationError {
+	TextureCreationError::InvalidFile(msg.into())
+}
+
+fn level_sizes(format: Format, dimensions: Dimensions, mip_count: u32) -> Result<Vec<usize>, TextureCreationError> {
+	let mip_count = mip_count.min(dimensions.max_mipmaps());
+	(0..mip_count)
+		.map(|mip| level_size(format, dimensions.mipmap_dimensions(mip).unwrap()))
+		.collect::<Option<_>>()
+		.ok_or(TextureCreationError::UnsupportedFormat(format))
+}
+
+/// DDS stores each cubemap face with its whole mip chain, but the faces of each level must be adjacent for upload.
+fn faces_to_levels(data: Vec<u8>, size: u32, mip_count: u32, format: Format) -> Result<Vec<u8>, TextureCreationError> {
+	let level_sizes = level_sizes(format, Dimensions::Dim2d { width: size, height: size }, mip_count)?;
+	let face_size: usize = level_sizes.iter().sum();
+	if data.len() < face_size * 6 {
+		return Err(TextureCreationError::NotEnoughData);
+	}
+
+	let mut out = Vec::with_capacity(face_size * 6);
+	let mut level_offset = 0;
+	for level_size in level_sizes {
+		for face in 0..6 {
+			let offset = face * face_size + level_offset;
+			out.extend_from_slice(&data[offset..offset + level_size]);
+		}
+		level_offset += level_size;
+	}
+	Ok(out)
+}
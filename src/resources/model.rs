@@ -1,9 +1,10 @@
-use crate::mesh_data::{MeshData, Pntl_32F};
+use crate::{
+	mesh_data::{MeshData, Pntl_32F},
+	vfs::Vfs,
+};
 use byteorder::{ReadBytesExt, LE};
 use std::{
-	fs::File,
-	io::{prelude::*, SeekFrom},
-	mem::drop,
+	io::{self, prelude::*, SeekFrom},
 	ops::Range,
 	path::{Path, PathBuf},
 	sync::Arc,
@@ -15,30 +16,32 @@ use vulkano::{
 	sync::GpuFuture,
 };
 
+/// Fails if the file can't be read, ends early or is malformed, which is reported as `InvalidData`.
 pub(crate) fn from_nice_model(
 	queue: &Arc<Queue>,
+	vfs: &dyn Vfs,
 	path: impl AsRef<Path> + Clone + Send,
-) -> (Arc<MeshData>, Vec<MaterialInfo>, impl GpuFuture + Send + Sync + 'static) {
-	let device = queue.device();
-
-	let mut file = File::open(path.clone()).unwrap();
+) -> io::Result<(Arc<MeshData>, Vec<MaterialInfo>, impl GpuFuture + Send + Sync + 'static)> {
+	let mut file = vfs.open(path.as_ref())?;
 
 	let mut magic_number = [0; 4];
-	file.read_exact(&mut magic_number).unwrap();
-	assert_eq!(&magic_number, b"nmdl");
+	file.read_exact(&mut magic_number)?;
+	if &magic_number != b"nmdl" {
+		return Err(invalid_data("not an nmdl file"));
+	}
 
 	// skip version for now
-	file.seek(SeekFrom::Current(4)).unwrap();
-
-	let vertex_count = file.read_u32::<LE>().unwrap() as usize;
-	let positions_offset = file.read_u32::<LE>().unwrap() as u64;
-	let normals_offset = file.read_u32::<LE>().unwrap() as u64;
-	let texcoords_main_offset = file.read_u32::<LE>().unwrap() as u64;
-	let texcoords_lightmap_offset = file.read_u32::<LE>().unwrap() as u64;
-	let index_count = file.read_u32::<LE>().unwrap() as usize;
-	let indices_offset = file.read_u32::<LE>().unwrap() as u64;
-	let material_count = file.read_u8().unwrap() as usize;
-	let materials_offset = file.read_u32::<LE>().unwrap() as u64;
+	file.seek(SeekFrom::Current(4))?;
+
+	let vertex_count = file.read_u32::<LE>()? as usize;
+	let positions_offset = file.read_u32::<LE>()? as u64;
+	let normals_offset = file.read_u32::<LE>()? as u64;
+	let texcoords_main_offset = file.read_u32::<LE>()? as u64;
+	let texcoords_lightmap_offset = file.read_u32::<LE>()? as u64;
+	let index_count = file.read_u32::<LE>()? as usize;
+	let indices_offset = file.read_u32::<LE>()? as u64;
+	let material_count = file.read_u8()? as usize;
+	let materials_offset = file.read_u32::<LE>()? as u64;
 
 	// println!("vertex_count: {}", vertex_count);
 	// println!("positions_offset: {}", positions_offset);
@@ -50,93 +53,82 @@ pub(crate) fn from_nice_model(
 	// println!("material_count: {}", material_count);
 	// println!("materials_offset: {}", materials_offset);
 
-	let tmpbuf: Arc<CpuAccessibleBuffer<[Pntl_32F]>> = unsafe {
-		CpuAccessibleBuffer::uninitialized_array(device.clone(), vertex_count, BufferUsage::transfer_source()).unwrap()
-	};
-	let mut tmpbuf_lock = tmpbuf.write().unwrap();
-
-	file.seek(SeekFrom::Start(positions_offset)).unwrap();
-	for i in 0..vertex_count {
-		tmpbuf_lock[i].pos =
-			[file.read_f32::<LE>().unwrap(), file.read_f32::<LE>().unwrap(), file.read_f32::<LE>().unwrap()];
+	// everything is read before any buffers are created, so a truncated file can't leave them half written. the
+	// vectors aren't preallocated, since the counts aren't trustworthy until the data has been read.
+	let mut vertices = vec![];
+	file.seek(SeekFrom::Start(positions_offset))?;
+	for _ in 0..vertex_count {
+		let pos = [file.read_f32::<LE>()?, file.read_f32::<LE>()?, file.read_f32::<LE>()?];
+		vertices.push(Pntl_32F { pos, ..Pntl_32F::default() });
 	}
 
-	file.seek(SeekFrom::Start(normals_offset)).unwrap();
-	for i in 0..vertex_count {
-		tmpbuf_lock[i].nor =
-			[file.read_f32::<LE>().unwrap(), file.read_f32::<LE>().unwrap(), file.read_f32::<LE>().unwrap()];
+	file.seek(SeekFrom::Start(normals_offset))?;
+	for vertex in &mut vertices {
+		vertex.nor = [file.read_f32::<LE>()?, file.read_f32::<LE>()?, file.read_f32::<LE>()?];
 	}
 
-	file.seek(SeekFrom::Start(texcoords_main_offset)).unwrap();
-	for i in 0..vertex_count {
-		tmpbuf_lock[i].texc = [file.read_f32::<LE>().unwrap(), file.read_f32::<LE>().unwrap()];
+	file.seek(SeekFrom::Start(texcoords_main_offset))?;
+	for vertex in &mut vertices {
+		vertex.texc = [file.read_f32::<LE>()?, file.read_f32::<LE>()?];
 	}
 
-	file.seek(SeekFrom::Start(texcoords_lightmap_offset)).unwrap();
-	for i in 0..vertex_count {
-		tmpbuf_lock[i].lmap = [file.read_f32::<LE>().unwrap(), file.read_f32::<LE>().unwrap()];
+	file.seek(SeekFrom::Start(texcoords_lightmap_offset))?;
+	for vertex in &mut vertices {
+		vertex.lmap = [file.read_f32::<LE>()?, file.read_f32::<LE>()?];
 	}
 
-	drop(tmpbuf_lock);
-
-	let (vertices, vertices_future) =
-		ImmutableBuffer::from_buffer(tmpbuf, BufferUsage::vertex_buffer(), queue.clone()).unwrap();
-
-	let tmpbuf: Arc<CpuAccessibleBuffer<[u32]>> = unsafe {
-		CpuAccessibleBuffer::uninitialized_array(device.clone(), index_count, BufferUsage::transfer_source()).unwrap()
-	};
-	let mut tmpbuf_lock = tmpbuf.write().unwrap();
-
-	file.seek(SeekFrom::Start(indices_offset)).unwrap();
-	for i in 0..index_count {
-		tmpbuf_lock[i] = file.read_u32::<LE>().unwrap();
+	let mut indices = vec![];
+	file.seek(SeekFrom::Start(indices_offset))?;
+	for _ in 0..index_count {
+		indices.push(file.read_u32::<LE>()?);
+	}
+	if vertices.is_empty() || indices.is_empty() {
+		return Err(invalid_data("the model is empty"));
 	}
 
-	drop(tmpbuf_lock);
-
-	let (indices, indices_future) =
-		ImmutableBuffer::from_buffer(tmpbuf, BufferUsage::index_buffer(), queue.clone()).unwrap();
-
-	file.seek(SeekFrom::Start(materials_offset)).unwrap();
+	file.seek(SeekFrom::Start(materials_offset))?;
 	let mut index = 0;
 	let mut mat_infos = vec![];
 	for _ in 0..material_count {
-		let index_count = file.read_u32::<LE>().unwrap() as usize;
+		let index_count = file.read_u32::<LE>()? as usize;
 		let nextindex = index + index_count;
+		if nextindex > indices.len() {
+			return Err(invalid_data("a material uses more indices than there are"));
+		}
 
 		mat_infos.push(MaterialRaw {
 			range: index..nextindex,
-			texture1_name_size: file.read_u16::<LE>().unwrap(),
-			texture1_name_offset: file.read_u32::<LE>().unwrap(),
-			texture2_name_size: file.read_u16::<LE>().unwrap(),
-			texture2_name_offset: file.read_u32::<LE>().unwrap(),
-			light_penetration: file.read_u8().unwrap(),
-			subsurface_scattering: file.read_u8().unwrap(),
-			emissive_brightness: file.read_u16::<LE>().unwrap(),
-			base_color: [file.read_u8().unwrap(), file.read_u8().unwrap(), file.read_u8().unwrap()],
+			texture1_name_size: file.read_u16::<LE>()?,
+			texture1_name_offset: file.read_u32::<LE>()?,
+			texture2_name_size: file.read_u16::<LE>()?,
+			texture2_name_offset: file.read_u32::<LE>()?,
+			light_penetration: file.read_u8()?,
+			subsurface_scattering: file.read_u8()?,
+			emissive_brightness: file.read_u16::<LE>()?,
+			base_color: [file.read_u8()?, file.read_u8()?, file.read_u8()?],
 		});
 
 		index = nextindex;
 	}
 
-	let mut read_path = |path_offset: u64, path_size: usize| {
-		file.seek(SeekFrom::Start(path_offset)).unwrap();
+	let mut read_path = |path_offset: u64, path_size: usize| -> io::Result<PathBuf> {
+		file.seek(SeekFrom::Start(path_offset))?;
 		let mut buf = vec![0; path_size];
-		file.read_exact(&mut buf).unwrap();
-		let mut path_str = String::from_utf8(buf).unwrap();
+		file.read_exact(&mut buf)?;
+		let mut path_str = String::from_utf8(buf).map_err(|_| invalid_data("a texture path isn't valid UTF-8"))?;
 		// println!("read_path({}):", path_str);
 		if path_str.is_empty() {
 			path_str = "default.ntx".to_string();
 		};
-		path.as_ref().parent().unwrap().join(path_str)
+		Ok(path.as_ref().parent().unwrap_or_else(|| Path::new("")).join(path_str))
 	};
 
 	let mut mats = vec![];
 	for mat_info in mat_infos {
 		mats.push(MaterialInfo {
 			range: mat_info.range,
-			tex1: read_path(mat_info.texture1_name_offset as u64, mat_info.texture1_name_size as usize),
-			tex2: read_path(mat_info.texture2_name_offset as u64, mat_info.texture2_name_size as usize),
+			tex1: read_path(mat_info.texture1_name_offset as u64, mat_info.texture1_name_size as usize)?,
+			tex2: read_path(mat_info.texture2_name_offset as u64, mat_info.texture2_name_size as usize)?,
 			light_penetration: mat_info.light_penetration,
 			subsurface_scattering: mat_info.subsurface_scattering,
 			emissive_brightness: mat_info.emissive_brightness,
@@ -144,8 +136,23 @@ pub(crate) fn from_nice_model(
 		});
 	}
 
+	let device = queue.device();
+	let tmpbuf =
+		CpuAccessibleBuffer::from_iter(device.clone(), BufferUsage::transfer_source(), vertices.into_iter()).unwrap();
+	let (vertices, vertices_future) =
+		ImmutableBuffer::from_buffer(tmpbuf, BufferUsage::vertex_buffer(), queue.clone()).unwrap();
+
+	let tmpbuf =
+		CpuAccessibleBuffer::from_iter(device.clone(), BufferUsage::transfer_source(), indices.into_iter()).unwrap();
+	let (indices, indices_future) =
+		ImmutableBuffer::from_buffer(tmpbuf, BufferUsage::index_buffer(), queue.clone()).unwrap();
+
 	let mesh_data = MeshData::from_bufs_u32(vertices, indices, PrimitiveTopology::TriangleList);
-	(mesh_data, mats, vertices_future.join(indices_future))
+	Ok((mesh_data, mats, vertices_future.join(indices_future)))
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, msg)
 }
 
 pub(crate) struct MaterialInfo {
@@ -1,8 +1,13 @@
 use crate::texture::Texture;
 use crate::texture::ImmutableTexture;
+use crate::vfs::Vfs;
 use byteorder::{ReadBytesExt, LE};
 use log::debug;
-use std::{fs::File, io::prelude::*, path::Path, sync::Arc};
+use std::{
+	io::{self, prelude::*},
+	path::Path,
+	sync::Arc,
+};
 use vulkano::{
 	buffer::{BufferUsage, CpuAccessibleBuffer},
 	device::Queue,
@@ -10,45 +15,103 @@ use vulkano::{
 	sync::GpuFuture,
 };
 
-pub(crate) fn from_nice_texture(
-	queue: &Arc<Queue>,
-	path: impl AsRef<Path> + Clone + Send,
-) -> (Arc<dyn Texture + Send + Sync>, impl GpuFuture) {
-	let mut fp = File::open(path).unwrap();
+/// The top level of an ntx file, read into memory.
+pub(crate) struct NiceTexture {
+	width: u32,
+	height: u32,
+	format: Format,
+	data: Vec<u8>,
+}
+impl NiceTexture {
+	/// Fails if the file can't be read, ends early or is malformed, which is reported as `InvalidData`.
+	pub(crate) fn read(vfs: &dyn Vfs, path: impl AsRef<Path>) -> io::Result<Self> {
+		let mut fp = vfs.open(path.as_ref())?;
+
+		let mut magic_number = [0; 3];
+		fp.read_exact(&mut magic_number)?;
+		if &magic_number != b"ntx" {
+			return Err(io::Error::new(io::ErrorKind::InvalidData, "not an ntx file"));
+		}
+
+		let format = fp.read_u8()?;
+		let width = fp.read_u16::<LE>()?;
+		let height = fp.read_u16::<LE>()?;
+		debug!(" => resolution: {}x{}", width, height);
+		if width == 0 || height == 0 {
+			return Err(io::Error::new(io::ErrorKind::InvalidData, "the texture is empty"));
+		}
+
+		let (bpp, format) = match format {
+			0 => (32, Format::R8G8B8A8Srgb),
+			1 => (32, Format::R8G8B8A8Unorm),
+			2 => (32, Format::A2B10G10R10UnormPack32),
+			3 => (32, Format::A2B10G10R10UnormPack32),
+			4 => (64, Format::R16G16B16A16Sfloat),
+			5 => (128, Format::R32G32B32A32Sfloat),
+			_ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown format {}", format))),
+		};
+		let bytes = ((width as u64) * (height as u64) * (bpp as u64) + 7) / 8;
+
+		let mut data = vec![0; bytes as usize];
+		fp.read_exact(&mut data)?;
 
-	let mut magic_number = [0; 3];
-	fp.read_exact(&mut magic_number).unwrap();
-	if &magic_number != b"ntx" {
-		panic!("Invalid ntx file");
+		Ok(Self { width: width as u32, height: height as u32, format, data })
 	}
 
-	let format = fp.read_u8().unwrap();
-	let width = fp.read_u16::<LE>().unwrap();
-	let height = fp.read_u16::<LE>().unwrap();
-	debug!(" => resolution: {}x{}", width, height);
-
-	let (bpp, fmt) = match format {
-		0 => (32, Format::R8G8B8A8Srgb),
-		1 => (32, Format::R8G8B8A8Unorm),
-		2 => (32, Format::A2B10G10R10UnormPack32),
-		3 => (32, Format::A2B10G10R10UnormPack32),
-		4 => (64, Format::R16G16B16A16Sfloat),
-		5 => (128, Format::R32G32B32A32Sfloat),
-		_ => panic!("Invalid ntx file"),
-	};
-	let bytes = ((width as u64) * (height as u64) * (bpp as u64) + 7) / 8;
-
-	let pixbuf: Arc<CpuAccessibleBuffer<[u8]>> = unsafe {
-		CpuAccessibleBuffer::uninitialized_array(queue.device().clone(), bytes as usize, BufferUsage::transfer_source())
+	/// Box filters the texture down until neither side is longer than `max_size`. Returns `None` if it's already small
+	/// enough, or if its format can't be filtered on the CPU.
+	pub(crate) fn preview(&self, max_size: u32) -> Option<Self> {
+		if self.width.max(self.height) <= max_size {
+			return None;
+		}
+		match self.format {
+			Format::R8G8B8A8Srgb | Format::R8G8B8A8Unorm => (),
+			_ => return None,
+		}
+
+		let (mut width, mut height, mut data) = (self.width, self.height, self.data.clone());
+		while width.max(height) > max_size {
+			let (half_width, half_height) = ((width / 2).max(1), (height / 2).max(1));
+			let mut half = Vec::with_capacity((half_width * half_height * 4) as usize);
+			for y in 0..half_height {
+				for x in 0..half_width {
+					let texel = |x: u32, y: u32| {
+						let i = ((y.min(height - 1) * width + x.min(width - 1)) * 4) as usize;
+						&data[i..i + 4]
+					};
+					let texels = [
+						texel(x * 2, y * 2),
+						texel(x * 2 + 1, y * 2),
+						texel(x * 2, y * 2 + 1),
+						texel(x * 2 + 1, y * 2 + 1),
+					];
+					for c in 0..4 {
+						half.push((texels.iter().map(|t| t[c] as u32).sum::<u32>() / 4) as u8);
+					}
+				}
+			}
+			width = half_width;
+			height = half_height;
+			data = half;
+		}
+
+		Some(Self { width, height, format: self.format, data })
+	}
+
+	pub(crate) fn upload(&self, queue: &Arc<Queue>) -> (Arc<dyn Texture + Send + Sync>, impl GpuFuture) {
+		let pixbuf: Arc<CpuAccessibleBuffer<[u8]>> = unsafe {
+			CpuAccessibleBuffer::uninitialized_array(
+				queue.device().clone(),
+				self.data.len(),
+				BufferUsage::transfer_source(),
+			)
 			.unwrap()
-	};
-	{
-		let mut pixels = pixbuf.write().unwrap();
-		fp.read_exact(&mut pixels).unwrap();
-	};
+		};
+		pixbuf.write().unwrap().copy_from_slice(&self.data);
 
-	let (tex, tex_future) =
-		ImmutableTexture::from_buffer(queue.clone(), pixbuf, [width as u32, height as u32], fmt).unwrap();
+		let (tex, tex_future) =
+			ImmutableTexture::from_buffer(queue.clone(), pixbuf, [self.width, self.height], self.format).unwrap();
 
-	(Arc::new(tex), tex_future)
+		(Arc::new(tex), tex_future)
+	}
 }
@@ -0,0 +1,39 @@
+use image::{png::PNGEncoder, ColorType, RgbaImage};
+use std::{fs::File, io, path::Path};
+
+/// A frame copied from a surface, as 8-bit sRGB RGBA pixels with rows from top to bottom.
+pub struct Screenshot {
+	width: u32,
+	height: u32,
+	pixels: Vec<u8>,
+}
+impl Screenshot {
+	/// Converts pixels in the swapchain's BGRA order.
+	pub(crate) fn from_bgra(dimensions: [u32; 2], bgra: &[[u8; 4]]) -> Self {
+		let mut pixels = Vec::with_capacity(bgra.len() * 4);
+		for &[b, g, r, a] in bgra {
+			pixels.extend_from_slice(&[r, g, b, a]);
+		}
+		Self { width: dimensions[0], height: dimensions[1], pixels }
+	}
+
+	pub fn width(&self) -> u32 {
+		self.width
+	}
+
+	pub fn height(&self) -> u32 {
+		self.height
+	}
+
+	pub fn pixels(&self) -> &[u8] {
+		&self.pixels
+	}
+
+	pub fn into_image(self) -> RgbaImage {
+		RgbaImage::from_raw(self.width, self.height, self.pixels).unwrap()
+	}
+
+	pub fn save_png(&self, path: impl AsRef<Path>) -> io::Result<()> {
+		PNGEncoder::new(File::create(path)?).encode(&self.pixels, self.width, self.height, ColorType::RGBA(8))
+	}
+}
@@ -1,36 +1,69 @@
-use crate::{camera::Camera, pipelines::Pipeline, Context};
+use crate::{
+	camera::Camera,
+	frame_stats::{FrameStats, FrameStatsRecorder},
+	gpu_timer::{FrameQueries, GpuTimer, Stamp},
+	pipelines::{AmbientOcclusion, AntiAliasing, Pipeline, PipelineDef},
+	screenshot::Screenshot,
+	Context,
+};
 use std::{
 	os::raw::c_ulong,
 	sync::{Arc, Mutex},
 };
 use vulkano::{
+	buffer::{BufferUsage, CpuAccessibleBuffer},
+	command_buffer::{AutoCommandBuffer, AutoCommandBufferBuilder},
 	device::{Device, Queue},
 	format::Format,
+	image::SwapchainImage,
 	swapchain::{
-		acquire_next_image, AcquireError, PresentMode, Surface as VkSurface, SurfaceCreationError, SurfaceTransform,
-		Swapchain, SwapchainCreationError,
+		acquire_next_image, AcquireError, Surface as VkSurface, SurfaceCreationError, SurfaceTransform, Swapchain,
+		SwapchainCreationError,
 	},
 	sync::{FenceSignalFuture, GpuFuture},
 };
 
+pub use vulkano::swapchain::PresentMode;
+
 pub(crate) const SWAP_FORMAT: Format = Format::B8G8R8A8Srgb;
 
+/// Controls how a surface's swapchain presents images.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct SurfaceConfig {
+	/// Used if the surface supports it. Otherwise, or if this is `None`, the mode is chosen based on `vsync`.
+	pub present_mode: Option<PresentMode>,
+	/// Without vsync, Mailbox, Immediate, Relaxed and Fifo are tried in that order. With vsync, Fifo is always used.
+	pub vsync: bool,
+	/// The number of swapchain images, clamped to what the surface supports. `None` uses the minimum, which gives
+	/// the lowest latency.
+	pub image_count: Option<u32>,
+}
+
 pub struct Surface<W: Send + Sync + 'static = ()> {
+	ctx: Arc<Context>,
 	device: Arc<Device>,
 	queue: Arc<Queue>,
 	surface: Arc<VkSurface<W>>,
 	swapchain: Arc<Swapchain<W>>,
+	images: Vec<Arc<SwapchainImage<W>>>,
 	pipeline: Box<dyn Pipeline>,
+	aa: AntiAliasing,
+	ao: AmbientOcclusion,
+	config: SurfaceConfig,
 	prev_frame_end: Option<Arc<FenceSignalFuture<Box<dyn GpuFuture>>>>,
 	camera: Arc<Mutex<Camera>>,
+	stats: FrameStatsRecorder,
+	timer: Option<GpuTimer>,
+	capture_requested: bool,
+	capture: Option<Screenshot>,
 }
 impl<W: Send + Sync + 'static> Surface<W> {
 	#[cfg(feature = "window")]
-	pub(crate) fn from_vk(ctx: &Arc<Context>, surface: Arc<VkSurface<W>>) -> Self {
+	pub(crate) fn from_vk(ctx: &Arc<Context>, surface: Arc<VkSurface<W>>, config: SurfaceConfig) -> Self {
 		if !surface.is_supported(ctx.queue().family()).unwrap() {
 			panic!("Vulkan surface not supported");
 		}
-		Self::new_inner(ctx, surface)
+		Self::new_inner(ctx, surface, config)
 	}
 
 	pub fn camera(&self) -> &Arc<Mutex<Camera>> {
@@ -41,7 +74,66 @@ impl<W: Send + Sync + 'static> Surface<W> {
 		self.camera = camera;
 	}
 
+	/// Switches this surface to the pipeline `P`, which may differ from the one its `Context` uses by default.
+	pub fn set_pipeline<P: PipelineDef + 'static>(&mut self) {
+		let dimensions = self.swapchain.dimensions();
+		let images = self.images.iter().map(|i| i.clone() as _).collect();
+		self.pipeline = self.ctx.pipeline_ctx_for::<P>().make_pipeline(images, dimensions);
+		self.pipeline.set_anti_aliasing(self.aa);
+		self.pipeline.set_ambient_occlusion(self.ao);
+	}
+
+	pub fn anti_aliasing(&self) -> AntiAliasing {
+		self.aa
+	}
+
+	pub fn set_anti_aliasing(&mut self, aa: AntiAliasing) {
+		self.aa = aa;
+		self.pipeline.set_anti_aliasing(aa);
+	}
+
+	pub fn ambient_occlusion(&self) -> AmbientOcclusion {
+		self.ao
+	}
+
+	pub fn set_ambient_occlusion(&mut self, ao: AmbientOcclusion) {
+		self.ao = ao;
+		self.pipeline.set_ambient_occlusion(ao);
+	}
+
+	pub fn config(&self) -> SurfaceConfig {
+		self.config
+	}
+
+	/// Rebuilds the swapchain if the config changed. If it can't be rebuilt at the current size, the config is applied
+	/// by the next `resize` instead.
+	pub fn set_config(&mut self, config: SurfaceConfig) {
+		if config == self.config {
+			return;
+		}
+
+		self.config = config;
+		self.recreate_swapchain(self.swapchain.dimensions());
+	}
+
+	/// Timings and counts from recent calls to `draw`.
+	pub fn stats(&self) -> FrameStats {
+		self.stats.stats()
+	}
+
+	/// Copies the next frame into host memory after it's drawn. It can be retrieved with `take_capture`.
+	pub fn capture_next_frame(&mut self) {
+		self.capture_requested = true;
+	}
+
+	/// Returns the most recently captured frame, if it hasn't already been taken.
+	pub fn take_capture(&mut self) -> Option<Screenshot> {
+		self.capture.take()
+	}
+
 	pub fn draw(&mut self) {
+		let start = self.stats.start_frame();
+
 		let (image_num, acquire_future) = match acquire_next_image(self.swapchain.clone(), None) {
 			Ok(r) => r,
 			Err(AcquireError::OutOfDate) => {
@@ -56,34 +148,88 @@ impl<W: Send + Sync + 'static> Surface<W> {
 			Box::new(acquire_future)
 		};
 
+		let queries = self.timer.as_mut().map(GpuTimer::start_frame);
 		let camera = self.camera.lock().unwrap();
-		let before_execute = before_execute
-			.then_execute(self.queue.clone(), self.pipeline.draw(image_num, self.queue.family(), &camera, &[]))
-			.unwrap()
-			.then_swapchain_present(self.queue.clone(), self.swapchain.clone(), image_num);
+		let lights = camera.mesh_group().lights().lock().unwrap();
+		let (command_buffer, counts) =
+			self.pipeline.draw(image_num, self.queue.family(), &camera, &lights, queries.as_ref());
+		let mut before_execute = camera
+			.mesh_group()
+			.irradiance_futures()
+			.into_iter()
+			.fold(before_execute, |future, irradiance| Box::new(future.join(irradiance)) as Box<dyn GpuFuture>);
+		if let Some(queries) = &queries {
+			let start = self.timestamp(queries, Stamp::Start);
+			before_execute = Box::new(before_execute.then_execute(self.queue.clone(), start).unwrap());
+		}
+		let mut before_execute: Box<dyn GpuFuture> =
+			Box::new(before_execute.then_execute(self.queue.clone(), command_buffer).unwrap());
+		if let Some(queries) = &queries {
+			let end = self.timestamp(queries, Stamp::End);
+			before_execute = Box::new(before_execute.then_execute(self.queue.clone(), end).unwrap());
+		}
+
+		let capture = if self.capture_requested { Some(self.make_capture_buffer()) } else { None };
+		let before_execute: Box<dyn GpuFuture> = match &capture {
+			Some(buffer) => {
+				let copy = AutoCommandBufferBuilder::primary_one_time_submit(self.device.clone(), self.queue.family())
+					.unwrap()
+					.copy_image_to_buffer(self.images[image_num].clone(), buffer.clone())
+					.unwrap()
+					.build()
+					.unwrap();
+				Box::new(before_execute.then_execute(self.queue.clone(), copy).unwrap())
+			},
+			None => Box::new(before_execute),
+		};
+		self.capture_requested = false;
+
+		let before_execute =
+			before_execute.then_swapchain_present(self.queue.clone(), self.swapchain.clone(), image_num);
 
 		let future = (Box::new(before_execute) as Box<dyn GpuFuture>).then_signal_fence_and_flush();
 		if let Some(prev_frame_end) = &mut self.prev_frame_end {
 			prev_frame_end.wait(None).unwrap();
 			prev_frame_end.cleanup_finished();
 		}
+		if let Some(times) = self.timer.as_mut().and_then(GpuTimer::read_previous) {
+			self.stats.add_gpu_times(times);
+		}
 		self.prev_frame_end = match future {
 			Ok(future) => Some(Arc::new(future)),
 			Err(e) => {
 				println!("{:?}", e);
+				if let Some(timer) = &mut self.timer {
+					timer.abandon_latest();
+				}
 				None
 			},
 		};
+
+		// screenshots are rare enough that stalling until this frame is done is fine
+		if let (Some(buffer), Some(prev_frame_end)) = (capture, &self.prev_frame_end) {
+			prev_frame_end.wait(None).unwrap();
+			let dimensions = self.swapchain.dimensions();
+			self.capture = Some(Screenshot::from_bgra(dimensions, &buffer.read().unwrap()));
+		}
+
+		self.stats.end_frame(start, counts);
 	}
 
 	pub fn resize(&mut self, width: u32, height: u32) {
 		let dimensions =
 			self.surface.capabilities(self.device.physical_device()).unwrap().current_extent.unwrap_or([width, height]);
 
-		match self.swapchain.recreate_with_dimension(dimensions) {
+		self.recreate_swapchain(dimensions);
+	}
+
+	fn recreate_swapchain(&mut self, dimensions: [u32; 2]) {
+		let old_swapchain = Some(&self.swapchain);
+		match create_swapchain(&self.device, &self.queue, &self.surface, self.config, dimensions, old_swapchain) {
 			Ok((swapchain, images)) => {
-				self.pipeline.resize(images.into_iter().map(|i| i as _).collect(), dimensions);
+				self.pipeline.resize(images.iter().map(|i| i.clone() as _).collect(), dimensions);
 				self.swapchain = swapchain;
+				self.images = images;
 			},
 			// this normally happens when the window was resized after getting the surface capabilities, but before
 			// recreating the swapchain. there should be another resize event on the next frame so we just ignore the
@@ -93,50 +239,62 @@ impl<W: Send + Sync + 'static> Surface<W> {
 		}
 	}
 
+	/// Records a command buffer that only writes a timestamp.
+	fn timestamp(&self, queries: &FrameQueries, stamp: Stamp) -> AutoCommandBuffer {
+		let command_buffer =
+			AutoCommandBufferBuilder::primary_one_time_submit(self.device.clone(), self.queue.family()).unwrap();
+		queries.write(command_buffer, stamp, None).build().unwrap()
+	}
+
+	fn make_capture_buffer(&self) -> Arc<CpuAccessibleBuffer<[[u8; 4]]>> {
+		let [width, height] = self.swapchain.dimensions();
+		unsafe {
+			CpuAccessibleBuffer::uninitialized_array(
+				self.device.clone(),
+				(width * height) as usize,
+				BufferUsage::transfer_destination(),
+			)
+			.unwrap()
+		}
+	}
+
 	pub(crate) fn window(&self) -> &W {
 		self.surface.window()
 	}
 
-	fn new_inner(ctx: &Arc<Context>, surface: Arc<VkSurface<W>>) -> Self {
+	fn new_inner(ctx: &Arc<Context>, surface: Arc<VkSurface<W>>, config: SurfaceConfig) -> Self {
 		let device = ctx.device().clone();
 		let queue = ctx.queue().clone();
-		let caps = surface.capabilities(device.physical_device()).unwrap();
-
-		let dimensions = caps.current_extent.unwrap();
-
-		let mode = if caps.present_modes.mailbox {
-			PresentMode::Mailbox
-		} else if caps.present_modes.immediate {
-			PresentMode::Immediate
-		} else if caps.present_modes.relaxed {
-			PresentMode::Relaxed
-		} else {
-			PresentMode::Fifo
-		};
+		let dimensions = surface.capabilities(device.physical_device()).unwrap().current_extent.unwrap();
+		let (swapchain, images) =
+			create_swapchain(&device, &queue, &surface, config, dimensions, None).expect("failed to create swapchain");
 
-		let (swapchain, images) = Swapchain::new(
-			device.clone(),
-			surface.clone(),
-			caps.min_image_count,
-			SWAP_FORMAT,
-			dimensions,
-			1,
-			caps.supported_usage_flags,
-			&queue,
-			SurfaceTransform::Identity,
-			caps.supported_composite_alpha.iter().next().unwrap(),
-			mode,
-			true,
-			None,
-		)
-		.expect("failed to create swapchain");
-
-		let pipeline = ctx.pipeline_ctx().make_pipeline(images.into_iter().map(|i| i as _).collect(), dimensions);
+		let pipeline = ctx.pipeline_ctx().make_pipeline(images.iter().map(|i| i.clone() as _).collect(), dimensions);
+		let aa = AntiAliasing::default();
+		let ao = AmbientOcclusion::default();
 		let prev_frame_end = None;
+		let timer = GpuTimer::new(&queue);
 
 		let camera = Arc::new(Mutex::new(Camera::new(ctx)));
 
-		Self { device, queue, surface, swapchain, pipeline, prev_frame_end, camera }
+		Self {
+			ctx: ctx.clone(),
+			device,
+			queue,
+			surface,
+			swapchain,
+			images,
+			pipeline,
+			aa,
+			ao,
+			config,
+			prev_frame_end,
+			camera,
+			stats: FrameStatsRecorder::default(),
+			timer,
+			capture_requested: false,
+			capture: None,
+		}
 	}
 }
 impl Surface<()> {
@@ -144,23 +302,66 @@ impl Surface<()> {
 		ctx: &Arc<Context>,
 		hinstance: *const T,
 		hwnd: *const U,
+		config: SurfaceConfig,
 	) -> Result<Self, SurfaceCreationError> {
-		Ok(Self::new_inner(ctx, VkSurface::from_hwnd(ctx.instance.clone(), hinstance, hwnd, ())?))
+		Ok(Self::new_inner(ctx, VkSurface::from_hwnd(ctx.instance.clone(), hinstance, hwnd, ())?, config))
 	}
 
 	pub unsafe fn from_xlib<D>(
 		ctx: &Arc<Context>,
 		display: *const D,
 		surface: c_ulong,
+		config: SurfaceConfig,
 	) -> Result<Self, SurfaceCreationError> {
-		Ok(Self::new_inner(ctx, VkSurface::from_xlib(ctx.instance.clone(), display, surface, ())?))
+		Ok(Self::new_inner(ctx, VkSurface::from_xlib(ctx.instance.clone(), display, surface, ())?, config))
 	}
 
 	pub unsafe fn from_wayland<D, S>(
 		ctx: &Arc<Context>,
 		display: *const D,
 		surface: *const S,
+		config: SurfaceConfig,
 	) -> Result<Self, SurfaceCreationError> {
-		Ok(Self::new_inner(ctx, VkSurface::from_wayland(ctx.instance.clone(), display, surface, ())?))
+		Ok(Self::new_inner(ctx, VkSurface::from_wayland(ctx.instance.clone(), display, surface, ())?, config))
 	}
 }
+
+fn create_swapchain<W: Send + Sync + 'static>(
+	device: &Arc<Device>,
+	queue: &Arc<Queue>,
+	surface: &Arc<VkSurface<W>>,
+	config: SurfaceConfig,
+	dimensions: [u32; 2],
+	old_swapchain: Option<&Arc<Swapchain<W>>>,
+) -> Result<(Arc<Swapchain<W>>, Vec<Arc<SwapchainImage<W>>>), SwapchainCreationError> {
+	let caps = surface.capabilities(device.physical_device()).unwrap();
+
+	let modes = caps.present_modes;
+	let mode = match config.present_mode {
+		Some(mode) if modes.supports(mode) => mode,
+		_ if config.vsync => PresentMode::Fifo,
+		_ if modes.mailbox => PresentMode::Mailbox,
+		_ if modes.immediate => PresentMode::Immediate,
+		_ if modes.relaxed => PresentMode::Relaxed,
+		_ => PresentMode::Fifo,
+	};
+
+	let max_image_count = caps.max_image_count.unwrap_or(u32::max_value());
+	let image_count = config.image_count.unwrap_or(caps.min_image_count).max(caps.min_image_count).min(max_image_count);
+
+	Swapchain::new(
+		device.clone(),
+		surface.clone(),
+		image_count,
+		SWAP_FORMAT,
+		dimensions,
+		1,
+		caps.supported_usage_flags,
+		queue,
+		SurfaceTransform::Identity,
+		caps.supported_composite_alpha.iter().next().unwrap(),
+		mode,
+		true,
+		old_swapchain,
+	)
+}
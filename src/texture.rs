@@ -1,12 +1,58 @@
+mod bc;
 mod immutable;
 mod target;
 
-pub use immutable::ImmutableTexture;
+pub use immutable::{ImmutableTexture, TextureCreationError};
 pub use target::TargetTexture;
 
 use std::sync::Arc;
-use vulkano::image::ImageViewAccess;
+use vulkano::{
+	format::Format,
+	image::{Dimensions, ImageViewAccess},
+};
 
 pub trait Texture {
-	fn image(&self) -> &Arc<dyn ImageViewAccess + Send + Sync>;
+	fn image(&self) -> Arc<dyn ImageViewAccess + Send + Sync>;
+
+	/// Tells textures that are streamed in progressively how soon their full resolution is needed. Higher is sooner.
+	/// The pipelines hint every texture they draw with a priority based on its mesh's distance to the camera.
+	fn hint_priority(&self, _priority: f32) {}
+
+	/// Changes whenever `image` starts returning a different image, so users only have to compare images when it does.
+	fn version(&self) -> u64 {
+		0
+	}
+
+	/// Changes when the texture's contents are replaced, like when it's evicted or reloaded, but not when the same
+	/// contents are streamed in at a higher resolution.
+	fn generation(&self) -> u64 {
+		0
+	}
+}
+
+/// The size in bytes of every level and array layer of an image.
+pub(crate) fn image_size(image: &dyn ImageViewAccess) -> usize {
+	let inner = image.parent().inner();
+	let format = inner.image.format();
+	let dimensions = image.dimensions();
+	let [width, height, depth] = dimensions.width_height_depth();
+	let levels = (0..inner.num_mipmap_levels as u32)
+		.map(|level| {
+			let level_dimensions =
+				Dimensions::Dim2d { width: (width >> level).max(1), height: (height >> level).max(1) };
+			level_size(format, level_dimensions).expect("images are only created in formats with a known size")
+				* (depth >> level).max(1) as usize
+		})
+		.sum::<usize>();
+	levels * dimensions.array_layers_with_cube() as usize
+}
+
+/// The size in bytes of a single array layer of a level with these dimensions, or `None` if `format` is compressed
+/// with something other than BC.
+pub(crate) fn level_size(format: Format, dimensions: Dimensions) -> Option<usize> {
+	let [width, height, _] = dimensions.width_height_depth();
+	match bc::block_size(format) {
+		Some(block_size) => Some(((width as usize + 3) / 4) * ((height as usize + 3) / 4) * block_size),
+		None => format.size().map(|size| width as usize * height as usize * size),
+	}
 }
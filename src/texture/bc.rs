@@ -0,0 +1,164 @@
+//! CPU decoding of block-compressed formats, used when the device doesn't support `texture_compression_bc`.
+
+use vulkano::format::Format::{self, *};
+
+/// Returns the size in bytes of one 4x4 block, or `None` if `format` isn't a BC format.
+pub(crate) fn block_size(format: Format) -> Option<usize> {
+	match format {
+		BC1_RGBUnormBlock | BC1_RGBSrgbBlock | BC1_RGBAUnormBlock | BC1_RGBASrgbBlock | BC4UnormBlock
+		| BC4SnormBlock => Some(8),
+		BC2UnormBlock | BC2SrgbBlock | BC3UnormBlock | BC3SrgbBlock | BC5UnormBlock | BC5SnormBlock
+		| BC6HUfloatBlock | BC6HSfloatBlock | BC7UnormBlock | BC7SrgbBlock => Some(16),
+		_ => None,
+	}
+}
+
+/// Decodes the first mip level of `data` to RGBA8. Returns the format the pixels should be uploaded as, or `None` if
+/// decoding `format` isn't supported (BC6H, BC7, and the signed BC4 and BC5 variants).
+pub(crate) fn decompress(format: Format, data: &[u8], width: u32, height: u32) -> Option<(Format, Vec<[u8; 4]>)> {
+	let (out_format, decode_block): (_, fn(&[u8], &mut [[u8; 4]; 16])) = match format {
+		BC1_RGBUnormBlock | BC1_RGBAUnormBlock => (R8G8B8A8Unorm, decode_bc1),
+		BC1_RGBSrgbBlock | BC1_RGBASrgbBlock => (R8G8B8A8Srgb, decode_bc1),
+		BC2UnormBlock => (R8G8B8A8Unorm, decode_bc2),
+		BC2SrgbBlock => (R8G8B8A8Srgb, decode_bc2),
+		BC3UnormBlock => (R8G8B8A8Unorm, decode_bc3),
+		BC3SrgbBlock => (R8G8B8A8Srgb, decode_bc3),
+		BC4UnormBlock => (R8G8B8A8Unorm, decode_bc4),
+		BC5UnormBlock => (R8G8B8A8Unorm, decode_bc5),
+		_ => return None,
+	};
+	let block_size = block_size(format).unwrap();
+
+	let (width, height) = (width as usize, height as usize);
+	let blocks_x = (width + 3) / 4;
+	let blocks_y = (height + 3) / 4;
+	if data.len() < blocks_x * blocks_y * block_size {
+		return None;
+	}
+
+	let mut pixels = vec![[0; 4]; width * height];
+	let mut block = [[0; 4]; 16];
+	for by in 0..blocks_y {
+		for bx in 0..blocks_x {
+			let offset = (by * blocks_x + bx) * block_size;
+			decode_block(&data[offset..offset + block_size], &mut block);
+
+			for py in 0..4 {
+				for px in 0..4 {
+					let (x, y) = (bx * 4 + px, by * 4 + py);
+					if x < width && y < height {
+						pixels[y * width + x] = block[py * 4 + px];
+					}
+				}
+			}
+		}
+	}
+
+	Some((out_format, pixels))
+}
+
+fn decode_bc1(data: &[u8], out: &mut [[u8; 4]; 16]) {
+	decode_color(data, out, true);
+}
+
+fn decode_bc2(data: &[u8], out: &mut [[u8; 4]; 16]) {
+	decode_color(&data[8..], out, false);
+	for i in 0..16 {
+		let alpha = (data[i / 2] >> (4 * (i % 2))) & 0xF;
+		out[i][3] = alpha * 17;
+	}
+}
+
+fn decode_bc3(data: &[u8], out: &mut [[u8; 4]; 16]) {
+	decode_color(&data[8..], out, false);
+	let mut alpha = [0; 16];
+	decode_channel(data, &mut alpha);
+	for i in 0..16 {
+		out[i][3] = alpha[i];
+	}
+}
+
+fn decode_bc4(data: &[u8], out: &mut [[u8; 4]; 16]) {
+	let mut red = [0; 16];
+	decode_channel(data, &mut red);
+	for i in 0..16 {
+		out[i] = [red[i], 0, 0, 255];
+	}
+}
+
+fn decode_bc5(data: &[u8], out: &mut [[u8; 4]; 16]) {
+	let mut red = [0; 16];
+	let mut green = [0; 16];
+	decode_channel(&data[..8], &mut red);
+	decode_channel(&data[8..], &mut green);
+	for i in 0..16 {
+		out[i] = [red[i], green[i], 0, 255];
+	}
+}
+
+/// Decodes an 8-byte BC1 color block. BC2 and BC3 always use the four-color mode, so `allow_alpha` is false for them.
+fn decode_color(data: &[u8], out: &mut [[u8; 4]; 16], allow_alpha: bool) {
+	let c0 = u16::from_le_bytes([data[0], data[1]]);
+	let c1 = u16::from_le_bytes([data[2], data[3]]);
+	let rgb0 = rgb565(c0);
+	let rgb1 = rgb565(c1);
+
+	let mut palette = [[0; 4]; 4];
+	palette[0] = [rgb0[0], rgb0[1], rgb0[2], 255];
+	palette[1] = [rgb1[0], rgb1[1], rgb1[2], 255];
+	if c0 > c1 || !allow_alpha {
+		for c in 0..3 {
+			palette[2][c] = ((2 * rgb0[c] as u32 + rgb1[c] as u32) / 3) as u8;
+			palette[3][c] = ((rgb0[c] as u32 + 2 * rgb1[c] as u32) / 3) as u8;
+		}
+		palette[2][3] = 255;
+		palette[3][3] = 255;
+	} else {
+		for c in 0..3 {
+			palette[2][c] = ((rgb0[c] as u32 + rgb1[c] as u32) / 2) as u8;
+		}
+		palette[2][3] = 255;
+		palette[3] = [0; 4];
+	}
+
+	let indices = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+	for i in 0..16 {
+		out[i] = palette[((indices >> (2 * i)) & 0x3) as usize];
+	}
+}
+
+/// Decodes an 8-byte single-channel block, as used for BC3 alpha and BC4/BC5 channels.
+fn decode_channel(data: &[u8], out: &mut [u8; 16]) {
+	let a0 = data[0] as u32;
+	let a1 = data[1] as u32;
+
+	let mut palette = [0; 8];
+	palette[0] = a0;
+	palette[1] = a1;
+	if a0 > a1 {
+		for i in 1..7 {
+			palette[i + 1] = ((7 - i) as u32 * a0 + i as u32 * a1) / 7;
+		}
+	} else {
+		for i in 1..5 {
+			palette[i + 1] = ((5 - i) as u32 * a0 + i as u32 * a1) / 5;
+		}
+		palette[6] = 0;
+		palette[7] = 255;
+	}
+
+	let mut indices = 0u64;
+	for i in 0..6 {
+		indices |= (data[2 + i] as u64) << (8 * i);
+	}
+	for i in 0..16 {
+		out[i] = palette[((indices >> (3 * i)) & 0x7) as usize] as u8;
+	}
+}
+
+fn rgb565(color: u16) -> [u8; 3] {
+	let r = ((color >> 11) & 0x1F) as u32;
+	let g = ((color >> 5) & 0x3F) as u32;
+	let b = (color & 0x1F) as u32;
+	[(r * 255 / 31) as u8, (g * 255 / 63) as u8, (b * 255 / 31) as u8]
+}
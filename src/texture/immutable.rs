@@ -1,13 +1,13 @@
 mod mipmaps_command_buffer;
 
-use self::mipmaps_command_buffer::MipmapsCommandBuffer;
-use super::Texture;
-use std::sync::Arc;
+use self::mipmaps_command_buffer::{MipmapSource, MipmapsCommandBuffer};
+use super::{bc, level_size, Texture};
+use std::{error, fmt, io, sync::Arc};
 use vulkano::{
 	buffer::{BufferAccess, BufferUsage, CpuAccessibleBuffer, TypedBufferAccess},
 	command_buffer::CommandBuffer,
 	device::Queue,
-	format::{AcceptsPixels, Format, FormatDesc},
+	format::{AcceptsPixels, Format, FormatDesc, FormatTy},
 	image::{Dimensions, ImageCreationError, ImageLayout, ImageUsage, ImageViewAccess, ImmutableImage, MipmapsCount},
 	sync::GpuFuture,
 };
@@ -34,6 +34,24 @@ impl ImmutableTexture {
 		Self::from_buffer(queue, buffer, dimensions, format)
 	}
 
+	/// Creates a cubemap from six square faces in the order +X, -X, +Y, -Y, +Z, -Z.
+	pub fn cubemap_from_iter_vk<F, P, I>(
+		queue: Arc<Queue>,
+		iter: I,
+		size: u32,
+		format: F,
+	) -> Result<(Self, impl GpuFuture), ImageCreationError>
+	where
+		P: Send + Sync + Clone + 'static,
+		F: FormatDesc + AcceptsPixels<P> + 'static + Send + Sync,
+		I: ExactSizeIterator<Item = P>,
+		Format: AcceptsPixels<P>,
+	{
+		let buffer =
+			CpuAccessibleBuffer::from_iter(queue.device().clone(), BufferUsage::transfer_source(), iter).unwrap();
+		Self::from_buffer_with_dimensions(queue, buffer, Dimensions::Cubemap { size }, format)
+	}
+
 	pub(crate) fn from_buffer<F, B, P>(
 		queue: Arc<Queue>,
 		buffer: B,
@@ -46,9 +64,23 @@ impl ImmutableTexture {
 		P: Send + Sync + Clone + 'static,
 		Format: AcceptsPixels<P>,
 	{
-		let device = queue.device();
-
 		let dimensions = Dimensions::Dim2d { width: dimensions[0], height: dimensions[1] };
+		Self::from_buffer_with_dimensions(queue, buffer, dimensions, format)
+	}
+
+	fn from_buffer_with_dimensions<F, B, P>(
+		queue: Arc<Queue>,
+		buffer: B,
+		dimensions: Dimensions,
+		format: F,
+	) -> Result<(Self, impl GpuFuture), ImageCreationError>
+	where
+		F: FormatDesc + AcceptsPixels<P> + Send + Sync + 'static,
+		B: BufferAccess + TypedBufferAccess<Content = [P]> + Clone + Send + Sync + 'static,
+		P: Send + Sync + Clone + 'static,
+		Format: AcceptsPixels<P>,
+	{
+		let device = queue.device();
 
 		let (image, init) = ImmutableImage::uninitialized(
 			device.clone(),
@@ -60,13 +92,228 @@ impl ImmutableTexture {
 			device.active_queue_families(),
 		)?;
 
-		let future = MipmapsCommandBuffer::new(device.clone(), queue.family(), buffer, init).execute(queue).unwrap();
+		let future = MipmapsCommandBuffer::new(device.clone(), queue.family(), buffer, init, MipmapSource::Generate)
+			.execute(queue)
+			.unwrap();
 
 		Ok((Self { image }, future))
 	}
+
+	/// Creates a texture from data that already contains its mip chain, tightly packed from the largest level down.
+	/// As many levels as fit in `data` are used. This is the only way to upload block-compressed formats.
+	///
+	/// If `format` is a BC format and the device doesn't support `texture_compression_bc`, the first level is decoded
+	/// on the CPU instead and mipmaps are generated from it.
+	pub fn from_compressed(
+		queue: Arc<Queue>,
+		data: &[u8],
+		dimensions: [u32; 2],
+		format: Format,
+	) -> Result<(Self, Box<dyn GpuFuture + Send>), TextureCreationError> {
+		let dimensions = Dimensions::Dim2d { width: dimensions[0], height: dimensions[1] };
+		Self::from_compressed_with_dimensions(queue, data, dimensions, format)
+	}
+
+	/// Like `from_compressed`, but creates a cubemap. Each level holds all six faces in the order +X, -X, +Y, -Y, +Z,
+	/// -Z before the next level starts.
+	pub fn cubemap_from_compressed(
+		queue: Arc<Queue>,
+		data: &[u8],
+		size: u32,
+		format: Format,
+	) -> Result<(Self, Box<dyn GpuFuture + Send>), TextureCreationError> {
+		Self::from_compressed_with_dimensions(queue, data, Dimensions::Cubemap { size }, format)
+	}
+
+	fn from_compressed_with_dimensions(
+		queue: Arc<Queue>,
+		data: &[u8],
+		dimensions: Dimensions,
+		format: Format,
+	) -> Result<(Self, Box<dyn GpuFuture + Send>), TextureCreationError> {
+		let device = queue.device().clone();
+		let layers = dimensions.array_layers_with_cube() as usize;
+		let (offsets, size) = level_offsets(format, dimensions, data.len())?;
+		let layer_size = level_size(format, dimensions).unwrap();
+
+		if format.ty() == FormatTy::Compressed && !device.enabled_features().texture_compression_bc {
+			log::warn!("texture_compression_bc is not supported, decompressing {:?} texture on the CPU", format);
+			let mut decoded = None;
+			let mut pixels = vec![];
+			for layer in 0..layers {
+				let (decoded_format, layer_pixels) =
+					bc::decompress(format, &data[layer * layer_size..], dimensions.width(), dimensions.height())
+						.ok_or(TextureCreationError::UnsupportedFormat(format))?;
+				decoded = Some(decoded_format);
+				pixels.extend(layer_pixels);
+			}
+			let buffer =
+				CpuAccessibleBuffer::from_iter(device, BufferUsage::transfer_source(), pixels.into_iter()).unwrap();
+			let (tex, future) = Self::from_buffer_with_dimensions(queue, buffer, dimensions, decoded.unwrap())?;
+			return Ok((tex, Box::new(future)));
+		}
+
+		let (image, init) = ImmutableImage::uninitialized(
+			device.clone(),
+			dimensions,
+			format,
+			MipmapsCount::Specific(offsets.len() as u32),
+			ImageUsage { transfer_destination: true, sampled: true, ..ImageUsage::none() },
+			ImageLayout::ShaderReadOnlyOptimal,
+			device.active_queue_families(),
+		)?;
+
+		let buffer = CpuAccessibleBuffer::from_iter(
+			device.clone(),
+			BufferUsage::transfer_source(),
+			data[..size].iter().cloned(),
+		)
+		.unwrap();
+
+		let future = MipmapsCommandBuffer::new(device, queue.family(), buffer, init, MipmapSource::Provided(offsets))
+			.execute(queue)
+			.unwrap();
+
+		Ok((Self { image }, Box::new(future)))
+	}
 }
+
+/// The byte offset of every level that fits in `len` bytes, and the total size of those levels.
+fn level_offsets(
+	format: Format,
+	dimensions: Dimensions,
+	len: usize,
+) -> Result<(Vec<usize>, usize), TextureCreationError> {
+	let layers = dimensions.array_layers_with_cube() as usize;
+	let mut offsets = vec![];
+	let mut offset = 0;
+	while (offsets.len() as u32) < dimensions.max_mipmaps() {
+		let level_dimensions = dimensions.mipmap_dimensions(offsets.len() as u32).unwrap();
+		let size =
+			level_size(format, level_dimensions).ok_or(TextureCreationError::UnsupportedFormat(format))? * layers;
+		if offset + size > len {
+			break;
+		}
+		offsets.push(offset);
+		offset += size;
+	}
+	if offsets.is_empty() {
+		return Err(TextureCreationError::NotEnoughData);
+	}
+	Ok((offsets, offset))
+}
+
 impl Texture for ImmutableTexture {
-	fn image(&self) -> &Arc<dyn ImageViewAccess + Send + Sync> {
-		&self.image
+	fn image(&self) -> Arc<dyn ImageViewAccess + Send + Sync> {
+		self.image.clone()
+	}
+}
+
+#[derive(Debug)]
+pub enum TextureCreationError {
+	ImageCreation(ImageCreationError),
+	/// The format is compressed with something other than BC, or it's a BC format that can't be decoded on the CPU
+	/// (BC6H, BC7, and the signed BC4 and BC5 variants) while the device doesn't support `texture_compression_bc`.
+	UnsupportedFormat(Format),
+	/// The data doesn't even hold the first level.
+	NotEnoughData,
+	/// The file couldn't be read, or it ended early.
+	Io(io::Error),
+	/// The file is malformed, or uses a feature of its format that isn't supported.
+	InvalidFile(String),
+}
+impl fmt::Display for TextureCreationError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			TextureCreationError::ImageCreation(err) => write!(f, "failed to create image: {}", err),
+			TextureCreationError::UnsupportedFormat(format) => write!(f, "{:?} textures are not supported", format),
+			TextureCreationError::NotEnoughData => write!(f, "not enough data for the first level"),
+			TextureCreationError::Io(err) => write!(f, "failed to read file: {}", err),
+			TextureCreationError::InvalidFile(msg) => write!(f, "invalid file: {}", msg),
+		}
+	}
+}
+impl error::Error for TextureCreationError {
+	fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+		match self {
+			TextureCreationError::ImageCreation(err) => Some(err),
+			TextureCreationError::Io(err) => Some(err),
+			TextureCreationError::UnsupportedFormat(_)
+			| TextureCreationError::NotEnoughData
+			| TextureCreationError::InvalidFile(_) => None,
+		}
+	}
+}
+impl From<ImageCreationError> for TextureCreationError {
+	fn from(err: ImageCreationError) -> Self {
+		TextureCreationError::ImageCreation(err)
+	}
+}
+impl From<io::Error> for TextureCreationError {
+	fn from(err: io::Error) -> Self {
+		TextureCreationError::Io(err)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn level_size_rounds_up_to_blocks() {
+		let dimensions = |width, height| Dimensions::Dim2d { width, height };
+		assert_eq!(level_size(Format::BC1_RGBAUnormBlock, dimensions(4, 4)), Some(8));
+		assert_eq!(level_size(Format::BC1_RGBAUnormBlock, dimensions(5, 1)), Some(16));
+		assert_eq!(level_size(Format::BC7UnormBlock, dimensions(1, 1)), Some(16));
+		assert_eq!(level_size(Format::BC3UnormBlock, dimensions(256, 128)), Some(64 * 32 * 16));
+		assert_eq!(level_size(Format::R8G8B8A8Unorm, dimensions(3, 5)), Some(60));
+		assert_eq!(level_size(Format::R16G16B16A16Sfloat, dimensions(2, 2)), Some(32));
+	}
+
+	#[test]
+	fn level_size_rejects_other_compressed_formats() {
+		let dimensions = Dimensions::Dim2d { width: 4, height: 4 };
+		assert_eq!(level_size(Format::ETC2_R8G8B8UnormBlock, dimensions), None);
+		assert_eq!(level_size(Format::ASTC_4x4UnormBlock, dimensions), None);
+	}
+
+	#[test]
+	fn level_offsets_of_full_chain() {
+		// 8x8, 4x4, 2x2 and 1x1 levels take 4, 1, 1 and 1 blocks
+		let dimensions = Dimensions::Dim2d { width: 8, height: 8 };
+		let (offsets, size) = level_offsets(Format::BC1_RGBAUnormBlock, dimensions, 1000).unwrap();
+		assert_eq!(offsets, vec![0, 32, 40, 48]);
+		assert_eq!(size, 56);
+	}
+
+	#[test]
+	fn level_offsets_of_truncated_chain() {
+		let dimensions = Dimensions::Dim2d { width: 8, height: 8 };
+		let (offsets, size) = level_offsets(Format::BC1_RGBAUnormBlock, dimensions, 45).unwrap();
+		assert_eq!(offsets, vec![0, 32]);
+		assert_eq!(size, 40);
+
+		match level_offsets(Format::BC1_RGBAUnormBlock, dimensions, 31) {
+			Err(TextureCreationError::NotEnoughData) => (),
+			res => panic!("expected NotEnoughData, got {:?}", res.map(|(offsets, _)| offsets)),
+		}
+	}
+
+	#[test]
+	fn level_offsets_of_cubemap() {
+		// every level holds all six faces
+		let dimensions = Dimensions::Cubemap { size: 4 };
+		let (offsets, size) = level_offsets(Format::BC3UnormBlock, dimensions, 6 * 16 * 3).unwrap();
+		assert_eq!(offsets, vec![0, 96, 192]);
+		assert_eq!(size, 288);
+	}
+
+	#[test]
+	fn level_offsets_of_unsupported_format() {
+		let dimensions = Dimensions::Dim2d { width: 4, height: 4 };
+		match level_offsets(Format::ETC2_R8G8B8UnormBlock, dimensions, 1000) {
+			Err(TextureCreationError::UnsupportedFormat(Format::ETC2_R8G8B8UnormBlock)) => (),
+			res => panic!("expected UnsupportedFormat, got {:?}", res.map(|(offsets, _)| offsets)),
+		}
 	}
 }
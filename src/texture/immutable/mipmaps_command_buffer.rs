@@ -24,6 +24,14 @@ use vulkano::{
 	sync::{AccessCheckError, AccessError, AccessFlagBits, GpuFuture, PipelineStages},
 };
 
+pub(crate) enum MipmapSource {
+	/// Only the first level is in the buffer. The rest are generated with linear blits.
+	Generate,
+	/// Every level is in the buffer, starting at these byte offsets, with the array layers of each level packed one
+	/// after another. Required for compressed formats, which can't be blitted.
+	Provided(Vec<usize>),
+}
+
 pub(crate) struct MipmapsCommandBuffer<B, P, F>
 where
 	F: FormatDesc + Send + Sync + 'static,
@@ -47,17 +55,20 @@ where
 		queue_family: QueueFamily,
 		buffer: B,
 		init: ImmutableImageInitialization<F>,
+		source: MipmapSource,
 	) -> Self {
 		let pool = Device::standard_command_pool(&device, queue_family);
 		let mut cmds =
 			unsafe { UnsafeCommandBufferBuilder::new(&pool, Kind::primary(), Flags::OneTimeSubmit).unwrap() };
 
+		let layers = init.dimensions().array_layers_with_cube();
+
 		let mut barrier = UnsafeCommandBufferBuilderPipelineBarrier::new();
 		unsafe {
 			barrier.add_image_memory_barrier(
 				&init,
 				0..init.mipmap_levels(),
-				0..1,
+				0..layers,
 				PipelineStages { bottom_of_pipe: true, ..PipelineStages::none() },
 				AccessFlagBits::none(),
 				PipelineStages { transfer: true, ..PipelineStages::none() },
@@ -73,107 +84,47 @@ where
 		let aspect = UnsafeCommandBufferBuilderImageAspect { color: true, depth: false, stencil: false };
 		let dimensions = init.dimensions();
 
-		let copy = UnsafeCommandBufferBuilderBufferImageCopy {
-			buffer_offset: 0,
-			buffer_row_length: 0,
-			buffer_image_height: 0,
-			image_aspect: aspect,
-			image_mip_level: 0,
-			image_base_array_layer: 0,
-			image_layer_count: 1,
-			image_offset: [0; 3],
-			image_extent: dimensions.width_height_depth(),
+		let offsets = match &source {
+			MipmapSource::Generate => vec![0],
+			MipmapSource::Provided(offsets) => offsets.clone(),
 		};
-		unsafe { cmds.copy_buffer_to_image(&buffer, &init, ImageLayout::TransferDstOptimal, iter::once(copy)) };
-
-		let mut barrier = UnsafeCommandBufferBuilderPipelineBarrier::new();
-		unsafe {
-			barrier.add_image_memory_barrier(
-				&init,
-				0..1,
-				0..1,
-				PipelineStages { transfer: true, ..PipelineStages::none() },
-				AccessFlagBits { transfer_write: true, ..AccessFlagBits::none() },
-				PipelineStages { transfer: true, ..PipelineStages::none() },
-				AccessFlagBits { transfer_read: true, ..AccessFlagBits::none() },
-				true,
-				None,
-				ImageLayout::TransferDstOptimal,
-				ImageLayout::TransferSrcOptimal,
-			);
-			cmds.pipeline_barrier(&barrier);
-		}
-
-		let mut last_mip_dimensions = dimensions;
-		for mip in 1..init.mipmap_levels() {
-			let mip_dimensions = last_mip_dimensions.mipmap_dimensions(1).unwrap();
-
-			let source_bottom_right = [
-				last_mip_dimensions.width() as i32,
-				last_mip_dimensions.height() as i32,
-				last_mip_dimensions.depth() as i32,
-			];
-			let destination_bottom_right =
-				[mip_dimensions.width() as i32, mip_dimensions.height() as i32, mip_dimensions.depth() as i32];
-
-			let blit = UnsafeCommandBufferBuilderImageBlit {
-				aspect,
-				source_mip_level: mip - 1,
-				destination_mip_level: mip,
-				source_base_array_layer: 0,
-				destination_base_array_layer: 0,
-				layer_count: 1,
-				source_top_left: [0; 3],
-				source_bottom_right,
-				destination_top_left: [0; 3],
-				destination_bottom_right,
-			};
-
-			unsafe {
-				cmds.blit_image(
-					&init,
-					ImageLayout::TransferSrcOptimal,
-					&init,
-					ImageLayout::TransferDstOptimal,
-					iter::once(blit),
-					Filter::Linear,
-				)
-			};
-
-			let mut barrier = UnsafeCommandBufferBuilderPipelineBarrier::new();
-			unsafe {
-				barrier.add_image_memory_barrier(
-					&init,
-					mip..mip + 1,
-					0..1,
-					PipelineStages { transfer: true, ..PipelineStages::none() },
-					AccessFlagBits { transfer_write: true, ..AccessFlagBits::none() },
-					PipelineStages { transfer: true, ..PipelineStages::none() },
-					AccessFlagBits { transfer_read: true, ..AccessFlagBits::none() },
-					true,
-					None,
-					ImageLayout::TransferDstOptimal,
-					ImageLayout::TransferSrcOptimal,
-				);
-				cmds.pipeline_barrier(&barrier);
+		let copies = offsets.into_iter().enumerate().map(|(mip, offset)| {
+			let mip_dimensions = dimensions.mipmap_dimensions(mip as u32).unwrap();
+			UnsafeCommandBufferBuilderBufferImageCopy {
+				buffer_offset: offset,
+				buffer_row_length: 0,
+				buffer_image_height: 0,
+				image_aspect: aspect,
+				image_mip_level: mip as u32,
+				image_base_array_layer: 0,
+				image_layer_count: layers,
+				image_offset: [0; 3],
+				image_extent: mip_dimensions.width_height_depth(),
 			}
+		});
+		unsafe { cmds.copy_buffer_to_image(&buffer, &init, ImageLayout::TransferDstOptimal, copies) };
 
-			last_mip_dimensions = mip_dimensions;
-		}
+		let final_source_layout = match source {
+			MipmapSource::Generate => {
+				generate_mipmaps(&mut cmds, &init, aspect);
+				ImageLayout::TransferSrcOptimal
+			},
+			MipmapSource::Provided(_) => ImageLayout::TransferDstOptimal,
+		};
 
 		let mut barrier = UnsafeCommandBufferBuilderPipelineBarrier::new();
 		unsafe {
 			barrier.add_image_memory_barrier(
 				&init,
 				0..init.mipmap_levels(),
-				0..1,
+				0..layers,
 				PipelineStages { transfer: true, ..PipelineStages::none() },
 				AccessFlagBits { transfer_write: true, ..AccessFlagBits::none() },
 				PipelineStages { top_of_pipe: true, ..PipelineStages::none() },
 				AccessFlagBits::none(),
 				true,
 				None,
-				ImageLayout::TransferSrcOptimal,
+				final_source_layout,
 				init.final_layout_requirement(),
 			);
 			cmds.pipeline_barrier(&barrier);
@@ -320,3 +271,91 @@ where
 		&self.device
 	}
 }
+
+/// Fills every mip level after the first by blitting from the previous one. Expects every level in
+/// `TransferDstOptimal` with the first one already written, and leaves every level in `TransferSrcOptimal`.
+fn generate_mipmaps<A, F>(
+	cmds: &mut UnsafeCommandBufferBuilder<A>,
+	init: &ImmutableImageInitialization<F>,
+	aspect: UnsafeCommandBufferBuilderImageAspect,
+) where
+	F: FormatDesc + Send + Sync + 'static,
+{
+	let dimensions = init.dimensions();
+	let layers = dimensions.array_layers_with_cube();
+
+	let mut barrier = UnsafeCommandBufferBuilderPipelineBarrier::new();
+	unsafe {
+		barrier.add_image_memory_barrier(
+			init,
+			0..1,
+			0..layers,
+			PipelineStages { transfer: true, ..PipelineStages::none() },
+			AccessFlagBits { transfer_write: true, ..AccessFlagBits::none() },
+			PipelineStages { transfer: true, ..PipelineStages::none() },
+			AccessFlagBits { transfer_read: true, ..AccessFlagBits::none() },
+			true,
+			None,
+			ImageLayout::TransferDstOptimal,
+			ImageLayout::TransferSrcOptimal,
+		);
+		cmds.pipeline_barrier(&barrier);
+	}
+
+	let mut last_mip_dimensions = dimensions;
+	for mip in 1..init.mipmap_levels() {
+		let mip_dimensions = last_mip_dimensions.mipmap_dimensions(1).unwrap();
+
+		let source_bottom_right = [
+			last_mip_dimensions.width() as i32,
+			last_mip_dimensions.height() as i32,
+			last_mip_dimensions.depth() as i32,
+		];
+		let destination_bottom_right =
+			[mip_dimensions.width() as i32, mip_dimensions.height() as i32, mip_dimensions.depth() as i32];
+
+		let blit = UnsafeCommandBufferBuilderImageBlit {
+			aspect,
+			source_mip_level: mip - 1,
+			destination_mip_level: mip,
+			source_base_array_layer: 0,
+			destination_base_array_layer: 0,
+			layer_count: layers,
+			source_top_left: [0; 3],
+			source_bottom_right,
+			destination_top_left: [0; 3],
+			destination_bottom_right,
+		};
+
+		unsafe {
+			cmds.blit_image(
+				init,
+				ImageLayout::TransferSrcOptimal,
+				init,
+				ImageLayout::TransferDstOptimal,
+				iter::once(blit),
+				Filter::Linear,
+			)
+		};
+
+		let mut barrier = UnsafeCommandBufferBuilderPipelineBarrier::new();
+		unsafe {
+			barrier.add_image_memory_barrier(
+				init,
+				mip..mip + 1,
+				0..layers,
+				PipelineStages { transfer: true, ..PipelineStages::none() },
+				AccessFlagBits { transfer_write: true, ..AccessFlagBits::none() },
+				PipelineStages { transfer: true, ..PipelineStages::none() },
+				AccessFlagBits { transfer_read: true, ..AccessFlagBits::none() },
+				true,
+				None,
+				ImageLayout::TransferDstOptimal,
+				ImageLayout::TransferSrcOptimal,
+			);
+			cmds.pipeline_barrier(&barrier);
+		}
+
+		last_mip_dimensions = mip_dimensions;
+	}
+}
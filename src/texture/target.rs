@@ -39,7 +39,7 @@ impl TargetTexture {
 	}
 }
 impl Texture for TargetTexture {
-	fn image(&self) -> &Arc<dyn ImageViewAccess + Send + Sync> {
-		&self.image
+	fn image(&self) -> Arc<dyn ImageViewAccess + Send + Sync> {
+		self.image.clone()
 	}
 }
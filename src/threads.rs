@@ -10,6 +10,8 @@ use std::{
 lazy_static! {
 	pub static ref FILE_THREAD: Mutex<ThreadPool> = Mutex::new(ThreadPool::builder().pool_size(1).create().unwrap());
 	pub static ref WAKER_THREAD: Mutex<ThreadPool> = Mutex::new(ThreadPool::builder().pool_size(1).create().unwrap());
+	pub static ref RECORD_THREADS: Mutex<ThreadPool> =
+		Mutex::new(ThreadPool::builder().pool_size(num_cpus::get()).create().unwrap());
 }
 
 pub fn yield_once() -> YieldOnce {
@@ -0,0 +1,349 @@
+//! Virtual file systems that `Resources` loads assets through.
+//!
+//! Packs are a simple archive format:
+//!
+//! - the magic number `npak`
+//! - the number of entries, as a little-endian `u32`
+//! - for each entry: the length of its name as a `u16`, the name as UTF-8 with `/` separators, and then the offset and
+//!   size of its data as `u64`s
+//! - the data of every entry
+
+use byteorder::{ReadBytesExt, WriteBytesExt, LE};
+use std::{
+	collections::HashMap,
+	fs::{self, File},
+	io::{self, prelude::*, BufReader, BufWriter, SeekFrom},
+	path::{Component, Path, PathBuf},
+	sync::{Arc, RwLock},
+	time::SystemTime,
+};
+
+pub trait ReadSeek: Read + Seek + Send {}
+impl<T: Read + Seek + Send> ReadSeek for T {}
+
+pub trait Vfs: Send + Sync {
+	fn open(&self, path: &Path) -> io::Result<Box<dyn ReadSeek>>;
+
+	/// Returns `None` if the file doesn't exist or the time isn't known.
+	fn modified(&self, path: &Path) -> Option<SystemTime>;
+}
+
+/// Reads files from a directory on disk.
+pub struct DirectoryVfs {
+	root: PathBuf,
+}
+impl DirectoryVfs {
+	pub fn new(root: impl Into<PathBuf>) -> Self {
+		Self { root: root.into() }
+	}
+}
+impl Vfs for DirectoryVfs {
+	fn open(&self, path: &Path) -> io::Result<Box<dyn ReadSeek>> {
+		Ok(Box::new(BufReader::new(File::open(self.root.join(path))?)))
+	}
+
+	fn modified(&self, path: &Path) -> Option<SystemTime> {
+		fs::metadata(self.root.join(path)).and_then(|meta| meta.modified()).ok()
+	}
+}
+
+/// Reads files from a pack. See the module documentation for the format.
+pub struct PackVfs {
+	path: PathBuf,
+	entries: HashMap<String, (u64, u64)>,
+	modified: Option<SystemTime>,
+}
+impl PackVfs {
+	pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+		let path = path.into();
+		let mut file = BufReader::new(File::open(&path)?);
+
+		let mut magic_number = [0; 4];
+		file.read_exact(&mut magic_number)?;
+		if &magic_number != b"npak" {
+			return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid pack file"));
+		}
+
+		let count = file.read_u32::<LE>()?;
+		let mut entries = HashMap::new();
+		for _ in 0..count {
+			let mut name = vec![0; file.read_u16::<LE>()? as usize];
+			file.read_exact(&mut name)?;
+			let name = String::from_utf8(name).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+			let offset = file.read_u64::<LE>()?;
+			let size = file.read_u64::<LE>()?;
+			entries.insert(name, (offset, size));
+		}
+
+		let modified = fs::metadata(&path).and_then(|meta| meta.modified()).ok();
+		Ok(Self { path, entries, modified })
+	}
+
+	/// Packs every file under `root` into a new pack at `output`.
+	pub fn create(root: impl AsRef<Path>, output: impl AsRef<Path>) -> io::Result<()> {
+		let root = root.as_ref();
+		let mut files = vec![];
+		collect_files(root, &mut files)?;
+		let names = files.iter().map(|file| entry_name(file.strip_prefix(root).unwrap())).collect::<Vec<_>>();
+		let sizes = files.iter().map(|file| Ok(fs::metadata(file)?.len())).collect::<io::Result<Vec<_>>>()?;
+
+		let mut out = BufWriter::new(File::create(output)?);
+		out.write_all(b"npak")?;
+		out.write_u32::<LE>(files.len() as u32)?;
+		let mut offset = 8 + names.iter().map(|name| 2 + name.len() as u64 + 16).sum::<u64>();
+		for (name, &size) in names.iter().zip(&sizes) {
+			out.write_u16::<LE>(name.len() as u16)?;
+			out.write_all(name.as_bytes())?;
+			out.write_u64::<LE>(offset)?;
+			out.write_u64::<LE>(size)?;
+			offset += size;
+		}
+		for file in &files {
+			io::copy(&mut File::open(file)?, &mut out)?;
+		}
+		out.flush()
+	}
+}
+impl Vfs for PackVfs {
+	fn open(&self, path: &Path) -> io::Result<Box<dyn ReadSeek>> {
+		let &(start, len) =
+			self.entries.get(&entry_name(path)).ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+		let mut file = File::open(&self.path)?;
+		file.seek(SeekFrom::Start(start))?;
+		Ok(Box::new(BufReader::new(PackEntry { file, start, len, pos: 0 })))
+	}
+
+	fn modified(&self, path: &Path) -> Option<SystemTime> {
+		if self.entries.contains_key(&entry_name(path)) {
+			self.modified
+		} else {
+			None
+		}
+	}
+}
+
+/// A single file within a pack.
+struct PackEntry {
+	file: File,
+	start: u64,
+	len: u64,
+	pos: u64,
+}
+impl Read for PackEntry {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let max = self.len.saturating_sub(self.pos).min(buf.len() as u64) as usize;
+		let read = self.file.read(&mut buf[..max])?;
+		self.pos += read as u64;
+		Ok(read)
+	}
+}
+impl Seek for PackEntry {
+	fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+		let pos = match pos {
+			SeekFrom::Start(pos) => pos as i64,
+			SeekFrom::End(offset) => self.len as i64 + offset,
+			SeekFrom::Current(offset) => self.pos as i64 + offset,
+		};
+		if pos < 0 {
+			return Err(io::Error::new(io::ErrorKind::InvalidInput, "seek before the start of a pack entry"));
+		}
+		self.pos = pos as u64;
+		self.file.seek(SeekFrom::Start(self.start + self.pos))?;
+		Ok(self.pos)
+	}
+}
+
+/// Combines several file systems, each mounted at a path prefix.
+#[derive(Default)]
+pub struct MountTable {
+	/// Sorted from highest to lowest priority.
+	mounts: RwLock<Vec<Mount>>,
+}
+impl MountTable {
+	/// Files in `vfs` appear under `point`. When several mounts contain the same file, the one with the highest
+	/// priority is used, and the most recent mount wins ties. This lets mods override files in the base game.
+	pub fn mount(&self, point: impl Into<PathBuf>, priority: i32, vfs: Arc<dyn Vfs>) {
+		let mut mounts = self.mounts.write().unwrap();
+		let index = mounts.iter().position(|mount| mount.priority <= priority).unwrap_or(mounts.len());
+		mounts.insert(index, Mount { point: point.into(), priority, vfs });
+	}
+
+	/// Removes every file system mounted at `point`.
+	pub fn unmount(&self, point: impl AsRef<Path>) {
+		self.mounts.write().unwrap().retain(|mount| mount.point != point.as_ref());
+	}
+}
+impl Vfs for MountTable {
+	fn open(&self, path: &Path) -> io::Result<Box<dyn ReadSeek>> {
+		for mount in self.mounts.read().unwrap().iter() {
+			if let Ok(rel_path) = path.strip_prefix(&mount.point) {
+				match mount.vfs.open(rel_path) {
+					Err(ref err) if err.kind() == io::ErrorKind::NotFound => continue,
+					result => return result,
+				}
+			}
+		}
+		Err(io::Error::new(io::ErrorKind::NotFound, format!("{} is not in any mounted file system", path.display())))
+	}
+
+	fn modified(&self, path: &Path) -> Option<SystemTime> {
+		self.mounts
+			.read()
+			.unwrap()
+			.iter()
+			.filter_map(|mount| path.strip_prefix(&mount.point).ok().and_then(|rel_path| mount.vfs.modified(rel_path)))
+			.next()
+	}
+}
+
+struct Mount {
+	point: PathBuf,
+	priority: i32,
+	vfs: Arc<dyn Vfs>,
+}
+
+/// Pack entries are named with `/` separators on every platform.
+fn entry_name(path: &Path) -> String {
+	let mut parts = vec![];
+	for component in path.components() {
+		match component {
+			Component::Normal(part) => parts.push(part.to_string_lossy()),
+			Component::ParentDir => {
+				parts.pop();
+			},
+			_ => (),
+		}
+	}
+	parts.join("/")
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+	for entry in fs::read_dir(dir)? {
+		let path = entry?.path();
+		if path.is_dir() {
+			collect_files(&path, files)?;
+		} else {
+			files.push(path);
+		}
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::{env, io::Cursor, process};
+
+	/// Files held in memory.
+	struct MemoryVfs(HashMap<PathBuf, &'static [u8]>);
+	impl Vfs for MemoryVfs {
+		fn open(&self, path: &Path) -> io::Result<Box<dyn ReadSeek>> {
+			let data = self.0.get(path).ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+			Ok(Box::new(Cursor::new(data.to_vec())))
+		}
+
+		fn modified(&self, _path: &Path) -> Option<SystemTime> {
+			None
+		}
+	}
+
+	fn memory_vfs(files: &[(&str, &'static [u8])]) -> Arc<dyn Vfs> {
+		Arc::new(MemoryVfs(files.iter().map(|&(path, data)| (PathBuf::from(path), data)).collect()))
+	}
+
+	fn read(vfs: &dyn Vfs, path: &str) -> io::Result<Vec<u8>> {
+		let mut data = vec![];
+		vfs.open(Path::new(path))?.read_to_end(&mut data)?;
+		Ok(data)
+	}
+
+	fn temp_dir(name: &str) -> PathBuf {
+		let dir = env::temp_dir().join(format!("nice-engine-{}-{}", name, process::id()));
+		let _ = fs::remove_dir_all(&dir);
+		fs::create_dir_all(&dir).unwrap();
+		dir
+	}
+
+	#[test]
+	fn pack_round_trip() {
+		let dir = temp_dir("pack_round_trip");
+		let root = dir.join("root");
+		fs::create_dir_all(root.join("textures/sky")).unwrap();
+		fs::write(root.join("readme.txt"), b"hello").unwrap();
+		fs::write(root.join("textures/sky/top.dds"), b"0123456789").unwrap();
+		fs::write(root.join("empty"), b"").unwrap();
+		let pack = dir.join("test.npak");
+		PackVfs::create(&root, &pack).unwrap();
+
+		let vfs = PackVfs::open(&pack).unwrap();
+		assert_eq!(read(&vfs, "readme.txt").unwrap(), b"hello");
+		assert_eq!(read(&vfs, "textures/sky/top.dds").unwrap(), b"0123456789");
+		assert_eq!(read(&vfs, "empty").unwrap(), b"");
+		assert_eq!(read(&vfs, "missing").unwrap_err().kind(), io::ErrorKind::NotFound);
+		assert!(vfs.modified(Path::new("readme.txt")).is_some());
+		assert!(vfs.modified(Path::new("missing")).is_none());
+
+		let mut entry = vfs.open(Path::new("textures/sky/top.dds")).unwrap();
+		let mut data = vec![];
+		entry.seek(SeekFrom::End(-3)).unwrap();
+		entry.read_to_end(&mut data).unwrap();
+		assert_eq!(data, b"789");
+		entry.seek(SeekFrom::Start(2)).unwrap();
+		let mut data = [0; 2];
+		entry.read_exact(&mut data).unwrap();
+		assert_eq!(&data, b"23");
+		assert!(entry.seek(SeekFrom::Current(-5)).is_err());
+
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn pack_rejects_other_files() {
+		let dir = temp_dir("pack_rejects_other_files");
+		let path = dir.join("not_a_pack");
+		fs::write(&path, b"nope, just text").unwrap();
+		assert_eq!(PackVfs::open(&path).err().unwrap().kind(), io::ErrorKind::InvalidData);
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn mount_priority() {
+		let table = MountTable::default();
+		table.mount("", 1, memory_vfs(&[("a", b"base"), ("b", b"base")]));
+		table.mount("", 2, memory_vfs(&[("a", b"mod")]));
+		table.mount("", 0, memory_vfs(&[("a", b"low"), ("c", b"low")]));
+		assert_eq!(read(&table, "a").unwrap(), b"mod");
+		assert_eq!(read(&table, "b").unwrap(), b"base");
+		assert_eq!(read(&table, "c").unwrap(), b"low");
+
+		// the most recent mount wins ties
+		table.mount("", 2, memory_vfs(&[("a", b"newer")]));
+		assert_eq!(read(&table, "a").unwrap(), b"newer");
+	}
+
+	#[test]
+	fn overlapping_mounts() {
+		let table = MountTable::default();
+		table.mount("", 0, memory_vfs(&[("data/a", b"root"), ("data/b", b"root")]));
+		table.mount("data", 0, memory_vfs(&[("a", b"data")]));
+		assert_eq!(read(&table, "data/a").unwrap(), b"data");
+		// files missing from the more specific mount fall through to the others
+		assert_eq!(read(&table, "data/b").unwrap(), b"root");
+		assert_eq!(read(&table, "other/a").unwrap_err().kind(), io::ErrorKind::NotFound);
+
+		table.unmount("data");
+		assert_eq!(read(&table, "data/a").unwrap(), b"root");
+		table.unmount("");
+		assert_eq!(read(&table, "data/a").unwrap_err().kind(), io::ErrorKind::NotFound);
+	}
+
+	#[test]
+	fn entry_name_normalization() {
+		assert_eq!(entry_name(Path::new("a/b/c.txt")), "a/b/c.txt");
+		assert_eq!(entry_name(Path::new("./a//b/")), "a/b");
+		assert_eq!(entry_name(Path::new("/a/b")), "a/b");
+		assert_eq!(entry_name(Path::new("a/x/../b")), "a/b");
+		assert_eq!(entry_name(Path::new("../a")), "a");
+		assert_eq!(entry_name(&Path::new("a").join("b").join("c")), "a/b/c");
+	}
+}
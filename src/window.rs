@@ -1,6 +1,9 @@
 #![cfg(feature = "window")]
 
-use crate::{surface::Surface, Context};
+use crate::{
+	surface::{Surface, SurfaceConfig},
+	Context,
+};
 use std::sync::Arc;
 use vulkano_win::{CreationError, VkSurfaceBuild};
 use winit::{EventsLoop, WindowBuilder};
@@ -10,11 +13,16 @@ pub struct Window {
 }
 impl Window {
 	pub fn new(ctx: &Arc<Context>, events: &EventsLoop) -> Result<Self, CreationError> {
+		Self::with_config(ctx, events, SurfaceConfig::default())
+	}
+
+	/// Like `new`, but with a non-default surface config.
+	pub fn with_config(ctx: &Arc<Context>, events: &EventsLoop, config: SurfaceConfig) -> Result<Self, CreationError> {
 		let vk_surface = WindowBuilder::new()
 			.with_dimensions((1440, 810).into())
 			.with_title("nIce Engine")
 			.build_vk_surface(events, ctx.instance.clone())?;
-		let surface = Surface::from_vk(ctx, vk_surface);
+		let surface = Surface::from_vk(ctx, vk_surface, config);
 		Ok(Self { surface })
 	}
 